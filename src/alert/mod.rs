@@ -0,0 +1,278 @@
+use crate::alert::smtp::SmtpOptions;
+use crate::status::BotStatus;
+use crate::time::get_local_date_time;
+use chrono::{Duration, NaiveDateTime};
+use log::{error, info};
+use std::collections::HashMap;
+
+mod smtp;
+mod webhook;
+
+/// an alert goes out after this many failures in a row, if ALERT_AFTER_FAILURES isn't set
+const DEFAULT_ALERT_AFTER_FAILURES: u32 = 5;
+/// an alert goes out once an earthquake has waited this long to be posted, if
+/// ALERT_STUCK_MINUTES isn't set
+const DEFAULT_ALERT_STUCK_MINUTES: i64 = 30;
+/// the same alert isn't sent again for this long, if ALERT_REPEAT_MINUTES isn't set
+const DEFAULT_ALERT_REPEAT_MINUTES: i64 = 360;
+
+/// what the operator gets alerted about
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Check {
+    /// getting the earthquakes from CWB (ex: the CWB key stopped working)
+    Poll,
+    /// posting earthquakes, summaries and digests for the profile with this name (ex: its
+    /// Twitter tokens were revoked). each profile is counted on its own, so one that
+    /// works doesn't hide one that doesn't
+    Publish(String),
+    /// earthquakes waiting too long to be posted
+    Stuck,
+}
+
+impl Check {
+    fn description(&self) -> String {
+        match self {
+            Check::Poll => "getting earthquakes from CWB".to_string(),
+            Check::Publish(profile) => format!("posting for profile {}", profile),
+            Check::Stuck => "posting earthquakes on time".to_string(),
+        }
+    }
+}
+
+/// when and where operator alerts are sent
+/// includes:
+/// * after_failures - how many failures in a row (polling or posting) before an alert
+/// * stuck_after - how long an earthquake can wait to be posted before an alert
+/// * repeat_after - how long before the same alert is sent again, if it's still failing
+/// * webhook - a url the alerts are POSTed to as json
+/// * smtp - the mail server and addresses to email the alerts to
+#[derive(Debug, Clone)]
+pub struct AlertOptions {
+    pub after_failures: u32,
+    pub stuck_after: Duration,
+    pub repeat_after: Duration,
+    pub webhook: Option<String>,
+    pub smtp: Option<SmtpOptions>,
+}
+
+impl AlertOptions {
+    /// read the options from the environment (or .env). there are no alerts (None)
+    /// unless ALERT_WEBHOOK_URL or the email settings are set, and a setting that's
+    /// there but wrong is an error
+    /// * ALERT_AFTER_FAILURES - a number of failures (5 if it isn't set)
+    /// * ALERT_STUCK_MINUTES - a number of minutes (30 if it isn't set)
+    /// * ALERT_REPEAT_MINUTES - a number of minutes (360 if it isn't set)
+    /// * ALERT_WEBHOOK_URL - a url like https://hooks.slack.com/services/...
+    /// * ALERT_SMTP_HOST, ALERT_SMTP_PORT, ALERT_SMTP_USERNAME, ALERT_SMTP_PASSWORD,
+    ///   ALERT_EMAIL_FROM and ALERT_EMAIL_TO - see `SmtpOptions::from_env`
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let webhook = std::env::var("ALERT_WEBHOOK_URL").ok();
+        let smtp = SmtpOptions::from_env()?;
+        if webhook.is_none() && smtp.is_none() {
+            return Ok(None);
+        }
+
+        let mut after_failures = DEFAULT_ALERT_AFTER_FAILURES;
+        if let Ok(failures) = std::env::var("ALERT_AFTER_FAILURES") {
+            match failures.parse::<u32>() {
+                Ok(failures) if failures > 0 => after_failures = failures,
+                _ => {
+                    return Err(format!(
+                        "ALERT_AFTER_FAILURES should be a number of failures, not {}",
+                        failures
+                    ))
+                }
+            }
+        }
+
+        let minutes = |name: &str, default: i64| match std::env::var(name) {
+            Ok(minutes) => match minutes.parse::<i64>() {
+                Ok(minutes) if minutes > 0 => Ok(Duration::minutes(minutes)),
+                _ => Err(format!("{} should be a number of minutes, not {}", name, minutes)),
+            },
+            Err(_) => Ok(Duration::minutes(default)),
+        };
+
+        Ok(Some(Self {
+            after_failures,
+            stuck_after: minutes("ALERT_STUCK_MINUTES", DEFAULT_ALERT_STUCK_MINUTES)?,
+            repeat_after: minutes("ALERT_REPEAT_MINUTES", DEFAULT_ALERT_REPEAT_MINUTES)?,
+            webhook,
+            smtp,
+        }))
+    }
+}
+
+/// how one check is doing
+/// includes:
+/// * failures - failures in a row
+/// * alerted - when the last alert for it went out (None if it's fine again)
+#[derive(Default)]
+struct CheckState {
+    failures: u32,
+    alerted: Option<NaiveDateTime>,
+}
+
+/// keeps track of failures and sends the operator alerts, once when something breaks
+/// (and again every `repeat_after` while it stays broken) and once when it's working
+/// again, so they don't get flooded
+pub struct Alerter {
+    options: Option<AlertOptions>,
+    checks: HashMap<Check, CheckState>,
+}
+
+impl Alerter {
+    /// `options` None keeps count of the failures but never sends anything
+    pub fn new(options: Option<AlertOptions>) -> Self {
+        Self {
+            options,
+            checks: HashMap::new(),
+        }
+    }
+
+    /// the check worked (if it had been alerted about, say it's working again)
+    pub async fn succeeded(&mut self, check: Check) {
+        let description = check.description();
+        let state = self.checks.entry(check).or_default();
+        state.failures = 0;
+        if state.alerted.take().is_some() {
+            self.send(
+                &format!("earthquake-bot: {} is working again", description),
+                "no need to do anything",
+            )
+            .await;
+        }
+    }
+
+    /// the check failed with `error` (alert once it's failed enough times in a row)
+    pub async fn failed(&mut self, check: Check, error: &str) {
+        let state = self.checks.entry(check.clone()).or_default();
+        state.failures += 1;
+        let failures = state.failures;
+
+        let after_failures = match &self.options {
+            Some(options) => options.after_failures,
+            None => return,
+        };
+        if failures >= after_failures {
+            let subject = format!(
+                "earthquake-bot: {} has failed {} times in a row",
+                check.description(),
+                failures
+            );
+            self.alert(check, &subject, &format!("the last error was: {}", error)).await;
+        }
+    }
+
+    /// alert if earthquakes have been waiting too long to be posted
+    pub async fn check_stuck(&mut self, status: &BotStatus) {
+        let stuck_after = match &self.options {
+            Some(options) => options.stuck_after,
+            None => return,
+        };
+
+        if status.unpublished_longer_than(stuck_after) {
+            let snapshot = status.snapshot();
+            let subject = format!(
+                "earthquake-bot: earthquakes have waited over {} minutes to be posted",
+                stuck_after.num_minutes()
+            );
+            let body = format!(
+                "{} earthquakes have been waiting since {}",
+                snapshot.queue_depth,
+                snapshot.unpublished_since.unwrap_or_default()
+            );
+            self.alert(Check::Stuck, &subject, &body).await;
+        } else {
+            self.succeeded(Check::Stuck).await;
+        }
+    }
+
+    /// send the alert for `check`, unless one already went out recently
+    async fn alert(&mut self, check: Check, subject: &str, body: &str) {
+        let repeat_after = match &self.options {
+            Some(options) => options.repeat_after,
+            None => return,
+        };
+
+        let now = get_local_date_time();
+        let state = self.checks.entry(check).or_default();
+        if matches!(state.alerted, Some(alerted) if now - alerted < repeat_after) {
+            return;
+        }
+        state.alerted = Some(now);
+
+        self.send(subject, body).await;
+    }
+
+    /// send an alert everywhere that's set up (problems are logged, since there's
+    /// nowhere else to report them)
+    async fn send(&self, subject: &str, body: &str) {
+        let options = match &self.options {
+            Some(options) => options,
+            None => return,
+        };
+        info!("sending an alert: {}", subject);
+
+        if let Some(url) = &options.webhook {
+            if let Err(e) = webhook::send(url, subject, body).await {
+                error!("couldn't send the alert to the webhook: {}", e);
+            }
+        }
+        if let Some(smtp) = &options.smtp {
+            if let Err(e) = smtp::send(smtp, subject, body).await {
+                error!("couldn't email the alert: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// alerts after 2 failures, with nowhere to send them
+    fn options() -> AlertOptions {
+        AlertOptions {
+            after_failures: 2,
+            stuck_after: Duration::minutes(30),
+            repeat_after: Duration::minutes(360),
+            webhook: None,
+            smtp: None,
+        }
+    }
+
+    fn failures(alerter: &Alerter, check: &Check) -> u32 {
+        alerter.checks.get(check).map_or(0, |state| state.failures)
+    }
+
+    #[tokio::test]
+    async fn failures_are_counted_without_options() {
+        let mut alerter = Alerter::new(None);
+        let check = Check::Publish("default".to_string());
+
+        alerter.failed(check.clone(), "oops").await;
+        alerter.failed(check.clone(), "oops").await;
+        assert_eq!(failures(&alerter, &check), 2);
+
+        alerter.succeeded(check.clone()).await;
+        assert_eq!(failures(&alerter, &check), 0);
+    }
+
+    #[tokio::test]
+    async fn each_profile_is_counted_on_its_own() {
+        let mut alerter = Alerter::new(Some(options()));
+        let failing = Check::Publish("en".to_string());
+        let working = Check::Publish("default".to_string());
+
+        for _ in 0..2 {
+            alerter.failed(failing.clone(), "401 unauthorized").await;
+            alerter.succeeded(working.clone()).await;
+        }
+
+        assert_eq!(failures(&alerter, &failing), 2);
+        assert!(alerter.checks[&failing].alerted.is_some());
+        assert_eq!(failures(&alerter, &working), 0);
+        assert!(alerter.checks[&working].alerted.is_none());
+    }
+}
@@ -0,0 +1,117 @@
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::time::Duration;
+
+/// the submission port (STARTTLS), if ALERT_SMTP_PORT isn't set
+const DEFAULT_SMTP_PORT: u16 = 587;
+/// the port where the connection is TLS from the start instead of after STARTTLS
+const IMPLICIT_TLS_PORT: u16 = 465;
+/// how long each step of sending the email can take
+const SMTP_TIMEOUT_SECONDS: u64 = 30;
+/// the name the bot gives the mail server
+const EHLO_NAME: &str = "earthquake-bot";
+
+/// the mail server and who the alerts are from and to
+/// includes:
+/// * host - the mail server (always connected to over TLS)
+/// * port - 465 for TLS from the start, anything else (like 587) for STARTTLS
+/// * username, password - the mail server login (not logged in if there's no username)
+/// * from - the address the alerts come from
+/// * to - the addresses they go to
+#[derive(Debug, Clone)]
+pub struct SmtpOptions {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl SmtpOptions {
+    /// read the options from the environment (or .env). no emails (None) unless
+    /// ALERT_SMTP_HOST, ALERT_EMAIL_FROM and ALERT_EMAIL_TO are set. only some of them,
+    /// or a setting that's there but wrong, is an error
+    /// * ALERT_SMTP_HOST - a mail server like smtp.example.com
+    /// * ALERT_SMTP_PORT - 465 or 587 (587 if it isn't set)
+    /// * ALERT_SMTP_USERNAME, ALERT_SMTP_PASSWORD - the mail server login
+    /// * ALERT_EMAIL_FROM - an address like bot@example.com
+    /// * ALERT_EMAIL_TO - addresses separated by commas
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let (host, from, to) = match (
+            std::env::var("ALERT_SMTP_HOST"),
+            std::env::var("ALERT_EMAIL_FROM"),
+            std::env::var("ALERT_EMAIL_TO"),
+        ) {
+            (Ok(host), Ok(from), Ok(to)) => (host, from, to),
+            (Err(_), Err(_), Err(_)) => return Ok(None),
+            _ => {
+                return Err(
+                    "ALERT_SMTP_HOST, ALERT_EMAIL_FROM and ALERT_EMAIL_TO all have to be set \
+                     to email alerts"
+                        .to_string(),
+                )
+            }
+        };
+        let to: Vec<String> = to
+            .split(',')
+            .map(|address| address.trim().to_string())
+            .filter(|address| !address.is_empty())
+            .collect();
+        if to.is_empty() {
+            return Err("ALERT_EMAIL_TO doesn't have any addresses".to_string());
+        }
+
+        let mut port = DEFAULT_SMTP_PORT;
+        if let Ok(smtp_port) = std::env::var("ALERT_SMTP_PORT") {
+            match smtp_port.parse() {
+                Ok(smtp_port) => port = smtp_port,
+                Err(_) => {
+                    return Err(format!(
+                        "ALERT_SMTP_PORT should be a port like 587, not {}",
+                        smtp_port
+                    ))
+                }
+            }
+        }
+
+        Ok(Some(Self {
+            host,
+            port,
+            username: std::env::var("ALERT_SMTP_USERNAME").ok(),
+            password: std::env::var("ALERT_SMTP_PASSWORD").ok(),
+            from,
+            to,
+        }))
+    }
+}
+
+/// email the alert
+pub async fn send(options: &SmtpOptions, subject: &str, body: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut message = Message::builder().from(options.from.parse()?).subject(subject);
+    for to in &options.to {
+        message = message.to(to.parse()?);
+    }
+    let message = message.header(ContentType::TEXT_PLAIN).body(body.to_string())?;
+
+    // TLS from the start on 465, and STARTTLS on anything else
+    let transport = if options.port == IMPLICIT_TLS_PORT {
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&options.host)?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&options.host)?
+    };
+    let mut transport = transport
+        .port(options.port)
+        .hello_name(ClientId::Domain(EHLO_NAME.to_string()))
+        .timeout(Some(Duration::from_secs(SMTP_TIMEOUT_SECONDS)));
+    if let Some(username) = &options.username {
+        let password = options.password.clone().unwrap_or_default();
+        transport = transport.credentials(Credentials::new(username.clone(), password));
+    }
+
+    transport.build().send(message).await?;
+
+    Ok(())
+}
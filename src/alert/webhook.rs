@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+/// how long to wait for the webhook to answer
+const WEBHOOK_TIMEOUT_SECONDS: u64 = 10;
+
+/// what's POSTed to the webhook
+/// includes:
+/// * text - the subject and body together (what Slack and similar webhooks show)
+/// * subject - what's wrong (or that it's fixed)
+/// * body - more about it
+#[derive(Serialize)]
+struct WebhookAlert<'a> {
+    text: String,
+    subject: &'a str,
+    body: &'a str,
+}
+
+/// POST the alert to `url` as json
+pub async fn send(url: &str, subject: &str, body: &str) -> Result<(), reqwest::Error> {
+    let alert = WebhookAlert {
+        text: format!("{}\n{}", subject, body),
+        subject,
+        body,
+    };
+
+    reqwest::Client::new()
+        .post(url)
+        .timeout(std::time::Duration::from_secs(WEBHOOK_TIMEOUT_SECONDS))
+        .json(&alert)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
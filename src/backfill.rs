@@ -0,0 +1,173 @@
+use crate::cwb_api::get_earthquakes_between;
+use crate::db::EqDb;
+use crate::time::CwbTime;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use log::warn;
+
+/// how many days are asked for at once if --window-days isn't given
+pub const DEFAULT_WINDOW_DAYS: i64 = 7;
+
+/// where a backfill has got to, saved after every window so it can pick up from there
+/// includes:
+/// * from - the start of the whole backfill
+/// * to - the end of the whole backfill (not included)
+/// * next - the start of the next window to get
+struct Cursor {
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    next: NaiveDateTime,
+}
+
+impl Cursor {
+    /// the cursor as it's saved in the database (ex: 2024-01-01T00:00:00|2024-02-01T00:00:00|2024-01-15T00:00:00)
+    fn to_db_string(&self) -> String {
+        [self.from, self.to, self.next]
+            .iter()
+            .map(|time| CwbTime::from(*time).format_with_t())
+            .collect::<Vec<String>>()
+            .join("|")
+    }
+
+    /// read a saved cursor (None if it isn't one)
+    fn from_db_string(cursor: &str) -> Option<Self> {
+        let times: Vec<NaiveDateTime> = cursor
+            .split('|')
+            .map(|time| NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S").ok())
+            .collect::<Option<Vec<NaiveDateTime>>>()?;
+
+        match times.as_slice() {
+            [from, to, next] => Some(Self {
+                from: *from,
+                to: *to,
+                next: *next,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// the window starting at `start`, `window_days` long but not past `end`: where it ends
+/// (where the next one starts), and the timeFrom and timeTo to ask the api for
+///
+/// timeTo includes its second, so it stops one second before the next window
+fn window(start: NaiveDateTime, end: NaiveDateTime, window_days: i64) -> (NaiveDateTime, String, String) {
+    let window_end = (start + Duration::days(window_days)).min(end);
+
+    (
+        window_end,
+        CwbTime::from(start).format_with_t(),
+        CwbTime::from(window_end - Duration::seconds(1)).format_with_t(),
+    )
+}
+
+/// save every earthquake from the start of `from` to the end of `to` (Taiwan time) in
+/// the archive, without posting anything or changing last_time
+///
+/// the CWB api is asked for `window_days` at a time (each window is paged), and the
+/// cursor is saved after each one. if a backfill of the same dates was stopped, this
+/// carries on from the last finished window (unless `restart`)
+pub async fn backfill(
+    db: &EqDb,
+    from: NaiveDate,
+    to: NaiveDate,
+    window_days: i64,
+    restart: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if to < from {
+        return Err(format!("the end date ({}) is before the start date ({})", to, from).into());
+    }
+    if window_days < 1 {
+        return Err("the window has to be at least 1 day".into());
+    }
+
+    let start = from.and_hms_opt(0, 0, 0).ok_or("bad start date")?;
+    let end = to.and_hms_opt(0, 0, 0).ok_or("bad end date")? + Duration::days(1);
+
+    let mut cursor = Cursor {
+        from: start,
+        to: end,
+        next: start,
+    };
+    match db.get_backfill_cursor().as_deref().map(Cursor::from_db_string) {
+        Some(Some(saved)) if !restart && saved.from == start && saved.to == end => {
+            println!("picking up the backfill from {}", saved.next);
+            cursor.next = saved.next;
+        }
+        Some(Some(saved)) if !restart => warn!(
+            "starting over, since the unfinished backfill was for {} to {}",
+            saved.from, saved.to
+        ),
+        Some(None) => warn!("the saved backfill cursor couldn't be read, so starting over"),
+        _ => {}
+    }
+
+    let mut total = 0;
+    while cursor.next < end {
+        let (window_end, time_from, time_to) = window(cursor.next, end, window_days);
+        let earthquakes = get_earthquakes_between(&time_from, &time_to).await?;
+        for eq in &earthquakes {
+            db.store_earthquake(eq)?;
+        }
+        println!("{} to {}: {} earthquakes", time_from, time_to, earthquakes.len());
+        total += earthquakes.len();
+
+        cursor.next = window_end;
+        db.store_backfill_cursor(cursor.to_db_string())?;
+    }
+
+    db.clear_backfill_cursor();
+    println!("backfill done, archived {} earthquakes", total);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap().and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn cursor_is_read_back_the_way_it_was_saved() {
+        let cursor = Cursor {
+            from: at("2024-01-01"),
+            to: at("2024-02-01"),
+            next: at("2024-01-15") + Duration::seconds(1),
+        };
+
+        let saved = cursor.to_db_string();
+        assert_eq!(saved, "2024-01-01T00:00:00|2024-02-01T00:00:00|2024-01-15T00:00:01");
+        let read = Cursor::from_db_string(&saved).unwrap();
+        assert_eq!((read.from, read.to, read.next), (cursor.from, cursor.to, cursor.next));
+
+        assert!(Cursor::from_db_string("2024-01-01T00:00:00|2024-02-01T00:00:00").is_none());
+        assert!(Cursor::from_db_string("2024-01-01|2024-02-01|2024-01-15").is_none());
+        assert!(Cursor::from_db_string("").is_none());
+    }
+
+    #[test]
+    fn windows_cover_every_second_once() {
+        let (start, end) = (at("2024-01-01"), at("2024-01-18"));
+
+        let mut windows = Vec::new();
+        let mut next = start;
+        while next < end {
+            let (window_end, time_from, time_to) = window(next, end, 7);
+            windows.push((time_from, time_to));
+            next = window_end;
+        }
+
+        // each window starts the second after the last one ended, and the last one is cut
+        // short at the end
+        assert_eq!(
+            windows,
+            vec![
+                ("2024-01-01T00:00:00".to_string(), "2024-01-07T23:59:59".to_string()),
+                ("2024-01-08T00:00:00".to_string(), "2024-01-14T23:59:59".to_string()),
+                ("2024-01-15T00:00:00".to_string(), "2024-01-17T23:59:59".to_string()),
+            ]
+        );
+    }
+}
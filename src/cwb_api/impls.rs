@@ -1,21 +1,41 @@
 use log::info;
 use super::structs::{Earthquake, Response};
+use super::STORES;
+use crate::metrics::METRICS;
 use crate::db::EqDb;
 use crate::time::CwbTime;
 // use reqwest::{Client, Error};
 use reqwest::Error;
+use tracing::{info_span, Instrument};
 
 impl Response {
     /// pass a url to create a new CWB EQ response struct
     pub async fn new_from_url(url: String) -> Result<Self, Error> {
-        let json = reqwest::get(&url).await?;
-        let s: Self = json.json().await?;
+        // time the request for the metrics, labeled with the dataset it's from
+        let dataset = STORES.iter().find(|store| url.contains(*store)).unwrap_or(&"unknown");
+        let timer = METRICS
+            .cwb_request_duration
+            .with_label_values(&[dataset])
+            .start_timer();
+        let result = Self::get_from_url(&url)
+            .instrument(info_span!("cwb_request", dataset = %dataset))
+            .await;
+        timer.observe_duration();
+        if result.is_err() {
+            METRICS.cwb_request_errors.with_label_values(&[dataset]).inc();
+        }
+        let s = result?;
 
         // before returning, sleep for a second to make sure I don't
         // accidentally hit the CWB servers too often/quickly
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         Ok(s)
     }
+
+    /// just the request (`new_from_url` adds the metrics and the wait)
+    async fn get_from_url(url: &str) -> Result<Self, Error> {
+        reqwest::get(url).await?.json().await
+    }
 }
 
 impl Earthquake {
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// seismic intensity on the CWB scale used since 2020
+///
+/// 0 to 4 and 7 are single levels, but 5 and 6 are split into weak (弱) and strong (強).
+/// the variants are in order, so comparing two intensities works as expected
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SeismicIntensity {
+    Zero,
+    One,
+    Two,
+    Three,
+    Four,
+    FiveWeak,
+    FiveStrong,
+    SixWeak,
+    SixStrong,
+    Seven,
+}
+
+impl SeismicIntensity {
+    /// every level, weakest first
+    pub const ALL: [SeismicIntensity; 10] = [
+        SeismicIntensity::Zero,
+        SeismicIntensity::One,
+        SeismicIntensity::Two,
+        SeismicIntensity::Three,
+        SeismicIntensity::Four,
+        SeismicIntensity::FiveWeak,
+        SeismicIntensity::FiveStrong,
+        SeismicIntensity::SixWeak,
+        SeismicIntensity::SixStrong,
+        SeismicIntensity::Seven,
+    ];
+
+    /// the level without any Chinese, short enough to fit on a map symbol
+    /// (ex: "3", "5-", "6+")
+    pub fn short_label(self) -> &'static str {
+        match self {
+            SeismicIntensity::Zero => "0",
+            SeismicIntensity::One => "1",
+            SeismicIntensity::Two => "2",
+            SeismicIntensity::Three => "3",
+            SeismicIntensity::Four => "4",
+            SeismicIntensity::FiveWeak => "5-",
+            SeismicIntensity::FiveStrong => "5+",
+            SeismicIntensity::SixWeak => "6-",
+            SeismicIntensity::SixStrong => "6+",
+            SeismicIntensity::Seven => "7",
+        }
+    }
+}
+
+/// the same form CWB uses (ex: "3級", "5弱", "6強")
+impl fmt::Display for SeismicIntensity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SeismicIntensity::Zero => "0級",
+            SeismicIntensity::One => "1級",
+            SeismicIntensity::Two => "2級",
+            SeismicIntensity::Three => "3級",
+            SeismicIntensity::Four => "4級",
+            SeismicIntensity::FiveWeak => "5弱",
+            SeismicIntensity::FiveStrong => "5強",
+            SeismicIntensity::SixWeak => "6弱",
+            SeismicIntensity::SixStrong => "6強",
+            SeismicIntensity::Seven => "7級",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+pub enum IntensityConversionErrors {
+    OutOfBounds(u8),
+    ParseError(String),
+}
+
+impl fmt::Display for IntensityConversionErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntensityConversionErrors::OutOfBounds(intensity) => {
+                write!(f, "parsed to {}, which isn't on the scale", intensity)
+            }
+            IntensityConversionErrors::ParseError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// whether a 5 or 6 was weak or strong
+enum Sublevel {
+    Weak,
+    Strong,
+}
+
+/// parses the Chinese form ("0級", "4級", "5弱", "6強") and English forms ("4",
+/// "5-", "5+", "5 weak", "6 strong", "6 lower", "6 upper")
+///
+/// a 5 or 6 without weak/strong (the pre-2020 scale) is read as the weak level
+impl FromStr for SeismicIntensity {
+    type Err = IntensityConversionErrors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim().to_lowercase();
+
+        let intensity_numbers: String = text.chars().filter(|c| c.is_ascii_digit()).collect();
+        let intensity = intensity_numbers
+            .parse::<u8>()
+            .map_err(|e| IntensityConversionErrors::ParseError(format!("{} ({})", e, s)))?;
+
+        let sublevel = if text.contains('弱')
+            || text.contains('-')
+            || text.contains("weak")
+            || text.contains("lower")
+        {
+            Some(Sublevel::Weak)
+        } else if text.contains('強')
+            || text.contains('强')
+            || text.contains('+')
+            || text.contains("strong")
+            || text.contains("upper")
+        {
+            Some(Sublevel::Strong)
+        } else {
+            None
+        };
+
+        match (intensity, sublevel) {
+            (0, None) => Ok(SeismicIntensity::Zero),
+            (1, None) => Ok(SeismicIntensity::One),
+            (2, None) => Ok(SeismicIntensity::Two),
+            (3, None) => Ok(SeismicIntensity::Three),
+            (4, None) => Ok(SeismicIntensity::Four),
+            (5, None) | (5, Some(Sublevel::Weak)) => Ok(SeismicIntensity::FiveWeak),
+            (5, Some(Sublevel::Strong)) => Ok(SeismicIntensity::FiveStrong),
+            (6, None) | (6, Some(Sublevel::Weak)) => Ok(SeismicIntensity::SixWeak),
+            (6, Some(Sublevel::Strong)) => Ok(SeismicIntensity::SixStrong),
+            (7, None) => Ok(SeismicIntensity::Seven),
+            (0..=7, Some(_)) => Err(IntensityConversionErrors::ParseError(format!(
+                "only 5 and 6 can be weak or strong ({})",
+                s
+            ))),
+            (_, _) => Err(IntensityConversionErrors::OutOfBounds(intensity)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_form_of_each_level_is_read() {
+        // each level's Chinese form, short English form and (for 5 and 6) words
+        let levels: [(SeismicIntensity, &[&str]); 10] = [
+            (SeismicIntensity::Zero, &["0級", "0"]),
+            (SeismicIntensity::One, &["1級", "1"]),
+            (SeismicIntensity::Two, &["2級", "2"]),
+            (SeismicIntensity::Three, &["3級", "3"]),
+            (SeismicIntensity::Four, &["4級", "4", " 4級 "]),
+            (SeismicIntensity::FiveWeak, &["5弱", "5-", "5 weak", "5 lower", "5 Weak"]),
+            (SeismicIntensity::FiveStrong, &["5強", "5强", "5+", "5 strong", "5 upper"]),
+            (SeismicIntensity::SixWeak, &["6弱", "6-", "6 weak", "6 lower"]),
+            (SeismicIntensity::SixStrong, &["6強", "6+", "6 strong", "6 UPPER"]),
+            (SeismicIntensity::Seven, &["7級", "7"]),
+        ];
+
+        for (level, strings) in levels.iter() {
+            for string in strings.iter() {
+                let parsed: SeismicIntensity = string.parse().unwrap_or_else(|e| panic!("{}: {}", string, e));
+                assert_eq!(parsed, *level, "{}", string);
+            }
+        }
+    }
+
+    #[test]
+    fn cwb_form_is_read_back_the_same() {
+        for level in SeismicIntensity::ALL {
+            assert!(matches!(level.to_string().parse::<SeismicIntensity>(), Ok(parsed) if parsed == level), "{}", level);
+        }
+    }
+
+    #[test]
+    fn five_and_six_from_before_2020_are_weak() {
+        assert!(matches!("5級".parse::<SeismicIntensity>(), Ok(SeismicIntensity::FiveWeak)));
+        assert!(matches!("6級".parse::<SeismicIntensity>(), Ok(SeismicIntensity::SixWeak)));
+    }
+
+    #[test]
+    fn bad_strings_are_errors() {
+        for string in ["", "級", "weak", "x級"] {
+            assert!(
+                matches!(string.parse::<SeismicIntensity>(), Err(IntensityConversionErrors::ParseError(_))),
+                "{}",
+                string
+            );
+        }
+
+        // only 5 and 6 are split
+        for string in ["4弱", "7強", "3+"] {
+            assert!(
+                matches!(string.parse::<SeismicIntensity>(), Err(IntensityConversionErrors::ParseError(_))),
+                "{}",
+                string
+            );
+        }
+
+        assert!(matches!("8級".parse::<SeismicIntensity>(), Err(IntensityConversionErrors::OutOfBounds(8))));
+        assert!(matches!("10".parse::<SeismicIntensity>(), Err(IntensityConversionErrors::OutOfBounds(10))));
+    }
+}
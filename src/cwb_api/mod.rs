@@ -3,14 +3,22 @@ use dotenv_codegen::dotenv;
 // use reqwest::Client;
 
 use crate::cwb_api::structs::Response;
+use log::warn;
+use std::collections::HashSet;
 
 pub mod impls;
+pub mod intensity;
 pub mod structs;
 
 /// the two urls for getting earthquake information
-const STORES: [&str; 2] = ["E-A0015-001", "E-A0016-001"];
+pub(crate) const STORES: [&str; 2] = ["E-A0015-001", "E-A0016-001"];
 /// the url root for the Taiwan CWB api
 const URL_ROOT: &str = "https://opendata.cwb.gov.tw/api/v1/rest/datastore/";
+/// how many earthquakes to ask for at once when getting a time window
+const PAGE_SIZE: usize = 100;
+/// the most pages to ask a store for in one time window, in case the api keeps sending
+/// full pages
+const MAX_PAGES: usize = 100;
 
 /// get both urls in a vector
 /// arguments: last_time: string (this one needs the T)
@@ -39,6 +47,77 @@ fn get_earthquake_urls(last_time: Option<String>) -> Vec<String> {
         .collect()
 }
 
+/// the url for one page of a store's earthquakes between two times
+/// arguments:
+/// * store - one of `STORES`
+/// * time_from, time_to - the times (these need the T)
+/// * offset - how many earthquakes to skip (for paging)
+fn get_window_url(store: &str, time_from: &str, time_to: &str, offset: usize) -> String {
+    dot().ok();
+    let api_key = dotenv!("CWB_API_KEY");
+
+    format!(
+        "{base_url}{store}?Authorization={api_key}&timeFrom={time_from}&timeTo={time_to}&limit={limit}&offset={offset}",
+        base_url = URL_ROOT,
+        store = store,
+        api_key = api_key,
+        time_from = time_from,
+        time_to = time_to,
+        limit = PAGE_SIZE,
+        offset = offset
+    )
+}
+
+/// every earthquake from both stores between two times (these need the T), getting
+/// as many pages as it takes (up to `MAX_PAGES` for each store, or it's an error)
+///
+/// each earthquake is only returned once. a page with nothing new on it ends the store,
+/// since the api isn't paging (it would just send the same page again)
+pub async fn get_earthquakes_between(
+    time_from: &str,
+    time_to: &str,
+) -> Result<Vec<structs::Earthquake>, Box<dyn std::error::Error>> {
+    let mut all_eqs = Vec::new();
+    // small, local earthquakes can share a number, so the origin time is part of it too
+    let mut seen = HashSet::new();
+
+    for store in STORES.iter() {
+        let mut offset = 0;
+        let mut pages = 0;
+        loop {
+            if pages == MAX_PAGES {
+                return Err(format!(
+                    "{} still had full pages after {} pages from {} to {} (try a smaller window)",
+                    store, MAX_PAGES, time_from, time_to
+                )
+                .into());
+            }
+            pages += 1;
+
+            let url = get_window_url(store, time_from, time_to, offset);
+            let page = Response::new_from_url(url).await?.records.earthquake;
+            let page_len = page.len();
+            let before = all_eqs.len();
+            all_eqs.extend(
+                page.into_iter()
+                    .filter(|eq| seen.insert((eq.earthquake_no, eq.earthquake_info.origin_time.clone()))),
+            );
+
+            // a page that isn't full is the last one
+            if page_len < PAGE_SIZE {
+                break;
+            }
+            if all_eqs.len() == before {
+                warn!("{} sent the same earthquakes again at offset {}, so stopping there", store, offset);
+                break;
+            }
+            offset += page_len;
+        }
+    }
+
+    Ok(all_eqs)
+}
+
 /// convenience struct to hold both responses (so it's an array of responses)
 #[derive(Debug)]
 pub struct BothResponses(pub [structs::Response; 2]);
@@ -47,6 +126,7 @@ impl BothResponses {
     /// this will take the two urls in this mod, then it'll get two `Response`s
     /// arguments:
     /// * last_time: Option<String>
+    ///
     /// returns:
     /// * Result<Self, Box<dyn Error::error>>
     pub async fn new_from_last_time(last_time: Option<String>) -> Result<Self, reqwest::Error> {
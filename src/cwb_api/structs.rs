@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use crate::cwb_api::structs::IntensityConversionErrors::OutOfBounds;
+use crate::cwb_api::intensity::{IntensityConversionErrors, SeismicIntensity};
+use crate::geo::GeoPoint;
 
 /// All CWB data goes into this parent struct
 /// includes:
@@ -24,6 +25,7 @@ pub struct Records {
 /// * report_content - a printable string (for tweeting)
 /// * report_image_URI - uri to the CWB map
 /// * web - web address for information about the earthquake
+/// * earthquake_no - CWB's number for it (ex: 113001, or 113xxx with 999 for small, local ones)
 /// * earthquake_info - a parent for the earthquake's time and epicenter
 /// * intensity - intensity of the epicenter
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -34,18 +36,40 @@ pub struct Earthquake {
     pub earthquake_info: EarthquakeInfo,
     #[serde(rename(serialize = "intensity", deserialize = "Intensity"))]
     pub intensity: Intensity,
+    #[serde(rename(serialize = "web", deserialize = "Web"), default)]
+    pub web: String,
+    #[serde(rename(serialize = "earthquake_no", deserialize = "EarthquakeNo"), default)]
+    pub earthquake_no: u32,
 }
 
 /// has information about the earthquake's time, lat, and long
 /// includes:
 /// * origin_time - the time the earthquake happened
+/// * focal_depth - how deep the earthquake was (km)
 /// * epicenter - the lat and long of the epicenter
+/// * earthquake_magnitude - how big the earthquake was
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EarthquakeInfo {
     #[serde(rename(serialize = "origin_time", deserialize = "OriginTime"))]
     pub origin_time: String,
+    #[serde(rename(serialize = "focal_depth", deserialize = "FocalDepth"))]
+    pub focal_depth: f64,
     #[serde(rename(serialize = "epicenter", deserialize = "Epicenter"))]
     pub epicenter: Epicenter,
+    #[serde(rename(serialize = "earthquake_magnitude", deserialize = "EarthquakeMagnitude"))]
+    pub earthquake_magnitude: EarthquakeMagnitude,
+}
+
+/// the earthquake's magnitude
+/// includes:
+/// * magnitude_type - the scale used (always 芮氏規模, local/Richter magnitude, so far)
+/// * magnitude_value - the magnitude
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EarthquakeMagnitude {
+    #[serde(rename(serialize = "magnitude_type", deserialize = "MagnitudeType"))]
+    pub magnitude_type: String,
+    #[serde(rename(serialize = "magnitude_value", deserialize = "MagnitudeValue"))]
+    pub magnitude_value: f64,
 }
 
 /// information about the earthquake's intensity
@@ -59,62 +83,175 @@ pub struct Intensity {
 
 /// stores the information about the intensity of each station
 /// includes:
+/// * area_desc - description of the area (ex: 花蓮縣地區, or 最大震度3級地區)
+/// * county_name - the county (or counties, separated by 、) the stations are in
+/// * area_intensity - the strongest intensity in the area
 /// * eq_station - information about each station's records
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ShakingArea {
+    #[serde(rename(serialize = "area_desc", deserialize = "AreaDesc"))]
+    pub area_desc: String,
+    #[serde(rename(serialize = "county_name", deserialize = "CountyName"))]
+    pub county_name: String,
+    #[serde(rename(serialize = "area_intensity", deserialize = "AreaIntensity"))]
+    pub area_intensity: String,
     #[serde(rename(serialize = "eq_station", deserialize = "EqStation"))]
     pub eq_station: Vec<EqStation>,
 }
 
 /// single station information
 /// includes:
+/// * station_name - name of the station (usually the town it's in)
 /// * station_intensity - this station's intensity reading
-/// * station_lat - this station's location
-/// * station_long - this station's location
+/// * station_lat - this station's latitude
+/// * station_lon - this station's longitude
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EqStation {
+    #[serde(rename(serialize = "station_name", deserialize = "StationName"))]
+    pub station_name: String,
     #[serde(rename(serialize = "station_intensity", deserialize = "SeismicIntensity"))]
     pub station_intensity: String,
     #[serde(rename(serialize = "station_lat", deserialize = "StationLatitude"))]
     pub station_lat: f64,
-    #[serde(rename(serialize = "station_long", deserialize = "StationLongitude"))]
-    pub station_long: f64,
+    #[serde(rename(serialize = "station_lon", deserialize = "StationLongitude"))]
+    pub station_lon: f64,
 }
 
 /// epicenter of the earthquake
 /// includes:
-/// * long - this.value is f64 of its location
-/// * lat - this.value is f64 of its location
+/// * location - description of where it is (ex: 花蓮縣政府南南東方 25.0 公里)
+/// * lat - latitude of the epicenter
+/// * lon - longitude of the epicenter
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Epicenter {
-    #[serde(rename(serialize = "long", deserialize = "EpicenterLatitude"))]
-    pub long: f64,
-    #[serde(rename(serialize = "lat", deserialize = "EpicenterLongitude"))]
+    #[serde(rename(serialize = "location", deserialize = "Location"))]
+    pub location: String,
+    #[serde(rename(serialize = "lat", deserialize = "EpicenterLatitude"))]
     pub lat: f64,
+    #[serde(rename(serialize = "lon", deserialize = "EpicenterLongitude"))]
+    pub lon: f64,
 }
 
 // start some implementations here
 
-pub enum IntensityConversionErrors {
-    OutOfBounds(u8),
-    ParseError(String)
+impl Epicenter {
+    /// where the epicenter is
+    pub fn point(&self) -> GeoPoint {
+        GeoPoint::new(self.lat, self.lon)
+    }
 }
 
-impl EqStation {
-    pub fn convert_station_intensity_to_u8(&self) -> Result<u8, IntensityConversionErrors> {
-        // Taiwan's station intensities can only go to 7, so bust the string to
-        // chars and take the first one
+impl ShakingArea {
+    /// read the area's intensity (ex: "4級" or "5弱")
+    pub fn intensity(&self) -> Result<SeismicIntensity, IntensityConversionErrors> {
+        self.area_intensity.parse()
+    }
 
-        let intensity_chars = self.station_intensity.chars();
+    /// every county in the area, written with 臺 (CWB sometimes uses 台)
+    pub fn counties(&self) -> impl Iterator<Item = String> + '_ {
+        self.county_name
+            .split(['、', ',', '，'])
+            .map(|name| name.trim().replace('台', "臺"))
+            .filter(|name| !name.is_empty())
+    }
+}
 
-        let intensity_numbers: String = intensity_chars.filter(|c| c.is_numeric()).collect();
+impl Earthquake {
+    /// the one with the biggest magnitude in `earthquakes` (the last of them if more
+    /// than one has it)
+    pub fn biggest(earthquakes: &[Earthquake]) -> Option<&Earthquake> {
+        earthquakes.iter().max_by(|a, b| {
+            a.earthquake_info
+                .earthquake_magnitude
+                .magnitude_value
+                .partial_cmp(&b.earthquake_info.earthquake_magnitude.magnitude_value)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+    }
+}
 
-        let intensity = intensity_numbers.parse::<u8>().map_err(|e| IntensityConversionErrors::ParseError(e.to_string()))?;
+impl EqStation {
+    /// where the station is
+    pub fn point(&self) -> GeoPoint {
+        GeoPoint::new(self.station_lat, self.station_lon)
+    }
 
-        if (1..=7).contains(&intensity) {
-            Ok(intensity)
-        } else {
-            Err(OutOfBounds(intensity))
+    /// read the station's intensity (ex: "4級" or "5弱")
+    pub fn intensity(&self) -> Result<SeismicIntensity, IntensityConversionErrors> {
+        self.station_intensity.parse()
+    }
+}
+
+#[cfg(test)]
+impl Earthquake {
+    /// a made up earthquake for tests (off Hualien, with no stations)
+    /// arguments: origin_time: string (without the T), magnitude
+    pub(crate) fn for_test(origin_time: &str, magnitude: f64) -> Self {
+        Self {
+            report_content: format!("{} earthquake", origin_time),
+            earthquake_info: EarthquakeInfo {
+                origin_time: origin_time.to_string(),
+                focal_depth: 10.0,
+                epicenter: Epicenter {
+                    location: "花蓮縣政府東方 20.0 公里 (位於臺灣東部海域)".to_string(),
+                    lat: 23.99,
+                    lon: 121.81,
+                },
+                earthquake_magnitude: EarthquakeMagnitude {
+                    magnitude_type: "芮氏規模".to_string(),
+                    magnitude_value: magnitude,
+                },
+            },
+            intensity: Intensity { shaking_area: Vec::new() },
+            web: String::new(),
+            earthquake_no: 113999,
         }
     }
+
+    /// add a shaking area in `county_name` with `area_intensity`, and a station for each
+    /// of `stations` (its intensity, all at the epicenter)
+    pub(crate) fn with_area(mut self, county_name: &str, area_intensity: &str, stations: &[&str]) -> Self {
+        let epicenter = &self.earthquake_info.epicenter;
+        let eq_station = stations
+            .iter()
+            .map(|intensity| EqStation {
+                station_name: format!("{} station", county_name),
+                station_intensity: intensity.to_string(),
+                station_lat: epicenter.lat,
+                station_lon: epicenter.lon,
+            })
+            .collect();
+        self.intensity.shaking_area.push(ShakingArea {
+            area_desc: format!("{}地區", county_name),
+            county_name: county_name.to_string(),
+            area_intensity: area_intensity.to_string(),
+            eq_station,
+        });
+        self
+    }
+
+    /// `for_test`, but with a location so long that a post about it has to be cut short
+    pub(crate) fn with_long_location(origin_time: &str, magnitude: f64) -> Self {
+        let mut eq = Self::for_test(origin_time, magnitude);
+        eq.earthquake_info.epicenter.location = "花蓮縣政府東南東方 25.0 公里".repeat(10);
+        eq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn biggest_is_by_magnitude_and_the_last_one_wins_a_tie() {
+        let earthquakes = [
+            Earthquake::for_test("2024-04-03 07:58:09", 7.2),
+            Earthquake::for_test("2024-04-03 08:11:00", 6.1),
+            Earthquake::for_test("2024-04-03 10:14:00", 7.2),
+        ];
+
+        let biggest = Earthquake::biggest(&earthquakes).unwrap();
+        assert_eq!(biggest.earthquake_info.origin_time, "2024-04-03 10:14:00");
+        assert!(Earthquake::biggest(&[]).is_none());
+    }
 }
@@ -1,10 +1,33 @@
-use crate::time::get_local_date_time_with_t;
+use crate::cwb_api::structs::Earthquake;
+use crate::time::{get_local_date_time, get_local_date_time_with_t, parse_date_or_time, CwbTime};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
 use dotenv_codegen::dotenv;
-use log::info;
-use sled::{Db, Result as SledResult};
+use log::{error, info};
+use sled::{Db, IVec, Result as SledResult, Tree};
 
 const CANT_ACCESS_LAST_TIME: &str = "failed to retrieve last_time from db";
 const LAST_TIME_KEY: &str = "last_time";
+/// the tree every earthquake the bot has seen is saved in, keyed by archive id (origin
+/// time first, so the keys sort by time)
+const EARTHQUAKE_TREE: &str = "earthquakes";
+/// archive ids from before they had the earthquake number in them were just the origin
+/// time with the T (ex: 2024-04-03T07:58:09), which is this long
+const OLD_ARCHIVE_ID_LENGTH: usize = 19;
+/// the tree of earthquakes that were posted, keyed by archive id, with when they were
+/// posted (Taiwan time, with the T)
+const PUBLISHED_TREE: &str = "published";
+/// where an unfinished backfill got to (see `backfill`)
+const BACKFILL_CURSOR_KEY: &str = "backfill_cursor";
+/// the date of the last digest is saved under this plus the digest's name
+const LAST_DIGEST_KEY_PREFIX: &str = "last_digest_";
+/// that the profile posted an earthquake is saved under this plus its archive id
+const POSTED_KEY_PREFIX: &str = "posted_";
+/// the profile's swarm thread that's up (see `swarm`) is saved under this: the id of its
+/// last post, then the origin time (with the T) of its newest earthquake
+const SWARM_THREAD_KEY: &str = "swarm_thread";
+/// each profile (other than the default one) keeps its state in the tree called this
+/// plus its name
+const PROFILE_TREE_PREFIX: &str = "profile_";
 
 /// open the database
 fn open_connection() -> SledResult<Db> {
@@ -12,24 +35,139 @@ fn open_connection() -> SledResult<Db> {
     sled::open(db_file)
 }
 
+/// the first byte of every archived earthquake. bincode doesn't save field names, so
+/// when `Earthquake`'s fields change this goes up. other versions are rejected, so the
+/// archive needs a migration when it does
+const ARCHIVE_VERSION: u8 = 1;
+
+/// an earthquake as it's saved in the archive: the version, then the earthquake
+fn encode_earthquake(eq: &Earthquake) -> Result<Vec<u8>, bincode::Error> {
+    let mut value = vec![ARCHIVE_VERSION];
+    bincode::serialize_into(&mut value, eq)?;
+    Ok(value)
+}
+
+/// read an earthquake saved by `encode_earthquake`
+fn decode_earthquake(value: &[u8]) -> Result<Earthquake, Box<dyn std::error::Error>> {
+    match value.split_first() {
+        Some((&ARCHIVE_VERSION, eq)) => Ok(bincode::deserialize(eq)?),
+        Some((version, _)) => Err(format!(
+            "archived earthquake is version {}, but only version {} can be read",
+            version, ARCHIVE_VERSION
+        )
+        .into()),
+        None => Err("archived earthquake is empty".into()),
+    }
+}
+
 /// struct will hold the `sled::Db` thing
-pub struct EqDb(Db);
+/// (cloning it is cheap, and every clone uses the same database)
+///
+/// the archive (and the feed and backfill) is shared, but last_time and the digest
+/// dates are kept in `state`: the top of the database for the default profile, or
+/// the profile's own tree (see `for_profile`)
+#[derive(Clone)]
+pub struct EqDb {
+    db: Db,
+    state: Tree,
+}
 
 impl EqDb {
-    /// connect and return Result with EqDb struct
+    /// connect and return Result with EqDb struct (moving any archive ids from before
+    /// they had the earthquake number in them)
     pub fn new() -> Result<Self, ()> {
         match open_connection() {
-            Ok(d) => Ok(EqDb(d)),
+            Ok(d) => {
+                let eq_db = EqDb {
+                    state: Tree::clone(&d),
+                    db: d,
+                };
+                if let Err(e) = eq_db.migrate_archive_ids() {
+                    error!("couldn't move the archive to the new ids: {}", e);
+                }
+                Ok(eq_db)
+            }
             Err(_) => Err(()),
         }
     }
 
+    /// give every earthquake archived by its origin time alone its full archive id, and
+    /// move what was posted (in the feed and each profile's state) with it
+    fn migrate_archive_ids(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let earthquakes = self.db.open_tree(EARTHQUAKE_TREE)?;
+        let mut old: Vec<(IVec, IVec)> = Vec::new();
+        for entry in earthquakes.iter() {
+            let (id, value) = entry?;
+            if id.len() == OLD_ARCHIVE_ID_LENGTH {
+                old.push((id, value));
+            }
+        }
+        if old.is_empty() {
+            return Ok(());
+        }
+
+        let profiles = self
+            .db
+            .tree_names()
+            .into_iter()
+            .filter(|name| name.starts_with(PROFILE_TREE_PREFIX.as_bytes()))
+            .map(|name| self.db.open_tree(name))
+            .collect::<Result<Vec<Tree>, sled::Error>>()?;
+        let published = self.db.open_tree(PUBLISHED_TREE)?;
+
+        for (old_id, value) in &old {
+            let new_id = Self::archive_id(&decode_earthquake(value)?);
+            earthquakes.insert(new_id.as_bytes(), value)?;
+            earthquakes.remove(old_id)?;
+
+            if let Some(published_at) = published.remove(old_id)? {
+                published.insert(new_id.as_bytes(), published_at)?;
+            }
+            let old_posted = [POSTED_KEY_PREFIX.as_bytes(), old_id].concat();
+            let new_posted = format!("{}{}", POSTED_KEY_PREFIX, new_id);
+            for state in profiles.iter().chain(std::iter::once(&*self.db)) {
+                if let Some(posted_at) = state.remove(&old_posted)? {
+                    state.insert(new_posted.as_bytes(), posted_at)?;
+                }
+            }
+        }
+
+        info!("moved {} archived earthquakes to ids with their earthquake numbers", old.len());
+        Ok(())
+    }
+
+    /// a database that's deleted when it's dropped
+    #[cfg(test)]
+    pub(crate) fn temporary() -> Self {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        EqDb {
+            state: Tree::clone(&db),
+            db,
+        }
+    }
+
+    /// the same database, but with the last_time and digest dates of the profile called
+    /// `name` (the default profile's are at the top of the database, like before there
+    /// were profiles)
+    pub fn for_profile(&self, name: &str) -> Result<Self, sled::Error> {
+        let state = if name == crate::profile::DEFAULT_PROFILE {
+            Tree::clone(&self.db)
+        } else {
+            self.db.open_tree(format!("{}{}", PROFILE_TREE_PREFIX, name))?
+        };
+
+        Ok(EqDb {
+            db: self.db.clone(),
+            state,
+        })
+    }
+
     /// get the last_time from the db
     pub fn get_last_time(&self) -> String {
         // call .expect().expect() because it shouldn't be an error or none
         // (otherwise there is a real error and it needs to panic)
         let last_time = self
-            .0
+            .state
             .get(LAST_TIME_KEY.as_bytes())
             .expect(CANT_ACCESS_LAST_TIME)
             .expect(CANT_ACCESS_LAST_TIME);
@@ -37,16 +175,23 @@ impl EqDb {
         String::from(std::str::from_utf8(last_time.as_ref()).unwrap())
     }
 
+    /// make sure the database can still be read (for the health check)
+    pub fn check(&self) -> Result<(), sled::Error> {
+        self.state.get(LAST_TIME_KEY)?;
+
+        Ok(())
+    }
+
     /// store last time in the database
     /// (`Earthquake` `update_last_time` should verify that the new time is newer
     /// than the old time before it stores a new time)
     pub fn store_last_time(&self, new_last_time: String) {
-        let _ = self.0.insert(LAST_TIME_KEY, new_last_time.as_bytes());
+        let _ = self.state.insert(LAST_TIME_KEY, new_last_time.as_bytes());
     }
 
     /// check if the db has a last_time value, and if not, create one with the current time
     pub fn check_last_time_create_if_not_exist(&self) {
-        let last_time = self.0.get(LAST_TIME_KEY);
+        let last_time = self.state.get(LAST_TIME_KEY);
 
         // check result, panic if it fails
         match last_time.expect(CANT_ACCESS_LAST_TIME) {
@@ -60,8 +205,259 @@ impl EqDb {
 
                 // this should work, so just unwrap it
                 // also, I already know the result is None, so drop it
-                let _ = self.0.insert(LAST_TIME_KEY, time_now.as_bytes()).unwrap();
+                let _ = self.state.insert(LAST_TIME_KEY, time_now.as_bytes()).unwrap();
             }
         }
     }
+
+    /// save an earthquake in the archive (saving the same earthquake again replaces it).
+    /// returns true if it wasn't archived before
+    ///
+    /// the earthquake is stored with bincode (after `ARCHIVE_VERSION`), since the api
+    /// structs are read with different field names than they're written with
+    pub fn store_earthquake(&self, eq: &Earthquake) -> Result<bool, Box<dyn std::error::Error>> {
+        let old = self
+            .db
+            .open_tree(EARTHQUAKE_TREE)?
+            .insert(Self::archive_id(eq), encode_earthquake(eq)?)?;
+
+        Ok(old.is_none())
+    }
+
+    /// the earthquake's key in the archive: its origin time with the T, then CWB's
+    /// number for it, since two earthquakes can happen in the same second
+    /// (ex: 2024-04-03T07:58:09-113019)
+    pub fn archive_id(eq: &Earthquake) -> String {
+        format!(
+            "{}-{}",
+            CwbTime::new_time_string_without_t(&eq.earthquake_info.origin_time).format_with_t(),
+            eq.earthquake_no
+        )
+    }
+
+    /// the archived earthquake with this `archive_id`, if there is one
+    pub fn get_earthquake(&self, id: &str) -> Result<Option<Earthquake>, Box<dyn std::error::Error>> {
+        match self.db.open_tree(EARTHQUAKE_TREE)?.get(id.as_bytes())? {
+            Some(value) => Ok(Some(decode_earthquake(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// every archived earthquake from `from` up to (but not including) `to`, oldest first
+    /// (the ids start with the origin time, so this is a range of them)
+    pub fn earthquakes_between(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Earthquake>, Box<dyn std::error::Error>> {
+        let from = CwbTime::from(from).format_with_t();
+        let to = CwbTime::from(to).format_with_t();
+
+        let mut earthquakes = Vec::new();
+        for entry in self.db.open_tree(EARTHQUAKE_TREE)?.range(from.as_bytes()..to.as_bytes()) {
+            let (_, value) = entry?;
+            earthquakes.push(decode_earthquake(&value)?);
+        }
+
+        Ok(earthquakes)
+    }
+
+    /// save that an earthquake was posted just now
+    pub fn mark_published(&self, eq: &Earthquake) -> Result<(), sled::Error> {
+        self.db
+            .open_tree(PUBLISHED_TREE)?
+            .insert(Self::archive_id(eq), get_local_date_time_with_t().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// the `count` newest posted earthquakes (by origin time, newest first), each with
+    /// when it was posted (Taiwan time, with the T). any that aren't in the archive are
+    /// skipped
+    pub fn recently_published(
+        &self,
+        count: usize,
+    ) -> Result<Vec<(Earthquake, String)>, Box<dyn std::error::Error>> {
+        let mut published = Vec::new();
+        for entry in self.db.open_tree(PUBLISHED_TREE)?.iter().rev() {
+            if published.len() >= count {
+                break;
+            }
+
+            let (id, published_at) = entry?;
+            if let Some(eq) = self.get_earthquake(&String::from_utf8_lossy(&id))? {
+                published.push((eq, String::from_utf8_lossy(&published_at).to_string()));
+            }
+        }
+
+        Ok(published)
+    }
+
+    /// archived earthquakes from `since` up to (but not including) `until`, at least
+    /// `min_mag` big, oldest first. anything that's None doesn't limit the search
+    pub fn search_earthquakes(
+        &self,
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+        min_mag: Option<f64>,
+    ) -> Result<Vec<Earthquake>, Box<dyn std::error::Error>> {
+        // earlier than anything CWB has, and later than anything archived
+        let since = since.unwrap_or_else(|| NaiveDate::from_ymd_opt(1900, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+        let until = until.unwrap_or_else(|| get_local_date_time() + Duration::days(1));
+
+        let mut earthquakes = self.earthquakes_between(since, until)?;
+        if let Some(min_mag) = min_mag {
+            earthquakes.retain(|eq| eq.earthquake_info.earthquake_magnitude.magnitude_value >= min_mag);
+        }
+
+        Ok(earthquakes)
+    }
+
+    /// save that this profile posted the earthquake (on its own or in a summary)
+    pub fn store_posted(&self, eq: &Earthquake) -> Result<(), sled::Error> {
+        self.state.insert(
+            format!("{}{}", POSTED_KEY_PREFIX, Self::archive_id(eq)),
+            get_local_date_time_with_t().as_bytes(),
+        )?;
+
+        Ok(())
+    }
+
+    /// whether this profile already posted the earthquake (if that can't be read, it
+    /// wasn't)
+    pub fn is_posted(&self, eq: &Earthquake) -> bool {
+        matches!(
+            self.state.contains_key(format!("{}{}", POSTED_KEY_PREFIX, Self::archive_id(eq))),
+            Ok(true)
+        )
+    }
+
+    /// the profile's swarm thread that's up, if there's been one: the id of its last post
+    /// (to reply to) and the origin time of its newest earthquake
+    pub fn get_swarm_thread(&self) -> Option<(u64, NaiveDateTime)> {
+        let thread = self.state.get(SWARM_THREAD_KEY).ok()??;
+        let thread = String::from_utf8_lossy(thread.as_ref()).to_string();
+        let (reply_to, newest) = thread.split_once(' ')?;
+
+        Some((reply_to.parse().ok()?, parse_date_or_time(newest).ok()?))
+    }
+
+    /// save that `reply_to` is now the last post of the profile's swarm thread, and
+    /// `newest` its newest earthquake
+    pub fn store_swarm_thread(&self, reply_to: u64, newest: &Earthquake) {
+        let newest = CwbTime::new_time_string_without_t(&newest.earthquake_info.origin_time).format_with_t();
+        let thread = format!("{} {}", reply_to, newest);
+        let _ = self.state.insert(SWARM_THREAD_KEY, thread.as_bytes());
+    }
+
+    /// the date (ex: 2024-04-03) the digest called `name` was last posted, if it ever was
+    pub fn get_last_digest(&self, name: &str) -> Option<String> {
+        let key = format!("{}{}", LAST_DIGEST_KEY_PREFIX, name);
+        let date = self.state.get(key.as_bytes()).ok()??;
+
+        Some(String::from_utf8_lossy(date.as_ref()).to_string())
+    }
+
+    /// save the date the digest called `name` was posted
+    pub fn store_last_digest(&self, name: &str, date: String) {
+        let key = format!("{}{}", LAST_DIGEST_KEY_PREFIX, name);
+        let _ = self.state.insert(key.as_bytes(), date.as_bytes());
+    }
+
+    /// where the last backfill stopped, if it didn't finish
+    pub fn get_backfill_cursor(&self) -> Option<String> {
+        let cursor = self.db.get(BACKFILL_CURSOR_KEY).ok()??;
+
+        Some(String::from_utf8_lossy(cursor.as_ref()).to_string())
+    }
+
+    /// save how far the backfill has got (flushed right away, so it's still there if the
+    /// backfill is stopped)
+    pub fn store_backfill_cursor(&self, cursor: String) -> Result<(), sled::Error> {
+        self.db.insert(BACKFILL_CURSOR_KEY, cursor.as_bytes())?;
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    /// forget the backfill cursor (once the backfill is done)
+    pub fn clear_backfill_cursor(&self) {
+        let _ = self.db.remove(BACKFILL_CURSOR_KEY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storing_an_earthquake_again_says_it_was_already_archived() {
+        let db = EqDb::temporary();
+        let eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2);
+
+        assert!(db.store_earthquake(&eq).unwrap());
+        assert!(!db.store_earthquake(&eq).unwrap());
+        assert!(db.store_earthquake(&Earthquake::for_test("2024-04-03 08:11:00", 5.0)).unwrap());
+    }
+
+    #[test]
+    fn archived_earthquakes_are_read_back_by_their_version() {
+        let db = EqDb::temporary();
+        let eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2);
+        db.store_earthquake(&eq).unwrap();
+
+        let read = db.get_earthquake("2024-04-03T07:58:09-113999").unwrap().unwrap();
+        assert_eq!(read.earthquake_no, eq.earthquake_no);
+        assert_eq!(read.earthquake_info.epicenter.location, eq.earthquake_info.epicenter.location);
+
+        let mut newer = encode_earthquake(&eq).unwrap();
+        newer[0] = ARCHIVE_VERSION + 1;
+        assert!(decode_earthquake(&newer).is_err());
+        assert!(decode_earthquake(&[]).is_err());
+    }
+
+    #[test]
+    fn earthquakes_in_the_same_second_are_both_archived() {
+        let db = EqDb::temporary();
+        let first = Earthquake::for_test("2024-04-03 07:58:09", 7.2);
+        let mut second = Earthquake::for_test("2024-04-03 07:58:09", 4.1);
+        second.earthquake_no = 113020;
+
+        assert!(db.store_earthquake(&first).unwrap());
+        assert!(db.store_earthquake(&second).unwrap());
+        assert_eq!(EqDb::archive_id(&second), "2024-04-03T07:58:09-113020");
+
+        // both are in a range that starts at that second, and neither is in one that ends there
+        let at = CwbTime::new_time_string_without_t("2024-04-03 07:58:09").get_date_time();
+        let between = db.earthquakes_between(at, at + Duration::seconds(1)).unwrap();
+        assert_eq!(between.len(), 2);
+        assert!(db.earthquakes_between(at - Duration::hours(1), at).unwrap().is_empty());
+    }
+
+    #[test]
+    fn old_archive_ids_are_moved_with_what_was_posted() {
+        let db = EqDb::temporary();
+        let profile = db.for_profile("en").unwrap();
+        let eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2);
+
+        // how an earthquake was saved before the ids had its number
+        let old_id = "2024-04-03T07:58:09";
+        let value = encode_earthquake(&eq).unwrap();
+        db.db.open_tree(EARTHQUAKE_TREE).unwrap().insert(old_id, value).unwrap();
+        db.db.open_tree(PUBLISHED_TREE).unwrap().insert(old_id, "2024-04-03T08:00:00").unwrap();
+        for state in [&db.state, &profile.state] {
+            state.insert(format!("{}{}", POSTED_KEY_PREFIX, old_id), "2024-04-03T08:00:00").unwrap();
+        }
+
+        db.migrate_archive_ids().unwrap();
+        assert!(db.get_earthquake(old_id).unwrap().is_none());
+        assert!(db.get_earthquake(&EqDb::archive_id(&eq)).unwrap().is_some());
+        assert_eq!(db.recently_published(10).unwrap().len(), 1);
+        assert!(db.is_posted(&eq) && profile.is_posted(&eq));
+
+        // and there's nothing left to move the next time
+        db.migrate_archive_ids().unwrap();
+        let day = CwbTime::new_time_string_without_t("2024-04-03 00:00:00").get_date_time();
+        assert_eq!(db.earthquakes_between(day, day + Duration::days(1)).unwrap().len(), 1);
+    }
 }
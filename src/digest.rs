@@ -0,0 +1,417 @@
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+use crate::taiwan_map::{county_intensities, MapRenderer};
+use crate::profile::{Language, Profile};
+use crate::template::cut_to_length;
+use crate::tweet::post_tweet;
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike, Weekday};
+use log::info;
+use std::collections::HashMap;
+
+/// the hour (Taiwan time) digests go out if DIGEST_HOUR isn't set
+const DEFAULT_DIGEST_HOUR: u32 = 20;
+/// the weekly digest goes out on this day
+const WEEKLY_DIGEST_DAY: Weekday = Weekday::Sun;
+/// the upper end of each magnitude band in the digest, and its label
+const MAGNITUDE_BANDS: [(f64, &str); 4] = [(4.0, "M<4"), (5.0, "M4"), (6.0, "M5"), (f64::MAX, "M6+")];
+
+/// how much time a digest covers
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum DigestPeriod {
+    /// the last 24 hours
+    Daily,
+    /// the last 7 days
+    Weekly,
+}
+
+impl DigestPeriod {
+    /// the name the last posted date is saved under
+    pub fn name(self) -> &'static str {
+        match self {
+            DigestPeriod::Daily => "daily",
+            DigestPeriod::Weekly => "weekly",
+        }
+    }
+
+    fn length(self) -> Duration {
+        match self {
+            DigestPeriod::Daily => Duration::days(1),
+            DigestPeriod::Weekly => Duration::days(7),
+        }
+    }
+
+    /// the start of the digest's text
+    fn heading(self, language: Language) -> &'static str {
+        match (self, language) {
+            (DigestPeriod::Daily, Language::Chinese) => "過去24小時台灣地震 Taiwan earthquakes in the past 24 hours",
+            (DigestPeriod::Weekly, Language::Chinese) => "本週台灣地震 Taiwan earthquakes this week",
+            (DigestPeriod::Daily, Language::English) => "Taiwan earthquakes in the past 24 hours",
+            (DigestPeriod::Weekly, Language::English) => "Taiwan earthquakes this week",
+        }
+    }
+}
+
+/// which digests get posted, and when
+/// includes:
+/// * daily - post a digest of the last 24 hours every day
+/// * weekly - post a digest of the last 7 days every Sunday
+/// * hour - the hour (Taiwan time, 0 to 23) they go out
+#[derive(Debug, Clone)]
+pub struct DigestOptions {
+    pub daily: bool,
+    pub weekly: bool,
+    pub hour: u32,
+}
+
+impl DigestOptions {
+    /// read the options from the environment (or .env). both digests are off unless
+    /// they're turned on, and a setting that's there but wrong is an error
+    /// * DIGEST_DAILY - true or false
+    /// * DIGEST_WEEKLY - true or false
+    /// * DIGEST_HOUR - 0 to 23 (20 if it isn't set)
+    pub fn from_env() -> Result<Self, String> {
+        let mut options = Self {
+            daily: false,
+            weekly: false,
+            hour: DEFAULT_DIGEST_HOUR,
+        };
+
+        if let Ok(daily) = std::env::var("DIGEST_DAILY") {
+            match daily.parse() {
+                Ok(daily) => options.daily = daily,
+                Err(_) => {
+                    return Err(format!(
+                        "DIGEST_DAILY should be true or false, not {}",
+                        daily
+                    ))
+                }
+            }
+        }
+
+        if let Ok(weekly) = std::env::var("DIGEST_WEEKLY") {
+            match weekly.parse() {
+                Ok(weekly) => options.weekly = weekly,
+                Err(_) => {
+                    return Err(format!(
+                        "DIGEST_WEEKLY should be true or false, not {}",
+                        weekly
+                    ))
+                }
+            }
+        }
+
+        if let Ok(hour) = std::env::var("DIGEST_HOUR") {
+            match hour.parse::<u32>() {
+                Ok(hour) if hour < 24 => options.hour = hour,
+                _ => {
+                    return Err(format!(
+                        "DIGEST_HOUR should be an hour from 0 to 23, not {}",
+                        hour
+                    ))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// the digests that should go out at `now` (Taiwan time): it's past the hour, it's
+    /// the right day, and that digest hasn't been posted today
+    pub fn due(&self, db: &EqDb, now: NaiveDateTime) -> Vec<DigestPeriod> {
+        if now.hour() < self.hour {
+            return Vec::new();
+        }
+
+        let today = now.date().to_string();
+        let mut due = Vec::new();
+        if self.daily {
+            due.push(DigestPeriod::Daily);
+        }
+        if self.weekly && now.weekday() == WEEKLY_DIGEST_DAY {
+            due.push(DigestPeriod::Weekly);
+        }
+        due.retain(|period| db.get_last_digest(period.name()).as_deref() != Some(today.as_str()));
+
+        due
+    }
+}
+
+/// the archived earthquakes for one digest
+/// includes:
+/// * period - daily or weekly
+/// * from - the start of the digest (Taiwan time)
+/// * to - the end of the digest (Taiwan time)
+/// * earthquakes - every earthquake in between, oldest first
+pub struct Digest {
+    pub period: DigestPeriod,
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub earthquakes: Vec<Earthquake>,
+}
+
+impl Digest {
+    /// get the earthquakes for the `period` ending at `to` from the archive
+    pub fn from_archive(
+        db: &EqDb,
+        period: DigestPeriod,
+        to: NaiveDateTime,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let from = to - period.length();
+
+        Ok(Self {
+            period,
+            from,
+            to,
+            earthquakes: db.earthquakes_between(from, to)?,
+        })
+    }
+
+    /// how many earthquakes were in each of `MAGNITUDE_BANDS`
+    pub fn band_counts(&self) -> [usize; MAGNITUDE_BANDS.len()] {
+        let mut counts = [0; MAGNITUDE_BANDS.len()];
+        for eq in &self.earthquakes {
+            let magnitude = eq.earthquake_info.earthquake_magnitude.magnitude_value;
+            if let Some(band) = MAGNITUDE_BANDS.iter().position(|(below, _)| magnitude < *below) {
+                counts[band] += 1;
+            }
+        }
+
+        counts
+    }
+
+    /// the earthquake with the biggest magnitude
+    pub fn strongest(&self) -> Option<&Earthquake> {
+        Earthquake::biggest(&self.earthquakes)
+    }
+
+    /// the county with the strongest shaking from any of the earthquakes (if more than
+    /// one county got that, the one that felt the most earthquakes), and that intensity
+    pub fn most_shaken_county(&self) -> Option<(String, SeismicIntensity)> {
+        // strongest intensity and how many earthquakes were felt, by county
+        let mut counties: HashMap<String, (SeismicIntensity, usize)> = HashMap::new();
+        for eq in &self.earthquakes {
+            for (county, intensity) in county_intensities(eq) {
+                if intensity == SeismicIntensity::Zero {
+                    continue;
+                }
+                let entry = counties.entry(county).or_insert((intensity, 0));
+                entry.0 = entry.0.max(intensity);
+                entry.1 += 1;
+            }
+        }
+
+        counties
+            .into_iter()
+            .max_by(|(a_name, a), (b_name, b)| a.cmp(b).then_with(|| b_name.cmp(a_name)))
+            .map(|(county, (intensity, _))| (county, intensity))
+    }
+
+    /// the digest's tweet text in `language`, ending with its hashtags (cut short like a
+    /// post if it's too long)
+    pub fn text(&self, language: Language) -> String {
+        let counts = self.band_counts();
+        let bands = counts.iter().zip(MAGNITUDE_BANDS.iter()).filter(|(count, _)| **count > 0);
+
+        let mut text = format!(
+            "{} ({}～{})",
+            self.period.heading(language),
+            self.from.format("%m/%d %H:%M"),
+            self.to.format("%m/%d %H:%M")
+        );
+        match language {
+            Language::Chinese => {
+                text.push_str(&format!("：共{}次地震", self.earthquakes.len()));
+                let bands: Vec<String> = bands.map(|(count, (_, label))| format!("{} {}次", label, count)).collect();
+                if !bands.is_empty() {
+                    text.push_str(&format!("（{}）", bands.join("、")));
+                }
+                text.push('。');
+
+                if let Some(strongest) = self.strongest() {
+                    let info = &strongest.earthquake_info;
+                    text.push_str(&format!(
+                        "最大規模{:.1}，{}，{}。",
+                        info.earthquake_magnitude.magnitude_value, info.origin_time, info.epicenter.location
+                    ));
+                }
+                if let Some((county, intensity)) = self.most_shaken_county() {
+                    text.push_str(&format!("最大震度{}{}。", county, intensity));
+                }
+            }
+            Language::English => {
+                text.push_str(&format!(": {} earthquakes", self.earthquakes.len()));
+                let bands: Vec<String> = bands.map(|(count, (_, label))| format!("{} {}", count, label)).collect();
+                if !bands.is_empty() {
+                    text.push_str(&format!(" ({})", bands.join(", ")));
+                }
+                text.push('.');
+
+                if let Some(strongest) = self.strongest() {
+                    let info = &strongest.earthquake_info;
+                    text.push_str(&format!(
+                        " The biggest was M{:.1} at {} at {:.2}°N {:.2}°E.",
+                        info.earthquake_magnitude.magnitude_value,
+                        info.origin_time,
+                        info.epicenter.lat,
+                        info.epicenter.lon
+                    ));
+                }
+                if let Some((county, intensity)) = self.most_shaken_county() {
+                    text.push_str(&format!(
+                        " Strongest shaking: intensity {} in {}.",
+                        intensity.short_label(),
+                        county
+                    ));
+                }
+            }
+        }
+
+        cut_to_length(format!("{} {}", text, language.hashtags()))
+    }
+}
+
+/// post the profile's `period` digest ending at `now` (a map of every epicenter big
+/// enough for the profile, and the digest text) and save that it went out today (in
+/// the profile's `db`). if there weren't any earthquakes nothing is posted, but it
+/// still counts as done for today
+pub async fn post_digest(
+    profile: &Profile,
+    db: &EqDb,
+    renderer: &MapRenderer,
+    period: DigestPeriod,
+    now: NaiveDateTime,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut digest = Digest::from_archive(db, period, now)?;
+    digest.earthquakes.retain(|eq| profile.wants(eq));
+
+    if digest.earthquakes.is_empty() {
+        info!("no earthquakes for the {} digest, so not posting it", period.name());
+    } else {
+        let img_64 = renderer.render_summary_png(&digest.earthquakes)?;
+        post_tweet(&profile.token, digest.text(profile.language), Some(&img_64), None).await?;
+    }
+
+    db.store_last_digest(period.name(), now.date().to_string());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::{is_cut_to_length, post_length};
+    use chrono::NaiveDate;
+
+    fn at(date: (i32, u32, u32), hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    /// a daily digest of `earthquakes`, up to 2024-04-03 08:00
+    fn daily(earthquakes: Vec<Earthquake>) -> Digest {
+        Digest {
+            period: DigestPeriod::Daily,
+            from: at((2024, 4, 2), 8),
+            to: at((2024, 4, 3), 8),
+            earthquakes,
+        }
+    }
+
+    #[test]
+    fn earthquakes_are_counted_in_their_magnitude_band() {
+        let digest = daily(
+            [3.9, 4.0, 4.9, 5.0, 5.99, 6.0, 7.2]
+                .iter()
+                .map(|magnitude| Earthquake::for_test("2024-04-03 07:58:09", *magnitude))
+                .collect(),
+        );
+
+        assert_eq!(digest.band_counts(), [1, 2, 2, 2]);
+        assert_eq!(daily(Vec::new()).band_counts(), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn most_shaken_county_is_the_strongest_then_the_most_felt() {
+        // 花蓮縣 and 宜蘭縣 both got 4, but 宜蘭縣 felt both earthquakes
+        let digest = daily(vec![
+            Earthquake::for_test("2024-04-03 07:58:09", 5.0)
+                .with_area("花蓮縣", "4級", &[])
+                .with_area("宜蘭縣", "2級", &[]),
+            Earthquake::for_test("2024-04-03 08:11:00", 5.0)
+                .with_area("宜蘭縣", "4級", &[])
+                .with_area("臺北市", "0級", &[]),
+        ]);
+        assert_eq!(
+            digest.most_shaken_county(),
+            Some(("宜蘭縣".to_string(), SeismicIntensity::Four))
+        );
+
+        // a tie on both goes to the name that sorts first, so it's always the same one
+        let digest = daily(vec![Earthquake::for_test("2024-04-03 07:58:09", 5.0)
+            .with_area("花蓮縣", "3級", &[])
+            .with_area("宜蘭縣", "3級", &[])]);
+        assert_eq!(
+            digest.most_shaken_county(),
+            Some(("宜蘭縣".to_string(), SeismicIntensity::Three))
+        );
+
+        // nothing felt
+        let digest = daily(vec![Earthquake::for_test("2024-04-03 07:58:09", 5.0).with_area("臺北市", "0級", &[])]);
+        assert_eq!(digest.most_shaken_county(), None);
+    }
+
+    #[test]
+    fn digests_are_due_once_a_day_after_the_hour() {
+        let db = EqDb::temporary();
+        let options = DigestOptions {
+            daily: true,
+            weekly: true,
+            hour: 20,
+        };
+
+        // 2024-04-06 is a Saturday and 2024-04-07 a Sunday
+        assert!(options.due(&db, at((2024, 4, 6), 19)).is_empty());
+        assert_eq!(options.due(&db, at((2024, 4, 6), 20)), vec![DigestPeriod::Daily]);
+        assert_eq!(
+            options.due(&db, at((2024, 4, 7), 21)),
+            vec![DigestPeriod::Daily, DigestPeriod::Weekly]
+        );
+
+        db.store_last_digest(DigestPeriod::Daily.name(), "2024-04-07".to_string());
+        assert_eq!(options.due(&db, at((2024, 4, 7), 22)), vec![DigestPeriod::Weekly]);
+        db.store_last_digest(DigestPeriod::Weekly.name(), "2024-04-07".to_string());
+        assert!(options.due(&db, at((2024, 4, 7), 23)).is_empty());
+        // and again the next day
+        assert_eq!(options.due(&db, at((2024, 4, 8), 20)), vec![DigestPeriod::Daily]);
+
+        let off = DigestOptions {
+            daily: false,
+            weekly: false,
+            hour: 20,
+        };
+        assert!(off.due(&db, at((2024, 4, 14), 20)).is_empty());
+    }
+
+    #[test]
+    fn english_text_names_the_most_shaken_county() {
+        let digest = daily(vec![Earthquake::for_test("2024-04-03 07:58:09", 5.0).with_area("花蓮縣", "5弱", &[])]);
+
+        assert!(digest.text(Language::Chinese).contains("最大震度花蓮縣5弱。"));
+        assert!(digest
+            .text(Language::English)
+            .contains(" Strongest shaking: intensity 5- in 花蓮縣."));
+    }
+
+    #[test]
+    fn text_is_cut_to_a_posts_length() {
+        let digest = daily(vec![Earthquake::with_long_location("2024-04-03 07:58:09", 7.2)]);
+
+        let text = digest.text(Language::Chinese);
+        assert!(text.starts_with("過去24小時台灣地震"));
+        assert!(is_cut_to_length(&text));
+
+        let text = digest.text(Language::English);
+        assert!(text.starts_with("Taiwan earthquakes in the past 24 hours (04/02 08:00～04/03 08:00)"));
+        assert!(post_length(&text) <= 280);
+    }
+}
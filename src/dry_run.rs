@@ -0,0 +1,106 @@
+use crate::cwb_api::structs::{Earthquake, Response};
+use crate::cwb_api::BothResponses;
+use crate::profile::Profile;
+use crate::swarm::{singles, split_swarm, Post, SwarmOptions};
+use crate::taiwan_map::{encode_png, MapRenderer};
+use crate::tweet::summary_text;
+use std::path::{Path, PathBuf};
+
+/// render the maps and print the tweet text for some earthquakes, without tweeting
+/// or touching the database (a swarm gets its summary map and thread too, with the
+/// same swarm settings as the bot)
+/// arguments:
+/// * renderer - draws the maps
+/// * profiles - print what each of these would post
+/// * file - a saved CWB api response to read. if None, get the latest earthquakes from the api
+/// * out_dir - where to save the maps (a png and an svg of each)
+pub async fn dry_run(
+    renderer: &MapRenderer,
+    profiles: &[Profile],
+    file: Option<PathBuf>,
+    out_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let earthquakes: Vec<Earthquake> = match file {
+        Some(file) => {
+            let response: Response = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+            response.records.earthquake
+        }
+        None => BothResponses::new_from_last_time(None)
+            .await?
+            .get_all_earthquakes(),
+    };
+
+    if earthquakes.is_empty() {
+        println!("no earthquakes found");
+    }
+
+    for eq in &earthquakes {
+        let (map_image, report) = renderer.render_with_report(eq);
+
+        // name the map after the origin time (ex: dry-run-2024-04-03_07-58-09.png)
+        let map_name = format!("dry-run-{}", file_time(eq));
+        let map_path = out_dir.join(format!("{}.png", map_name));
+        std::fs::write(&map_path, encode_png(&map_image)?)?;
+        let svg_path = out_dir.join(format!("{}.svg", map_name));
+        std::fs::write(&svg_path, renderer.render_svg(eq))?;
+
+        println!("=== {} ===", eq.earthquake_info.origin_time);
+        println!("map: {} (and {})", map_path.display(), svg_path.display());
+        print!("{}", report);
+        println!();
+    }
+
+    // what each profile would post: the earthquakes big enough for it, with a swarm
+    // (if there is one) as a thread
+    let swarm_options = SwarmOptions::from_env()?;
+    for profile in profiles {
+        println!("=== profile {} ===", profile.name);
+
+        let (wanted, too_small): (Vec<Earthquake>, Vec<Earthquake>) =
+            earthquakes.iter().cloned().partition(|eq| profile.wants(eq));
+        for eq in &too_small {
+            println!("{}: too small, so not posted", eq.earthquake_info.origin_time);
+        }
+
+        // (without the database, so there's no thread up and nothing posted yet)
+        let posts = match &swarm_options {
+            Some(options) => split_swarm(wanted, &[], None, options),
+            None => singles(wanted),
+        };
+        for post in &posts {
+            match post {
+                Post::Single(eq) | Post::Reply(eq) => {
+                    println!("{}: {}", eq.earthquake_info.origin_time, profile.quake_text(eq))
+                }
+                Post::Thread { swarm, replies } => {
+                    let (first, last) = (&swarm[0], &swarm[swarm.len() - 1]);
+                    let map_name = format!("dry-run-summary-{}-{}", profile.name, file_time(first));
+                    let map_path = out_dir.join(format!("{}.png", map_name));
+                    std::fs::write(&map_path, renderer.render_summary_png(swarm)?)?;
+                    let svg_path = out_dir.join(format!("{}.svg", map_name));
+                    std::fs::write(&svg_path, renderer.render_summary_svg(swarm))?;
+
+                    println!(
+                        "swarm: {} earthquakes, {} to {} (posted as this thread instead of one by one)",
+                        swarm.len(),
+                        first.earthquake_info.origin_time,
+                        last.earthquake_info.origin_time
+                    );
+                    println!("text: {}", summary_text(swarm, profile.language));
+                    for (i, eq) in replies.iter().enumerate() {
+                        println!("reply {}: {}", i + 1, profile.quake_text(eq));
+                    }
+                    println!("map: {} (and {})", map_path.display(), svg_path.display());
+                }
+            }
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// the origin time, for a file name (ex: 2024-04-03_07-58-09)
+fn file_time(eq: &Earthquake) -> String {
+    eq.earthquake_info.origin_time.replace(' ', "_").replace(':', "-")
+}
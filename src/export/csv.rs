@@ -0,0 +1,70 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+
+const HEADER: &str = "id,origin_time,latitude,longitude,depth_km,magnitude,magnitude_type,location,max_intensity";
+
+/// a csv with a row for each earthquake (times are Taiwan time, and max_intensity is
+/// the strongest readable station, empty if none could be read)
+pub fn to_csv(earthquakes: &[Earthquake]) -> String {
+    let mut csv = format!("{}\n", HEADER);
+
+    for eq in earthquakes {
+        let info = &eq.earthquake_info;
+        let max_intensity = eq
+            .intensity
+            .shaking_area
+            .iter()
+            .flat_map(|area| area.eq_station.iter())
+            .filter_map(|station| station.intensity().ok())
+            .max()
+            .map(|intensity| intensity.to_string())
+            .unwrap_or_default();
+
+        let row = [
+            EqDb::archive_id(eq),
+            info.origin_time.clone(),
+            info.epicenter.lat.to_string(),
+            info.epicenter.lon.to_string(),
+            info.focal_depth.to_string(),
+            info.earthquake_magnitude.magnitude_value.to_string(),
+            info.earthquake_magnitude.magnitude_type.clone(),
+            info.epicenter.location.clone(),
+            max_intensity,
+        ];
+        let row: Vec<String> = row.iter().map(|field| quote(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// quote a field if it has a comma, quote or line break in it
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_fields_read_back_the_same() {
+        let mut eq = Earthquake::for_test("2022-06-20 07:12:00", 4.2);
+        let location = "花蓮縣, \"east\" of the\ncounty hall";
+        eq.earthquake_info.epicenter.location = location.to_string();
+
+        let csv = to_csv(&[eq]);
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(reader.headers().unwrap(), HEADER.split(',').collect::<Vec<_>>());
+
+        let rows: Vec<_> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][7], location);
+        assert_eq!(&rows[0][1], "2022-06-20 07:12:00");
+    }
+}
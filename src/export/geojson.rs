@@ -0,0 +1,77 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+use serde_json::{json, Value};
+
+/// a GeoJSON FeatureCollection of the earthquakes: a point for each epicenter, and a
+/// point for each station with its intensity. every feature has the earthquake's
+/// archive id as `quake_id` and says what it is in `kind` (epicenter or station)
+pub fn to_geojson(earthquakes: &[Earthquake]) -> Value {
+    let mut features = Vec::new();
+
+    for eq in earthquakes {
+        let quake_id = EqDb::archive_id(eq);
+        let info = &eq.earthquake_info;
+
+        features.push(json!({
+            "type": "Feature",
+            "geometry": point(info.epicenter.lat, info.epicenter.lon),
+            "properties": {
+                "kind": "epicenter",
+                "quake_id": quake_id,
+                "origin_time": info.origin_time,
+                "magnitude": info.earthquake_magnitude.magnitude_value,
+                "magnitude_type": info.earthquake_magnitude.magnitude_type,
+                "depth_km": info.focal_depth,
+                "location": info.epicenter.location,
+                "report": eq.report_content,
+            },
+        }));
+
+        for area in &eq.intensity.shaking_area {
+            for station in &area.eq_station {
+                features.push(json!({
+                    "type": "Feature",
+                    "geometry": point(station.station_lat, station.station_lon),
+                    "properties": {
+                        "kind": "station",
+                        "quake_id": quake_id,
+                        "name": station.station_name,
+                        "county": area.county_name,
+                        // CWB's text (ex: 5弱), and the same level read into the
+                        // standard form (null if it couldn't be read)
+                        "intensity_text": station.station_intensity,
+                        "intensity": station.intensity().ok().map(|intensity| intensity.to_string()),
+                    },
+                }));
+            }
+        }
+    }
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+/// a GeoJSON point (which is [lon, lat])
+fn point(lat: f64, lon: f64) -> Value {
+    json!({
+        "type": "Point",
+        "coordinates": [lon, lat],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_are_lon_then_lat() {
+        let eq = Earthquake::for_test("2022-06-20 07:12:00", 4.2);
+
+        let geojson: Value = serde_json::from_str(&to_geojson(&[eq]).to_string()).unwrap();
+        let coordinates = &geojson["features"][0]["geometry"]["coordinates"];
+        assert_eq!(coordinates[0], 121.81);
+        assert_eq!(coordinates[1], 23.99);
+    }
+}
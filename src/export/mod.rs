@@ -0,0 +1,42 @@
+use crate::cwb_api::structs::Earthquake;
+
+mod csv;
+mod geojson;
+mod quakeml;
+
+pub use crate::export::csv::to_csv;
+pub use crate::export::geojson::to_geojson;
+pub use crate::export::quakeml::to_quakeml;
+
+/// the formats earthquakes can be exported in (for GIS tools and other catalogs)
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// a FeatureCollection with a point for each epicenter and station
+    Geojson,
+    /// a QuakeML 1.2 event for each earthquake (the station intensities aren't included,
+    /// since QuakeML has nowhere to put them)
+    Quakeml,
+    /// a row for each earthquake
+    Csv,
+}
+
+impl ExportFormat {
+    /// the content type to serve the export with
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Geojson => "application/geo+json",
+            ExportFormat::Quakeml => "application/xml",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+/// export `earthquakes` as `format`
+pub fn export(earthquakes: &[Earthquake], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Geojson => to_geojson(earthquakes).to_string(),
+        ExportFormat::Quakeml => to_quakeml(earthquakes),
+        ExportFormat::Csv => to_csv(earthquakes),
+    }
+}
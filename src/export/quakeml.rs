@@ -0,0 +1,117 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+use crate::time::CwbTime;
+use crate::xml::escape_xml;
+use chrono::Duration;
+use std::fmt::Write;
+
+/// the start of every publicID (QuakeML wants them to be unique resource names)
+const ID_ROOT: &str = "smi:earthquake-bot";
+
+/// a QuakeML 1.2 document with an event (origin and magnitude) for each earthquake
+///
+/// CWB's times are Taiwan time, but QuakeML's are UTC, so they're moved back 8 hours
+pub fn to_quakeml(earthquakes: &[Earthquake]) -> String {
+    let mut events = String::new();
+
+    for eq in earthquakes {
+        let info = &eq.earthquake_info;
+        let id = EqDb::archive_id(eq);
+        let utc_time = CwbTime::new_time_string_without_t(&info.origin_time).get_date_time() - Duration::hours(8);
+
+        // writing to a String can't fail
+        let _ = write!(
+            events,
+            r#"    <event publicID="{root}/event/{id}">
+      <description>
+        <text>{location}</text>
+        <type>region name</type>
+      </description>
+      <origin publicID="{root}/origin/{id}">
+        <time><value>{time}Z</value></time>
+        <latitude><value>{lat}</value></latitude>
+        <longitude><value>{lon}</value></longitude>
+        <depth><value>{depth}</value></depth>
+      </origin>
+      <magnitude publicID="{root}/magnitude/{id}">
+        <mag><value>{magnitude}</value></mag>
+{magnitude_type}        <originID>{root}/origin/{id}</originID>
+      </magnitude>
+      <preferredOriginID>{root}/origin/{id}</preferredOriginID>
+      <preferredMagnitudeID>{root}/magnitude/{id}</preferredMagnitudeID>
+    </event>
+"#,
+            root = ID_ROOT,
+            id = id,
+            location = escape_xml(&info.epicenter.location),
+            time = utc_time.format("%Y-%m-%dT%H:%M:%S"),
+            lat = info.epicenter.lat,
+            lon = info.epicenter.lon,
+            // QuakeML depths are in meters
+            depth = info.focal_depth * 1000.0,
+            magnitude = info.earthquake_magnitude.magnitude_value,
+            magnitude_type = match magnitude_type(&info.earthquake_magnitude.magnitude_type) {
+                Some(magnitude_type) => format!("        <type>{}</type>\n", escape_xml(&magnitude_type)),
+                None => String::new(),
+            },
+        );
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<q:quakeml xmlns:q="http://quakeml.org/xmlns/quakeml/1.2" xmlns="http://quakeml.org/xmlns/bed/1.2">
+  <eventParameters publicID="{}/events">
+{}  </eventParameters>
+</q:quakeml>
+"#,
+        ID_ROOT, events
+    )
+}
+
+/// QuakeML's name for one of CWB's magnitude types (ex: 芮氏規模 is ML), or None if CWB
+/// didn't say which it was. types it doesn't know are passed through as they are
+fn magnitude_type(cwb_type: &str) -> Option<String> {
+    match cwb_type.trim() {
+        "" => None,
+        "芮氏規模" => Some("ML".to_string()),
+        "地震矩規模" => Some("Mw".to_string()),
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_escaped_and_reads_back_the_same() {
+        let mut eq = Earthquake::for_test("2022-06-20 07:12:00", 4.2);
+        eq.earthquake_info.epicenter.location = r#"花蓮 <"east" & sea>"#.to_string();
+
+        let xml = to_quakeml(&[eq]);
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let text = doc.descendants().find(|node| node.has_tag_name("text")).unwrap();
+        assert_eq!(text.text(), Some(r#"花蓮 <"east" & sea>"#));
+    }
+
+    #[test]
+    fn cwbs_magnitude_type_is_used() {
+        let mut eq = Earthquake::for_test("2022-06-20 07:12:00", 4.2);
+        let magnitude_types = |eq: &Earthquake| {
+            let xml = to_quakeml(std::slice::from_ref(eq));
+            let doc = roxmltree::Document::parse(&xml).unwrap();
+            let magnitude = doc.descendants().find(|node| node.has_tag_name("magnitude")).unwrap();
+            magnitude
+                .children()
+                .filter(|node| node.has_tag_name("type"))
+                .map(|node| node.text().unwrap_or_default().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(magnitude_types(&eq), ["ML"]);
+        eq.earthquake_info.earthquake_magnitude.magnitude_type = "地震矩規模".to_string();
+        assert_eq!(magnitude_types(&eq), ["Mw"]);
+        eq.earthquake_info.earthquake_magnitude.magnitude_type = String::new();
+        assert!(magnitude_types(&eq).is_empty());
+    }
+}
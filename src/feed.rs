@@ -0,0 +1,258 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+use crate::time::{get_local_date_time, CwbTime};
+use crate::xml::escape_xml;
+use log::error;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// how many of the posted earthquakes are in the feed if FEED_ENTRIES isn't set
+const DEFAULT_FEED_ENTRIES: usize = 50;
+/// the start of every Atom id (they have to be permanent and unique)
+const ID_ROOT: &str = "urn:earthquake-bot";
+/// Taiwan's offset from UTC, for the Atom times (the saved times don't have one)
+const TAIWAN_OFFSET: &str = "+08:00";
+
+/// settings for the Atom feed of posted earthquakes
+/// includes:
+/// * file - where to write the feed every time something is posted (None for no file)
+/// * base_url - where the http server can be reached, for the map links (ex:
+///   https://quakes.example.com). without it the links are relative to the server
+/// * entries - how many of the newest posted earthquakes are in the feed
+#[derive(Debug, Clone)]
+pub struct FeedOptions {
+    pub file: Option<PathBuf>,
+    pub base_url: Option<String>,
+    pub entries: usize,
+}
+
+impl FeedOptions {
+    /// read the options from the environment (or .env). no file is written unless
+    /// FEED_FILE is set (the http server has the feed either way), and a setting that's
+    /// there but wrong is an error
+    /// * FEED_FILE - a path like feed.atom
+    /// * FEED_BASE_URL - a url like https://quakes.example.com
+    /// * FEED_ENTRIES - a number of earthquakes (50 if it isn't set)
+    pub fn from_env() -> Result<Self, String> {
+        let mut options = Self {
+            file: std::env::var("FEED_FILE").ok().map(PathBuf::from),
+            base_url: std::env::var("FEED_BASE_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string()),
+            entries: DEFAULT_FEED_ENTRIES,
+        };
+
+        if let Ok(entries) = std::env::var("FEED_ENTRIES") {
+            match entries.parse::<usize>() {
+                Ok(entries) if entries > 0 => options.entries = entries,
+                _ => {
+                    return Err(format!(
+                        "FEED_ENTRIES should be a number of earthquakes, not {}",
+                        entries
+                    ))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// `path` on the http server, with the base url if there is one
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url.as_deref().unwrap_or(""), path)
+    }
+}
+
+/// an Atom feed with an entry for each of the newest posted earthquakes: the report
+/// text, origin time, magnitude and a link to its map
+pub fn atom_feed(db: &EqDb, options: &FeedOptions) -> Result<String, Box<dyn std::error::Error>> {
+    let published = db.recently_published(options.entries)?;
+
+    // the feed changed when the latest earthquake in it was posted
+    let updated = published
+        .iter()
+        .map(|(_, published_at)| published_at.clone())
+        .max()
+        .unwrap_or_else(|| CwbTime::from(get_local_date_time()).format_with_t());
+
+    let mut entries = String::new();
+    for (eq, published_at) in &published {
+        let info = &eq.earthquake_info;
+        let id = EqDb::archive_id(eq);
+        let origin_time = CwbTime::new_time_string_without_t(&info.origin_time).format_with_t();
+
+        // writing to a String can't fail
+        let _ = write!(
+            entries,
+            r#"  <entry>
+    <id>{root}:quake:{id}</id>
+    <title>M{magnitude:.1} {location} ({origin_time})</title>
+    <published>{published_at}{offset}</published>
+    <updated>{published_at}{offset}</updated>
+    <link rel="alternate" type="image/png" href="{map}"/>
+    <link rel="related" type="application/json" href="{json}"/>
+    <category term="M{magnitude:.1}" label="magnitude"/>
+    <summary type="text">{report}</summary>
+    <content type="text">{report}
+地震時間 Origin time: {origin_time}{offset}
+規模 Magnitude: {magnitude_type} {magnitude:.1}
+地圖 Map: {map}</content>
+  </entry>
+"#,
+            root = ID_ROOT,
+            id = id,
+            magnitude = info.earthquake_magnitude.magnitude_value,
+            magnitude_type = escape_xml(&info.earthquake_magnitude.magnitude_type),
+            location = escape_xml(&info.epicenter.location),
+            origin_time = origin_time,
+            published_at = published_at,
+            offset = TAIWAN_OFFSET,
+            map = escape_xml(&options.url(&format!("/quakes/{}/map.png", id))),
+            json = escape_xml(&options.url(&format!("/quakes/{}", id))),
+            report = escape_xml(&eq.report_content),
+        );
+    }
+
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>{root}:feed</id>
+  <title>台灣地震 Taiwan earthquakes</title>
+  <subtitle>CWB earthquake reports, with a map of the shaking</subtitle>
+  <author><name>earthquake-bot</name></author>
+  <link rel="self" type="application/atom+xml" href="{link}"/>
+  <updated>{updated}{offset}</updated>
+{entries}</feed>
+"#,
+        root = ID_ROOT,
+        link = escape_xml(&options.url("/feed.atom")),
+        updated = updated,
+        offset = TAIWAN_OFFSET,
+        entries = entries,
+    ))
+}
+
+/// write the feed to FEED_FILE (if it's set). it's written next to the file first and
+/// then moved over it, so anything serving the file never sees half a feed
+pub fn write_feed(db: &EqDb, options: &FeedOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let file = match &options.file {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+
+    let mut temp_name = file.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp_file = PathBuf::from(temp_name);
+    std::fs::write(&temp_file, atom_feed(db, options)?)?;
+    std::fs::rename(&temp_file, file)?;
+
+    Ok(())
+}
+
+/// save that `earthquakes` were posted and write the feed again (problems are logged,
+/// since the posts already went out)
+pub fn add_to_feed(db: &EqDb, earthquakes: &[Earthquake], options: &FeedOptions) {
+    for eq in earthquakes {
+        if let Err(e) = db.mark_published(eq) {
+            error!("couldn't save that the {} earthquake was posted: {}", eq.earthquake_info.origin_time, e);
+        }
+    }
+
+    if let Err(e) = write_feed(db, options) {
+        error!("couldn't write the feed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(file: Option<PathBuf>) -> FeedOptions {
+        FeedOptions {
+            file,
+            base_url: Some("https://quakes.example.com".to_string()),
+            entries: DEFAULT_FEED_ENTRIES,
+        }
+    }
+
+    /// a db with an archived, posted earthquake whose location and report need escaping
+    fn db_with_posted_quake() -> EqDb {
+        let db = EqDb::temporary();
+        let mut eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2);
+        eq.earthquake_info.epicenter.location = "花蓮縣 <東方> & 近海".to_string();
+        eq.report_content = "規模 < 8 & 深度 10 公里".to_string();
+        db.store_earthquake(&eq).unwrap();
+        db.mark_published(&eq).unwrap();
+
+        // archived but never posted, so it isn't in the feed
+        db.store_earthquake(&Earthquake::for_test("2024-04-03 08:11:00", 6.1)).unwrap();
+
+        db
+    }
+
+    /// the text of every `<tag>` in `feed`
+    fn all<'a>(feed: &'a str, tag: &str) -> Vec<&'a str> {
+        let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+        feed.split(open.as_str())
+            .skip(1)
+            .map(|rest| &rest[..rest.find(close.as_str()).unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn text_in_the_feed_is_escaped() {
+        let feed = atom_feed(&db_with_posted_quake(), &options(None)).unwrap();
+
+        assert!(feed.contains("<title>M7.2 花蓮縣 &lt;東方&gt; &amp; 近海 (2024-04-03T07:58:09)</title>"));
+        assert!(feed.contains("<summary type=\"text\">規模 &lt; 8 &amp; 深度 10 公里</summary>"));
+        assert!(!feed.contains("<東方>"));
+        assert!(!feed.contains("& "));
+    }
+
+    #[test]
+    fn entry_ids_come_from_the_archive_id() {
+        let db = db_with_posted_quake();
+        let feed = atom_feed(&db, &options(None)).unwrap();
+
+        assert_eq!(
+            all(&feed, "id"),
+            ["urn:earthquake-bot:feed", "urn:earthquake-bot:quake:2024-04-03T07:58:09-113999"]
+        );
+        // the same every time the feed is written
+        assert_eq!(all(&atom_feed(&db, &options(None)).unwrap(), "id"), all(&feed, "id"));
+        assert!(feed.contains(r#"href="https://quakes.example.com/quakes/2024-04-03T07:58:09-113999/map.png""#));
+    }
+
+    #[test]
+    fn times_have_taiwans_offset() {
+        let feed = atom_feed(&db_with_posted_quake(), &options(None)).unwrap();
+
+        let times = [all(&feed, "updated"), all(&feed, "published")].concat();
+        assert_eq!(times.len(), 3);
+        for time in times {
+            assert!(time.contains('T') && time.ends_with(TAIWAN_OFFSET), "{}", time);
+        }
+        assert!(feed.contains("Origin time: 2024-04-03T07:58:09+08:00"));
+    }
+
+    #[test]
+    fn feed_file_is_written_whole() {
+        let db = db_with_posted_quake();
+        let file = std::env::temp_dir().join(format!("earthquake-bot-feed-{}.atom", std::process::id()));
+        let options = options(Some(file.clone()));
+
+        write_feed(&db, &options).unwrap();
+        let written = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(all(&written, "id"), all(&atom_feed(&db, &options).unwrap(), "id"));
+        assert!(written.ends_with("</feed>\n"));
+        // the temporary file was moved over it
+        assert!(!file.with_extension("atom.tmp").exists());
+    }
+
+    #[test]
+    fn no_feed_file_without_feed_file_set() {
+        assert!(write_feed(&EqDb::temporary(), &options(None)).is_ok());
+    }
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// a real life location in degrees
+/// includes:
+/// * lat - north/south position (Taiwan is around 22 to 25.3)
+/// * lon - east/west position (Taiwan is around 119.3 to 122)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// km per degree of latitude (close enough everywhere around Taiwan)
+const KM_PER_DEGREE_LAT: f64 = 110.57;
+/// km per degree of longitude at the equator
+const KM_PER_DEGREE_LON: f64 = 111.32;
+
+impl GeoPoint {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+
+    /// rough distance in km, treating the area around the two points as flat
+    /// (good enough over the size of Taiwan)
+    pub fn distance_km(&self, other: GeoPoint) -> f64 {
+        let mid_lat = ((self.lat + other.lat) / 2.0).to_radians();
+        let dx = (self.lon - other.lon) * KM_PER_DEGREE_LON * mid_lat.cos();
+        let dy = (self.lat - other.lat) * KM_PER_DEGREE_LAT;
+
+        (dx * dx + dy * dy).sqrt()
+    }
+}
@@ -0,0 +1,79 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+use tracing::level_filters::LevelFilter;
+use tracing::subscriber::Interest;
+use tracing::{info_span, Metadata, Span, Subscriber};
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::layer::{Context, Filter, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer};
+
+/// RUST_LOG's filter for the lines, but every span is kept, so the lines inside them
+/// have the fields no matter what's being logged
+struct KeepSpans(EnvFilter);
+
+impl<S: Subscriber> Filter<S> for KeepSpans {
+    fn enabled(&self, metadata: &Metadata<'_>, context: &Context<'_, S>) -> bool {
+        metadata.is_span() || Filter::enabled(&self.0, metadata, context)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.is_span() {
+            Interest::always()
+        } else {
+            Filter::<S>::callsite_enabled(&self.0, metadata)
+        }
+    }
+}
+
+/// start logging (tracing spans and events, and `log` lines) to stderr. the options come
+/// from the environment (or .env)
+/// * RUST_LOG - which lines to log, like env_logger (ex: info or info,sled=warn)
+/// * LOG_FORMAT - text or json (text if it isn't set)
+pub fn init() {
+    let json = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => true,
+        Ok("text") | Err(_) => false,
+        Ok(other) => {
+            eprintln!("LOG_FORMAT should be text or json, not {}", other);
+            false
+        }
+    };
+    // only errors if RUST_LOG isn't set (bad directives are skipped)
+    let filter = KeepSpans(
+        EnvFilter::builder()
+            .with_default_directive(LevelFilter::ERROR.into())
+            .from_env_lossy(),
+    );
+
+    // try_init also sends lines from `log` (and the libraries that use it) through
+    // tracing, so they get the span fields too
+    let registry = tracing_subscriber::registry();
+    let result = if json {
+        let layer = fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_writer(std::io::stderr);
+        registry.with(layer.with_filter(filter)).try_init()
+    } else {
+        let layer = fmt::layer().with_writer(std::io::stderr);
+        registry.with(layer.with_filter(filter)).try_init()
+    };
+    if let Err(e) = result {
+        eprintln!("couldn't start logging: {}", e);
+    }
+}
+
+/// the span everything about one earthquake (archiving, rendering, publishing) runs in
+pub fn quake_span(eq: &Earthquake) -> Span {
+    let info = &eq.earthquake_info;
+
+    info_span!(
+        "quake",
+        quake_id = %EqDb::archive_id(eq),
+        earthquake_no = eq.earthquake_no,
+        origin_time = %info.origin_time,
+        magnitude = %info.earthquake_magnitude.magnitude_value,
+    )
+}
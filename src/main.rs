@@ -1,63 +1,341 @@
+use crate::alert::{AlertOptions, Alerter, Check};
+use crate::backfill::{backfill, DEFAULT_WINDOW_DAYS};
+use crate::cwb_api::structs::Response;
 use crate::cwb_api::BothResponses;
 use crate::db::EqDb;
+use crate::digest::{Digest, DigestOptions, DigestPeriod};
+use crate::dry_run::dry_run;
+use crate::export::{export, ExportFormat};
+use crate::feed::FeedOptions;
+use crate::logging::quake_span;
+use crate::metrics::METRICS;
+use crate::pipeline::Pipeline;
+use crate::profile::Profile;
+use crate::server::{quake_channel, ServerOptions};
+use crate::status::BotStatus;
+use crate::swarm::SwarmOptions;
+use crate::taiwan_map::{MapRenderer, RenderOptions};
+use crate::time::{get_local_date_time, parse_date_or_time};
+use crate::tweet::TwitterPublisher;
 
-use std::thread::sleep;
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use log::{error, info};
+use tokio::time::sleep;
+use tracing::{info_span, Instrument};
 
+mod alert;
+mod backfill;
 mod cwb_api;
 mod db;
+mod digest;
+mod dry_run;
+mod export;
+mod feed;
+mod geo;
+mod logging;
+mod metrics;
+mod pipeline;
+mod profile;
+mod server;
+mod status;
+mod swarm;
 mod taiwan_map;
+mod template;
 mod time;
 mod tweet;
+mod xml;
 
 const WAIT_BETWEEN_API_CALLS: u64 = 1;
 const ERROR_WAIT_MINUTES: u64 = 3;
 
+/// tweets Taiwan CWB earthquake reports with a map of the shaking
+#[derive(Parser)]
+struct Cli {
+    /// run the bot if there's no command
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// render maps and print the tweet text and station diagnostics without tweeting
+    DryRun {
+        /// read a saved CWB api response instead of getting the latest earthquakes
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// folder to save the maps in
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+    },
+    /// print a digest of the archived earthquakes and save its map without posting it
+    Digest {
+        /// how much time the digest covers
+        #[arg(long, value_enum, default_value = "weekly")]
+        period: DigestPeriod,
+        /// folder to save the map in
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+        /// the profile to preview it for (the first one in PROFILES if it isn't set)
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// save the earthquakes between two dates (Taiwan time) in the archive without
+    /// posting them. if it's stopped, running it again with the same dates carries on
+    Backfill {
+        /// the first day to get (ex: 2024-01-01)
+        #[arg(long)]
+        from: NaiveDate,
+        /// the last day to get (ex: 2024-04-30)
+        #[arg(long)]
+        to: NaiveDate,
+        /// how many days to ask the CWB api for at once
+        #[arg(long, default_value_t = DEFAULT_WINDOW_DAYS)]
+        window_days: i64,
+        /// start from the beginning even if a backfill of these dates was stopped
+        #[arg(long)]
+        restart: bool,
+    },
+    /// export earthquakes for GIS tools (from the archive, or a saved CWB api response)
+    Export {
+        /// what to export them as
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+        /// read a saved CWB api response instead of the archive
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// only archived earthquakes at or after this (ex: 2024-04-01 or 2024-04-01T12:00:00)
+        #[arg(long)]
+        since: Option<String>,
+        /// only archived earthquakes before this
+        #[arg(long)]
+        until: Option<String>,
+        /// only earthquakes at least this big
+        #[arg(long)]
+        min_mag: Option<f64>,
+        /// file to write (printed if it isn't given)
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    // runtime settings (like the map options) can come from .env too
+    dotenv::dotenv().ok();
+    logging::init();
+
+    let cli = Cli::parse();
+
+    match cli.command {
+        None => run_bot().await,
+        Some(Command::DryRun { file, out_dir }) => {
+            let renderer = load_renderer();
+            let profiles = load_profiles();
+            if let Err(e) = dry_run(&renderer, &profiles, file, &out_dir).await {
+                error!("dry run failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Digest {
+            period,
+            out_dir,
+            profile,
+        }) => {
+            let (eq_db, renderer) = startup_checks();
+            let profiles = load_profiles();
+            let profile = match profile {
+                Some(name) => match profiles.into_iter().find(|profile| profile.name == name) {
+                    Some(profile) => profile,
+                    None => {
+                        error!("there's no profile called {} in PROFILES", name);
+                        std::process::exit(1);
+                    }
+                },
+                // there's always at least one profile
+                None => profiles.into_iter().next().unwrap(),
+            };
+            if let Err(e) = preview_digest(&eq_db, &renderer, &profile, period, &out_dir) {
+                error!("digest failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Backfill {
+            from,
+            to,
+            window_days,
+            restart,
+        }) => {
+            let eq_db = open_db();
+            if let Err(e) = backfill(&eq_db, from, to, window_days, restart).await {
+                error!("backfill failed (run it again to carry on): {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Export {
+            format,
+            file,
+            since,
+            until,
+            min_mag,
+            out,
+        }) => {
+            if let Err(e) = run_export(format, file, since, until, min_mag, out) {
+                error!("export failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// check for new earthquakes every minute and tweet them
+async fn run_bot() {
+    let (eq_db, renderer) = startup_checks();
+    // shared with the http server
+    let renderer = Arc::new(renderer);
 
-    let eq_db = startup_checks();
+    // the accounts to post to (a mistake in their settings stops the bot here, before
+    // anything is posted)
+    let profiles = load_profiles();
+    info!(
+        "posting as {}",
+        profiles.iter().map(|profile| profile.name.as_str()).collect::<Vec<&str>>().join(", ")
+    );
 
     // just here in case I want to set a time by myself for testing
     // eq_db.store_last_time("2021-10-24T13:00:00".to_string());
 
-    let mut times_run = 0;
+    // with enough earthquakes close together, one summary thread is posted instead
+    let swarm_options = check_settings(SwarmOptions::from_env());
+    if let Some(options) = &swarm_options {
+        info!(
+            "summarizing {} or more earthquakes within {} minutes (but not M{:.1} or bigger)",
+            options.min_quakes,
+            options.window.num_minutes(),
+            options.single_magnitude
+        );
+    }
+
+    // digests of the archived earthquakes, if they're turned on
+    let digest_options = check_settings(DigestOptions::from_env());
+
+    // operator alerts when polling or posting keeps failing, or earthquakes are stuck
+    let alert_options = check_settings(AlertOptions::from_env());
+    if let Some(options) = &alert_options {
+        info!(
+            "alerting after {} failures in a row or {} minutes stuck",
+            options.after_failures,
+            options.stuck_after.num_minutes()
+        );
+    }
+    let mut alerter = Alerter::new(alert_options);
+
+    // the Atom feed of posted earthquakes (written to a file, and served over http)
+    let feed_options = check_settings(FeedOptions::from_env());
+
+    // the http server (health checks, status, metrics, the archive api, the feed and the
+    // stream of new earthquakes) runs next to the polling loop
+    let status = Arc::new(BotStatus::new());
+    let quakes = quake_channel();
+    if let Some(server_options) = check_settings(ServerOptions::from_env()) {
+        let (server_db, server_renderer, server_status) = (eq_db.clone(), renderer.clone(), status.clone());
+        let (server_feed, server_quakes) = (feed_options.clone(), quakes.clone());
+        tokio::spawn(async move {
+            if let Err(e) = server::serve(
+                server_options,
+                server_db,
+                server_renderer,
+                server_status,
+                server_feed,
+                server_quakes,
+            )
+            .await
+            {
+                error!("the http server stopped: {}", e);
+            }
+        });
+    }
+
+    // the profiles all post from the same poll
+    let pipeline = match Pipeline::new(
+        profiles,
+        &eq_db,
+        TwitterPublisher::new(renderer),
+        status.clone(),
+        swarm_options,
+        digest_options,
+        feed_options,
+    ) {
+        Ok(pipeline) => pipeline,
+        Err(e) => panic!("couldn't get the profiles from the database: {}", e),
+    };
 
-    info!("last time (for api calls): {}", eq_db.get_last_time());
+    let mut times_run = 0;
 
     loop {
-        // get the last time from the database
-        let last_time = eq_db.get_last_time();
+        // get the oldest last time of the profiles from the database
+        let last_time = pipeline.last_time();
         // get data from 2 api endpoints
-        let two_res = BothResponses::new_from_last_time(Some(last_time)).await;
+        let two_res = BothResponses::new_from_last_time(Some(last_time.clone()))
+            .instrument(info_span!("poll", poll = times_run, last_time = %last_time))
+            .await;
 
         // handle the error in case there is an issue with the api
         if let Ok(responses) = two_res {
+            status.poll_succeeded();
+            alerter.succeeded(Check::Poll).await;
+
             // extract all earthquakes from the responses
             let all_eqs = responses.get_all_earthquakes();
 
-            // process each earthquake
-            for eq in all_eqs {
-                match eq.tweet().await {
-                    Ok(_) => eq.update_last_time(&eq_db),
+            // keep every earthquake in the archive (for the digests). the ones that weren't
+            // archived yet are new, so they're counted and sent to anyone watching
+            // /quakes/stream (it's fine if no one is). the poll starts at the oldest
+            // profile's last_time, so most of them have been seen before
+            for eq in &all_eqs {
+                let is_new = quake_span(eq).in_scope(|| match eq_db.store_earthquake(eq) {
+                    Ok(is_new) => {
+                        if is_new {
+                            info!("got a new earthquake");
+                        }
+                        is_new
+                    }
                     Err(e) => {
-                        error!("an error occurred: {}", e);
-                        error!("breaking and going to try again later");
-                        sleep(Duration::from_secs(60 * WAIT_BETWEEN_API_CALLS));
-                        break;
-                    },
+                        error!("couldn't archive the earthquake: {}", e);
+                        true
+                    }
+                });
+                if is_new {
+                    METRICS.quakes_seen.inc();
+                    let _ = quakes.send(eq.clone());
                 }
             }
 
-            // sleep
-            sleep(Duration::from_secs(60 * WAIT_BETWEEN_API_CALLS));
+            // post them for each profile
+            let all_published = pipeline.publish(&all_eqs, &mut alerter).await;
+
+            // post any digests that are due
+            pipeline.post_digests(&mut alerter).await;
+
+            alerter.check_stuck(&status).await;
+
+            // sleep (twice as long if something couldn't be posted)
+            if !all_published {
+                sleep(Duration::from_secs(60 * WAIT_BETWEEN_API_CALLS)).await;
+            }
+            sleep(Duration::from_secs(60 * WAIT_BETWEEN_API_CALLS)).await;
         } else if let Err(r_error) = two_res {
+            status.poll_failed();
+            METRICS.retries.with_label_values(&["poll"]).inc();
+
             // there was an error, so I guess take a break for some time
             error!("error: {}", r_error);
             error!("error, waiting {} minute(s)", ERROR_WAIT_MINUTES);
-            sleep(Duration::from_secs(60 * ERROR_WAIT_MINUTES));
+            alerter.failed(Check::Poll, &r_error.to_string()).await;
+            sleep(Duration::from_secs(60 * ERROR_WAIT_MINUTES)).await;
         }
 
         // increment the times run variable, and print if it has run 60 * x times
@@ -70,13 +348,74 @@ async fn main() {
     }
 }
 
-fn startup_checks() -> EqDb {
-    // make sure certain files are around
-    // check_for_file(".env");
-    check_for_file(taiwan_map::EPI_PIC_LOC);
-    check_for_file(taiwan_map::MAP_LOC);
+/// print the digest ending now and save its map, without posting it
+fn preview_digest(
+    eq_db: &EqDb,
+    renderer: &MapRenderer,
+    profile: &Profile,
+    period: DigestPeriod,
+    out_dir: &std::path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut digest = Digest::from_archive(eq_db, period, get_local_date_time())?;
+    digest.earthquakes.retain(|eq| profile.wants(eq));
+
+    println!("text: {}", digest.text(profile.language));
+    if digest.earthquakes.is_empty() {
+        println!("no earthquakes in the archive for this digest, so it wouldn't be posted");
+    } else {
+        let map_path = out_dir.join(format!("digest-{}.png", period.name()));
+        std::fs::write(&map_path, renderer.render_summary_png(&digest.earthquakes)?)?;
+        println!("map: {}", map_path.display());
+    }
+
+    Ok(())
+}
+
+/// export earthquakes from a saved CWB response, or the archive if there's no file
+fn run_export(
+    format: ExportFormat,
+    file: Option<PathBuf>,
+    since: Option<String>,
+    until: Option<String>,
+    min_mag: Option<f64>,
+    out: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let earthquakes = match file {
+        Some(file) => {
+            let response: Response = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+            let mut earthquakes = response.records.earthquake;
+            if let Some(min_mag) = min_mag {
+                earthquakes.retain(|eq| eq.earthquake_info.earthquake_magnitude.magnitude_value >= min_mag);
+            }
+            earthquakes
+        }
+        None => {
+            let since = since.as_deref().map(parse_date_or_time).transpose()?;
+            let until = until.as_deref().map(parse_date_or_time).transpose()?;
+            open_db().search_earthquakes(since, until, min_mag)?
+        }
+    };
+
+    let exported = export(&earthquakes, format);
+    match out {
+        Some(out) => {
+            std::fs::write(&out, exported)?;
+            println!("exported {} earthquakes to {}", earthquakes.len(), out.display());
+        }
+        None => print!("{}", exported),
+    }
+
+    Ok(())
+}
 
-    // connect to database, and panic if it fails, otherwise return the EqDb struct
+fn startup_checks() -> (EqDb, MapRenderer) {
+    let renderer = load_renderer();
+
+    (open_db(), renderer)
+}
+
+/// connect to database, and panic if it fails, otherwise return the EqDb struct
+fn open_db() -> EqDb {
     if let Ok(eq_db) = EqDb::new() {
         // check if a last_time exists, and if not add it. if it fails, panic
         eq_db.check_last_time_create_if_not_exist();
@@ -86,6 +425,45 @@ fn startup_checks() -> EqDb {
     }
 }
 
+/// read the profiles, and exit if their settings have a mistake
+fn load_profiles() -> Vec<Profile> {
+    check_settings(Profile::all_from_env())
+}
+
+/// the settings that were read, or exit if they have a mistake (so a typo stops the
+/// bot at startup instead of quietly using the default)
+fn check_settings<T>(settings: Result<T, String>) -> T {
+    match settings {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// make sure the map files are there, then load them
+fn load_renderer() -> MapRenderer {
+    // make sure certain files are around (unless they're compiled into the binary)
+    // check_for_file(".env");
+    if cfg!(not(feature = "embed-assets")) {
+        check_for_file(taiwan_map::EPI_PIC_LOC);
+        check_for_file(taiwan_map::MAP_LOC);
+        check_for_file(taiwan_map::MAP_GEOREF_LOC);
+        check_for_file(taiwan_map::COUNTIES_LOC);
+    }
+
+    // decode the map images once so every earthquake can reuse them. a missing font
+    // is the usual reason this fails, and the error says where to get one
+    match MapRenderer::new(check_settings(RenderOptions::from_env())) {
+        Ok(renderer) => renderer,
+        Err(e) => {
+            error!("error loading the map images: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn check_for_file(file_name: &str) {
     if !std::path::Path::new(file_name).exists() {
         panic!("{} wasn't found", file_name);
@@ -0,0 +1,120 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+
+/// the only publisher so far (the `publisher` label on the publishing metrics)
+pub const TWITTER_PUBLISHER: &str = "twitter";
+
+/// every metric the bot keeps, shared by the whole process (the polling loop, the api
+/// requests and the renderer all add to these) and shown at /metrics
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// the prometheus metrics
+/// includes:
+/// * cwb_request_duration - how long each CWB request took, by dataset
+/// * cwb_request_errors - CWB requests that failed, by dataset
+/// * quakes_seen - new earthquakes from CWB (not ones that were already archived)
+/// * quakes_filtered - earthquakes that weren't posted on their own, by reason
+/// * quakes_published - earthquakes posted (on their own or in a summary), by publisher
+/// * publish_errors - posts that failed, by publisher
+/// * render_duration - how long each map took to draw, by kind (quake or summary)
+/// * retries - times something failed and will be tried again later, by operation
+/// * outbox_depth - earthquakes from the last poll that haven't been posted yet
+/// * last_successful_poll - unix time of the last poll that worked
+/// * last_publish - unix time of the last post
+pub struct Metrics {
+    registry: Registry,
+    pub cwb_request_duration: HistogramVec,
+    pub cwb_request_errors: IntCounterVec,
+    pub quakes_seen: IntCounter,
+    pub quakes_filtered: IntCounterVec,
+    pub quakes_published: IntCounterVec,
+    pub publish_errors: IntCounterVec,
+    pub render_duration: HistogramVec,
+    pub retries: IntCounterVec,
+    pub outbox_depth: IntGauge,
+    pub last_successful_poll: IntGauge,
+    pub last_publish: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        // these are all made with fixed, valid names, so they can't fail
+        let metrics = Self {
+            registry: Registry::new_custom(Some("earthquake_bot".to_string()), None).unwrap(),
+            cwb_request_duration: HistogramVec::new(
+                HistogramOpts::new("cwb_request_duration_seconds", "how long each CWB request took"),
+                &["dataset"],
+            )
+            .unwrap(),
+            cwb_request_errors: IntCounterVec::new(
+                Opts::new("cwb_request_errors_total", "CWB requests that failed"),
+                &["dataset"],
+            )
+            .unwrap(),
+            quakes_seen: IntCounter::new("quakes_seen_total", "new earthquakes from CWB").unwrap(),
+            quakes_filtered: IntCounterVec::new(
+                Opts::new("quakes_filtered_total", "earthquakes that weren't posted on their own"),
+                &["reason"],
+            )
+            .unwrap(),
+            quakes_published: IntCounterVec::new(
+                Opts::new("quakes_published_total", "earthquakes posted"),
+                &["publisher"],
+            )
+            .unwrap(),
+            publish_errors: IntCounterVec::new(
+                Opts::new("publish_errors_total", "posts that failed"),
+                &["publisher"],
+            )
+            .unwrap(),
+            render_duration: HistogramVec::new(
+                HistogramOpts::new("render_duration_seconds", "how long each map took to draw")
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+                &["kind"],
+            )
+            .unwrap(),
+            retries: IntCounterVec::new(
+                Opts::new("retries_total", "times something failed and will be tried again later"),
+                &["operation"],
+            )
+            .unwrap(),
+            outbox_depth: IntGauge::new("outbox_depth", "earthquakes waiting to be posted").unwrap(),
+            last_successful_poll: IntGauge::new(
+                "last_successful_poll_timestamp_seconds",
+                "unix time of the last poll that worked",
+            )
+            .unwrap(),
+            last_publish: IntGauge::new("last_publish_timestamp_seconds", "unix time of the last post").unwrap(),
+        };
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.cwb_request_duration.clone()),
+            Box::new(metrics.cwb_request_errors.clone()),
+            Box::new(metrics.quakes_seen.clone()),
+            Box::new(metrics.quakes_filtered.clone()),
+            Box::new(metrics.quakes_published.clone()),
+            Box::new(metrics.publish_errors.clone()),
+            Box::new(metrics.render_duration.clone()),
+            Box::new(metrics.retries.clone()),
+            Box::new(metrics.outbox_depth.clone()),
+            Box::new(metrics.last_successful_poll.clone()),
+            Box::new(metrics.last_publish.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+
+        metrics
+    }
+
+    /// every metric in the prometheus text format
+    pub fn encode(&self) -> String {
+        let mut text = Vec::new();
+        // the text encoder only fails on a broken metric, which none of these are
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut text);
+
+        String::from_utf8_lossy(&text).to_string()
+    }
+}
@@ -0,0 +1,618 @@
+use crate::alert::{Alerter, Check};
+use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+use crate::digest::{DigestOptions, DigestPeriod};
+use crate::feed::{add_to_feed, FeedOptions};
+use crate::logging::quake_span;
+use crate::metrics::METRICS;
+use crate::profile::Profile;
+use crate::status::BotStatus;
+use crate::swarm::{origin_time, singles, split_swarm, Post, SwarmOptions};
+use crate::time::{get_local_date_time, CwbTime};
+use chrono::NaiveDateTime;
+use log::{error, info};
+use std::sync::Arc;
+use tracing::{info_span, Instrument, Span};
+
+/// where the posts go (see `TwitterPublisher`)
+pub trait Publisher {
+    /// the publisher's name, for the metrics (ex: twitter)
+    const NAME: &'static str;
+
+    /// post one earthquake for `profile`
+    async fn post(&self, profile: &Profile, eq: &Earthquake) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// post a swarm for `profile` as one thread: a summary of `swarm`, then a reply for
+    /// each of `replies` (both oldest first). a reply that fails ends the thread, and
+    /// the ones after it are left for the next poll
+    async fn post_summary(
+        &self,
+        profile: &Profile,
+        swarm: &[Earthquake],
+        replies: &[Earthquake],
+    ) -> Result<PostedThread, Box<dyn std::error::Error>>;
+
+    /// post one earthquake for `profile` as a reply to the post `reply_to`. returns the
+    /// reply's id
+    async fn post_reply(&self, profile: &Profile, eq: &Earthquake, reply_to: u64) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// post `profile`'s digest of the archived earthquakes for `period`, up to `now`
+    async fn post_digest(
+        &self,
+        profile: &Profile,
+        db: &EqDb,
+        period: DigestPeriod,
+        now: NaiveDateTime,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// a swarm thread that went up
+/// includes:
+/// * last_post - the id of the thread's last post (the next replies go under it)
+/// * replies - how many of the replies were posted (always the first ones)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostedThread {
+    pub last_post: u64,
+    pub replies: usize,
+}
+
+/// posting the earthquakes from each poll (and the digests) for every profile
+///
+/// the profiles share the poll, the archive and the publisher, but each one keeps its
+/// own last_time, digest dates and what it posted (in its own tree), so a profile that
+/// can't post doesn't hold up the others
+pub struct Pipeline<P: Publisher> {
+    profiles: Vec<(Profile, EqDb)>,
+    publisher: P,
+    status: Arc<BotStatus>,
+    swarm_options: Option<SwarmOptions>,
+    digest_options: DigestOptions,
+    feed_options: FeedOptions,
+}
+
+impl<P: Publisher> Pipeline<P> {
+    /// get each profile's state from the database (a profile that's new starts from now)
+    pub fn new(
+        profiles: Vec<Profile>,
+        eq_db: &EqDb,
+        publisher: P,
+        status: Arc<BotStatus>,
+        swarm_options: Option<SwarmOptions>,
+        digest_options: DigestOptions,
+        feed_options: FeedOptions,
+    ) -> Result<Self, sled::Error> {
+        let mut profile_dbs = Vec::new();
+        for profile in profiles {
+            let db = eq_db.for_profile(&profile.name)?;
+            db.check_last_time_create_if_not_exist();
+            info!("profile {}: last time (for api calls): {}", profile.name, db.get_last_time());
+            status.set_last_time(&profile.name, db.get_last_time());
+            profile_dbs.push((profile, db));
+        }
+
+        Ok(Self {
+            profiles: profile_dbs,
+            publisher,
+            status,
+            swarm_options,
+            digest_options,
+            feed_options,
+        })
+    }
+
+    /// the last_time to poll from: the oldest of the profiles', so the poll has what
+    /// every one of them still needs
+    pub fn last_time(&self) -> String {
+        self.profiles
+            .iter()
+            .map(|(_, db)| db.get_last_time())
+            .min()
+            .unwrap_or_else(|| CwbTime::from(get_local_date_time()).format_with_t())
+    }
+
+    /// post the earthquakes each profile hasn't posted yet (and wants). returns false if
+    /// any posts failed (those are tried again after the next poll)
+    ///
+    /// the poll starts at the oldest profile's last_time, so the others skip what they
+    /// already posted before anything is drawn
+    pub async fn publish(&self, earthquakes: &[Earthquake], alerter: &mut Alerter) -> bool {
+        // what's new to each profile, split into what it posts (and when) and what it
+        // skips (ones that are too small for it, or that it already posted)
+        let mut batches = Vec::new();
+        for (profile, db) in &self.profiles {
+            let last_time = CwbTime::new_time_string_with_t(&db.get_last_time()).get_date_time();
+            let new_eqs: Vec<Earthquake> = earthquakes
+                .iter()
+                .filter(|eq| {
+                    CwbTime::new_time_string_without_t(&eq.earthquake_info.origin_time).get_date_time() >= last_time
+                })
+                .cloned()
+                .collect();
+
+            let (mut skipped, new_eqs): (Vec<Earthquake>, Vec<Earthquake>) =
+                new_eqs.into_iter().partition(|eq| db.is_posted(eq));
+            let (wanted, too_small): (Vec<Earthquake>, Vec<Earthquake>) =
+                new_eqs.into_iter().partition(|eq| profile.wants(eq));
+            METRICS
+                .quakes_filtered
+                .with_label_values(&["magnitude"])
+                .inc_by(too_small.len() as u64);
+            skipped.extend(too_small);
+
+            let posts = match &self.swarm_options {
+                Some(options) => {
+                    let posted = self.posted_near(profile, db, &wanted, options);
+                    let thread = db.get_swarm_thread().map(|(_, newest)| newest);
+                    split_swarm(wanted, &posted, thread, options)
+                }
+                None => singles(wanted),
+            };
+            batches.push((profile, db, posts, skipped));
+        }
+
+        let mut queue_depth = batches
+            .iter()
+            .flat_map(|(_, _, posts, _)| posts)
+            .map(|post| post.earthquakes().len())
+            .sum();
+        self.status.set_queue_depth(queue_depth);
+
+        let mut all_published = true;
+        for (profile, db, posts, skipped) in batches {
+            let span = info_span!("profile", profile = %profile.name);
+            let (published, not_posted) = self
+                .publish_for_profile(profile, db, posts, &mut queue_depth, alerter)
+                .instrument(span)
+                .await;
+
+            // the skipped ones only move last_time up to the oldest one that wasn't
+            // posted, so it doesn't move past that
+            for eq in &skipped {
+                let eq_time = CwbTime::new_time_string_without_t(&eq.earthquake_info.origin_time).get_date_time();
+                if !matches!(not_posted, Some(not_posted) if eq_time >= not_posted) {
+                    eq.update_last_time(db);
+                }
+            }
+            all_published &= published;
+            self.status.set_last_time(&profile.name, db.get_last_time());
+        }
+
+        all_published
+    }
+
+    /// the archived earthquakes the profile already posted that are within a swarm window
+    /// of `earthquakes` (but aren't one of them), so a swarm can span polls
+    fn posted_near(
+        &self,
+        profile: &Profile,
+        db: &EqDb,
+        earthquakes: &[Earthquake],
+        options: &SwarmOptions,
+    ) -> Vec<Earthquake> {
+        let times = earthquakes
+            .iter()
+            .map(|eq| CwbTime::new_time_string_without_t(&eq.earthquake_info.origin_time).get_date_time());
+        let (from, to) = match (times.clone().min(), times.max()) {
+            (Some(first), Some(last)) => (first - options.window, last + options.window),
+            _ => return Vec::new(),
+        };
+
+        let new_ids: Vec<String> = earthquakes.iter().map(EqDb::archive_id).collect();
+        match db.earthquakes_between(from, to) {
+            Ok(archived) => archived
+                .into_iter()
+                .filter(|eq| profile.wants(eq) && db.is_posted(eq) && !new_ids.contains(&EqDb::archive_id(eq)))
+                .collect(),
+            Err(e) => {
+                error!("couldn't read the archive to look for a swarm: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// post one profile's earthquakes, in order: a new swarm as one thread, the next
+    /// ones in it as replies to that thread, and the rest one by one. stops at the first
+    /// post that fails. returns whether nothing failed, and the origin time of the one
+    /// that failed, if one did
+    async fn publish_for_profile(
+        &self,
+        profile: &Profile,
+        db: &EqDb,
+        posts: Vec<Post>,
+        queue_depth: &mut usize,
+        alerter: &mut Alerter,
+    ) -> (bool, Option<NaiveDateTime>) {
+        for post in posts {
+            let oldest = origin_time(&post.earthquakes()[0]);
+            // the earthquakes posted, the thread's last post (for a thread or reply) and
+            // the ones left over (replies that didn't go up after the summary did)
+            let (result, span) = match post {
+                Post::Thread { swarm, replies } => {
+                    METRICS
+                        .quakes_filtered
+                        .with_label_values(&["swarm"])
+                        .inc_by(replies.len() as u64);
+                    let swarm_span = info_span!(
+                        "swarm",
+                        quakes = swarm.len(),
+                        first_quake_id = %EqDb::archive_id(&swarm[0]),
+                    );
+                    let result = self
+                        .publisher
+                        .post_summary(profile, &swarm, &replies)
+                        .instrument(swarm_span.clone())
+                        .await
+                        .map(|thread| {
+                            let mut posted = replies;
+                            let left = posted.split_off(thread.replies.min(posted.len()));
+                            (posted, Some(thread.last_post), left)
+                        });
+                    (result, swarm_span)
+                }
+                Post::Reply(eq) => {
+                    METRICS.quakes_filtered.with_label_values(&["swarm"]).inc();
+                    let span = quake_span(&eq);
+                    // the thread is only there if it was saved
+                    let result = match db.get_swarm_thread() {
+                        Some((reply_to, _)) => self
+                            .publisher
+                            .post_reply(profile, &eq, reply_to)
+                            .instrument(span.clone())
+                            .await
+                            .map(|reply| (vec![eq], Some(reply), Vec::new())),
+                        None => Err("the swarm thread to reply to wasn't saved".into()),
+                    };
+                    (result, span)
+                }
+                Post::Single(eq) => {
+                    // rendering and posting (and the lines about them) are in the quake's span
+                    let span = quake_span(&eq);
+                    let result = self.publisher.post(profile, &eq).instrument(span.clone()).await;
+                    (result.map(|_| (vec![eq], None, Vec::new())), span)
+                }
+            };
+
+            let (e, failed_at): (Box<dyn std::error::Error>, NaiveDateTime) = match result {
+                Ok((eqs, thread_post, left)) => {
+                    // the next earthquakes in the swarm reply to the thread's last post
+                    // (even if none of its replies went up, the summary covers them)
+                    if let (Some(reply_to), Some(newest)) = (thread_post, eqs.last().or(left.first())) {
+                        db.store_swarm_thread(reply_to, newest);
+                    }
+                    self.record_posted(db, &eqs, &span);
+                    *queue_depth -= eqs.len();
+                    self.status.set_queue_depth(*queue_depth);
+
+                    // the ones left are replies to the thread next time
+                    match left.first() {
+                        None => {
+                            alerter.succeeded(Check::Publish(profile.name.clone())).await;
+                            continue;
+                        }
+                        Some(first) => (
+                            format!("{} of the {} swarm replies weren't posted", left.len(), eqs.len() + left.len()).into(),
+                            origin_time(first),
+                        ),
+                    }
+                }
+                Err(e) => (e, oldest),
+            };
+
+            // stop here, so nothing newer is posted before this one
+            span.in_scope(|| {
+                error!("an error occurred: {}", e);
+                error!("stopping and going to try again later");
+            });
+            METRICS.publish_errors.with_label_values(&[P::NAME]).inc();
+            METRICS.retries.with_label_values(&["publish"]).inc();
+            alerter.failed(Check::Publish(profile.name.clone()), &e.to_string()).await;
+            return (false, Some(failed_at));
+        }
+
+        (true, None)
+    }
+
+    /// save that `eqs` were posted, move last_time past them and add them to the feed
+    fn record_posted(&self, db: &EqDb, eqs: &[Earthquake], span: &Span) {
+        span.in_scope(|| {
+            for eq in eqs {
+                if let Err(e) = db.store_posted(eq) {
+                    error!("couldn't save that the earthquake was posted: {}", e);
+                }
+                eq.update_last_time(db);
+            }
+        });
+        if eqs.is_empty() {
+            return;
+        }
+
+        self.status.published();
+        METRICS
+            .quakes_published
+            .with_label_values(&[P::NAME])
+            .inc_by(eqs.len() as u64);
+        add_to_feed(db, eqs, &self.feed_options);
+    }
+
+    /// post any digests that are due for each profile (one that fails is tried again
+    /// next time)
+    pub async fn post_digests(&self, alerter: &mut Alerter) {
+        let now = get_local_date_time();
+
+        for (profile, db) in &self.profiles {
+            for period in self.digest_options.due(db, now) {
+                let digest_span = info_span!("digest", profile = %profile.name, period = period.name());
+                match self
+                    .publisher
+                    .post_digest(profile, db, period, now)
+                    .instrument(digest_span.clone())
+                    .await
+                {
+                    Ok(_) => {
+                        self.status.published();
+                        alerter.succeeded(Check::Publish(profile.name.clone())).await;
+                    }
+                    Err(e) => {
+                        digest_span.in_scope(|| error!("an error occurred posting the {} digest: {}", period.name(), e));
+                        METRICS.publish_errors.with_label_values(&[P::NAME]).inc();
+                        METRICS.retries.with_label_values(&["digest"]).inc();
+                        alerter.failed(Check::Publish(profile.name.clone()), &e.to_string()).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::Language;
+    use std::cell::RefCell;
+
+    /// remembers what it posted, and fails for the profiles in `failing` and the
+    /// earthquakes (origin times) in `failing_quakes`
+    #[derive(Default)]
+    struct TestPublisher {
+        failing: RefCell<Vec<String>>,
+        failing_quakes: RefCell<Vec<String>>,
+        posted: RefCell<Vec<(String, String)>>,
+    }
+
+    impl TestPublisher {
+        /// the origin times `profile` posted, in order
+        fn posted_by(&self, profile: &str) -> Vec<String> {
+            self.posted
+                .borrow()
+                .iter()
+                .filter(|(name, _)| name == profile)
+                .map(|(_, origin_time)| origin_time.clone())
+                .collect()
+        }
+    }
+
+    impl Publisher for &TestPublisher {
+        const NAME: &'static str = "test";
+
+        async fn post(&self, profile: &Profile, eq: &Earthquake) -> Result<(), Box<dyn std::error::Error>> {
+            let origin_time = eq.earthquake_info.origin_time.clone();
+            if self.failing.borrow().contains(&profile.name) || self.failing_quakes.borrow().contains(&origin_time) {
+                return Err("401 unauthorized".into());
+            }
+            self.posted.borrow_mut().push((profile.name.clone(), origin_time));
+            Ok(())
+        }
+
+        async fn post_summary(
+            &self,
+            profile: &Profile,
+            _swarm: &[Earthquake],
+            replies: &[Earthquake],
+        ) -> Result<PostedThread, Box<dyn std::error::Error>> {
+            if self.failing.borrow().contains(&profile.name) {
+                return Err("401 unauthorized".into());
+            }
+            // like a thread, a reply that fails ends it
+            let mut posted = 0;
+            for eq in replies {
+                if self.post(profile, eq).await.is_err() {
+                    break;
+                }
+                posted += 1;
+            }
+            Ok(PostedThread {
+                last_post: self.posted.borrow().len() as u64,
+                replies: posted,
+            })
+        }
+
+        async fn post_reply(&self, profile: &Profile, eq: &Earthquake, _reply_to: u64) -> Result<u64, Box<dyn std::error::Error>> {
+            self.post(profile, eq).await?;
+            Ok(self.posted.borrow().len() as u64)
+        }
+
+        async fn post_digest(
+            &self,
+            _profile: &Profile,
+            _db: &EqDb,
+            _period: DigestPeriod,
+            _now: NaiveDateTime,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failing_profile_does_not_make_the_others_post_again() {
+        let eq_db = EqDb::temporary();
+        for name in ["zh", "en"] {
+            eq_db
+                .for_profile(name)
+                .unwrap()
+                .store_last_time("2024-04-03T00:00:00".to_string());
+        }
+
+        let publisher = TestPublisher::default();
+        publisher.failing.borrow_mut().push("en".to_string());
+        let pipeline = Pipeline::new(
+            vec![
+                Profile::for_test("zh", Language::Chinese),
+                Profile::for_test("en", Language::English),
+            ],
+            &eq_db,
+            &publisher,
+            Arc::new(BotStatus::new()),
+            None,
+            DigestOptions {
+                daily: false,
+                weekly: false,
+                hour: 8,
+            },
+            FeedOptions {
+                file: None,
+                base_url: None,
+                entries: 50,
+            },
+        )
+        .unwrap();
+        let mut alerter = Alerter::new(None);
+        let earthquakes = vec![
+            Earthquake::for_test("2024-04-03 07:58:09", 7.2),
+            Earthquake::for_test("2024-04-03 08:11:00", 6.5),
+        ];
+
+        assert!(!pipeline.publish(&earthquakes, &mut alerter).await);
+        assert_eq!(publisher.posted_by("zh"), vec!["2024-04-03 07:58:09", "2024-04-03 08:11:00"]);
+        assert!(publisher.posted_by("en").is_empty());
+        // the next poll starts where en is still stuck
+        assert_eq!(pipeline.last_time(), "2024-04-03T00:00:00");
+
+        // the same earthquakes come back (even if zh's last_time is behind too, like
+        // after restoring a backup), and en works again
+        eq_db
+            .for_profile("zh")
+            .unwrap()
+            .store_last_time("2024-04-03T00:00:00".to_string());
+        publisher.failing.borrow_mut().clear();
+        assert!(pipeline.publish(&earthquakes, &mut alerter).await);
+        assert_eq!(publisher.posted_by("zh"), vec!["2024-04-03 07:58:09", "2024-04-03 08:11:00"]);
+        assert_eq!(publisher.posted_by("en"), vec!["2024-04-03 07:58:09", "2024-04-03 08:11:00"]);
+        assert_eq!(pipeline.last_time(), "2024-04-03T08:11:01");
+    }
+
+    #[tokio::test]
+    async fn a_swarm_thread_gets_replies_from_the_next_polls() {
+        let eq_db = EqDb::temporary();
+        let db = eq_db.for_profile("zh").unwrap();
+        db.store_last_time("2024-04-03T00:00:00".to_string());
+
+        let publisher = TestPublisher::default();
+        let pipeline = Pipeline::new(
+            vec![Profile::for_test("zh", Language::Chinese)],
+            &eq_db,
+            &publisher,
+            Arc::new(BotStatus::new()),
+            Some(SwarmOptions {
+                min_quakes: 3,
+                window: chrono::Duration::minutes(60),
+                single_magnitude: 6.0,
+            }),
+            DigestOptions {
+                daily: false,
+                weekly: false,
+                hour: 8,
+            },
+            FeedOptions {
+                file: None,
+                base_url: None,
+                entries: 50,
+            },
+        )
+        .unwrap();
+        let mut alerter = Alerter::new(None);
+
+        let first_poll = [
+            Earthquake::for_test("2024-04-03 08:00:00", 4.0),
+            Earthquake::for_test("2024-04-03 08:10:00", 4.0),
+            Earthquake::for_test("2024-04-03 08:20:00", 4.0),
+        ];
+        assert!(pipeline.publish(&first_poll, &mut alerter).await);
+        let (thread_post, newest) = db.get_swarm_thread().unwrap();
+        assert_eq!(newest, CwbTime::new_time_string_without_t("2024-04-03 08:20:00").get_date_time());
+
+        // the next one goes under the thread's last post right away
+        assert!(pipeline.publish(&[Earthquake::for_test("2024-04-03 08:50:00", 4.0)], &mut alerter).await);
+        assert_eq!(
+            publisher.posted_by("zh"),
+            vec!["2024-04-03 08:00:00", "2024-04-03 08:10:00", "2024-04-03 08:20:00", "2024-04-03 08:50:00"]
+        );
+        let (reply_post, newest) = db.get_swarm_thread().unwrap();
+        assert_ne!(reply_post, thread_post);
+        assert_eq!(newest, CwbTime::new_time_string_without_t("2024-04-03 08:50:00").get_date_time());
+    }
+
+    #[tokio::test]
+    async fn swarm_replies_that_didnt_go_up_are_replies_next_time() {
+        let eq_db = EqDb::temporary();
+        let db = eq_db.for_profile("zh").unwrap();
+        db.store_last_time("2024-04-03T00:00:00".to_string());
+
+        let publisher = TestPublisher::default();
+        publisher.failing_quakes.borrow_mut().push("2024-04-03 08:20:00".to_string());
+        let pipeline = Pipeline::new(
+            vec![Profile::for_test("zh", Language::Chinese)],
+            &eq_db,
+            &publisher,
+            Arc::new(BotStatus::new()),
+            Some(SwarmOptions {
+                min_quakes: 3,
+                window: chrono::Duration::minutes(60),
+                single_magnitude: 6.0,
+            }),
+            DigestOptions {
+                daily: false,
+                weekly: false,
+                hour: 8,
+            },
+            FeedOptions {
+                file: None,
+                base_url: None,
+                entries: 50,
+            },
+        )
+        .unwrap();
+        let mut alerter = Alerter::new(None);
+
+        let poll = [
+            Earthquake::for_test("2024-04-03 08:00:00", 4.0),
+            Earthquake::for_test("2024-04-03 08:10:00", 4.0),
+            Earthquake::for_test("2024-04-03 08:20:00", 4.0),
+            Earthquake::for_test("2024-04-03 08:30:00", 4.0),
+        ];
+        for eq in &poll {
+            eq_db.store_earthquake(eq).unwrap();
+        }
+
+        // the third reply fails, so only the first two count as posted
+        assert!(!pipeline.publish(&poll, &mut alerter).await);
+        assert_eq!(publisher.posted_by("zh"), vec!["2024-04-03 08:00:00", "2024-04-03 08:10:00"]);
+        assert!(db.is_posted(&poll[1]));
+        assert!(!db.is_posted(&poll[2]) && !db.is_posted(&poll[3]));
+        assert_eq!(db.get_last_time(), "2024-04-03T08:10:01");
+        assert_eq!(db.recently_published(10).unwrap().len(), 2);
+        let (thread_post, newest) = db.get_swarm_thread().unwrap();
+        assert_eq!(newest, origin_time(&poll[1]));
+
+        // the same poll comes back, and the rest go under the thread instead of a new one
+        publisher.failing_quakes.borrow_mut().clear();
+        assert!(pipeline.publish(&poll, &mut alerter).await);
+        assert_eq!(
+            publisher.posted_by("zh"),
+            vec!["2024-04-03 08:00:00", "2024-04-03 08:10:00", "2024-04-03 08:20:00", "2024-04-03 08:30:00"]
+        );
+        let (reply_post, newest) = db.get_swarm_thread().unwrap();
+        assert_ne!(reply_post, thread_post);
+        assert_eq!(newest, origin_time(&poll[3]));
+        assert_eq!(db.recently_published(10).unwrap().len(), 4);
+    }
+}
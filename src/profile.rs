@@ -0,0 +1,200 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::template::Template;
+use dotenv_codegen::dotenv;
+use egg_mode::KeyPair;
+use egg_mode::Token;
+use egg_mode::Token::Access;
+
+/// the profile the bot runs as if PROFILES isn't set. it posts with the keys compiled in
+/// from .env and keeps its state where it was before there were profiles
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// what language a profile posts in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    /// CWB's report, with Chinese and English hashtags
+    Chinese,
+    /// the magnitude, time, depth, epicenter and strongest shaking, written in English
+    English,
+}
+
+impl Language {
+    /// read zh or en
+    fn parse(language: &str) -> Option<Self> {
+        match language {
+            "zh" => Some(Language::Chinese),
+            "en" => Some(Language::English),
+            _ => None,
+        }
+    }
+
+    /// hashtags at the end of every post
+    pub fn hashtags(self) -> &'static str {
+        match self {
+            Language::Chinese => "#台灣 #地震 #Taiwan #earthquake",
+            Language::English => "#Taiwan #earthquake",
+        }
+    }
+
+    /// the template a profile posts with if it doesn't have its own
+    pub fn default_template(self) -> &'static str {
+        match self {
+            Language::Chinese => "{report} {hashtags}",
+            Language::English => {
+                "M{magnitude} earthquake in Taiwan at {time:%H:%M on %b %-d, %Y} Taiwan time, {depth} km deep \
+                 at {lat}°N {lon}°E.{if max_intensity} Strongest shaking: intensity {max_intensity}.{end} {hashtags}"
+            }
+        }
+    }
+}
+
+/// one account the bot posts to
+/// includes:
+/// * name - what it's called in PROFILES (and the name of its tree in the database)
+/// * language - what its posts are written in
+/// * min_magnitude - smaller earthquakes aren't posted (None posts everything)
+/// * template - the text of its posts (see `Template`)
+/// * token - the Twitter keys it posts with
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub language: Language,
+    pub min_magnitude: Option<f64>,
+    pub template: Template,
+    pub token: Token,
+}
+
+impl Profile {
+    /// read the profiles from the environment (or .env). without PROFILES there's just
+    /// the default profile, which posts everything in Chinese with the compiled in keys
+    /// * PROFILES - profile names separated by commas (ex: zh,en,m5). they can only have
+    ///   lowercase letters, numbers and _
+    ///
+    /// and for each profile (ex: PROFILE_EN_LANGUAGE for the en profile):
+    /// * PROFILE_<NAME>_LANGUAGE - zh or en (zh if it isn't set)
+    /// * PROFILE_<NAME>_MIN_MAGNITUDE - a magnitude like 5.0 (everything if it isn't set)
+    /// * PROFILE_<NAME>_TEMPLATE - the text of its posts, like {report} {hashtags} (see
+    ///   `Template`. the language's usual post if it isn't set)
+    /// * PROFILE_<NAME>_API_KEY, PROFILE_<NAME>_API_SECRET_KEY, PROFILE_<NAME>_ACCESS_TOKEN
+    ///   and PROFILE_<NAME>_ACCESS_TOKEN_SECRET - its Twitter keys (only the default
+    ///   profile can leave them out, and use the compiled in ones)
+    ///
+    /// a mistake in any of them is an error, so the bot doesn't start posting to the
+    /// wrong account or in the wrong way
+    pub fn all_from_env() -> Result<Vec<Self>, String> {
+        let names = match std::env::var("PROFILES") {
+            Ok(names) => names,
+            Err(_) => return Ok(vec![Self::from_env(DEFAULT_PROFILE)?]),
+        };
+
+        let mut profiles: Vec<Self> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+                return Err(format!(
+                    "the profile name {} can only have lowercase letters, numbers and _",
+                    name
+                ));
+            }
+            if profiles.iter().any(|profile| profile.name == name) {
+                return Err(format!("the profile {} is in PROFILES twice", name));
+            }
+            profiles.push(Self::from_env(name)?);
+        }
+
+        if profiles.is_empty() {
+            return Err("PROFILES doesn't have any profile names".to_string());
+        }
+
+        Ok(profiles)
+    }
+
+    /// read the settings for the profile called `name`
+    fn from_env(name: &str) -> Result<Self, String> {
+        let var = |setting: &str| std::env::var(format!("PROFILE_{}_{}", name.to_uppercase(), setting)).ok();
+
+        let language = match var("LANGUAGE") {
+            Some(language) => Language::parse(&language)
+                .ok_or_else(|| format!("the {} profile's language should be zh or en, not {}", name, language))?,
+            None => Language::Chinese,
+        };
+
+        let min_magnitude = match var("MIN_MAGNITUDE") {
+            Some(magnitude) => Some(magnitude.parse::<f64>().map_err(|_| {
+                format!("the {} profile's min magnitude should be a number, not {}", name, magnitude)
+            })?),
+            None => None,
+        };
+
+        let template = var("TEMPLATE");
+        let template = Template::parse(template.as_deref().unwrap_or_else(|| language.default_template()))
+            .map_err(|e| format!("the {} profile's template has a mistake: {}", name, e))?;
+
+        let keys = (
+            var("API_KEY"),
+            var("API_SECRET_KEY"),
+            var("ACCESS_TOKEN"),
+            var("ACCESS_TOKEN_SECRET"),
+        );
+        let token = match keys {
+            (Some(api_key), Some(api_secret_key), Some(access_token), Some(access_token_secret)) => Access {
+                consumer: KeyPair::new(api_key, api_secret_key),
+                access: KeyPair::new(access_token, access_token_secret),
+            },
+            (None, None, None, None) if name == DEFAULT_PROFILE => compiled_in_token(),
+            _ => {
+                return Err(format!(
+                    "the {} profile needs all 4 of its Twitter keys (PROFILE_{}_API_KEY and so on)",
+                    name,
+                    name.to_uppercase()
+                ))
+            }
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            language,
+            min_magnitude,
+            template,
+            token,
+        })
+    }
+
+    /// whether the earthquake is big enough for this profile
+    pub fn wants(&self, eq: &Earthquake) -> bool {
+        match self.min_magnitude {
+            Some(min_magnitude) => eq.earthquake_info.earthquake_magnitude.magnitude_value >= min_magnitude,
+            None => true,
+        }
+    }
+
+    /// the text this profile posts for one earthquake
+    pub fn quake_text(&self, eq: &Earthquake) -> String {
+        self.template.render(eq, self.language.hashtags())
+    }
+}
+
+/// the Twitter keys compiled in from .env (the default profile's)
+fn compiled_in_token() -> Token {
+    let consumer = KeyPair::new(dotenv!("API_KEY"), dotenv!("API_SECRET_KEY"));
+    let access = KeyPair::new(dotenv!("ACCESS_TOKEN"), dotenv!("ACCESS_TOKEN_SECRET"));
+
+    Access { consumer, access }
+}
+
+#[cfg(test)]
+impl Profile {
+    /// a profile for tests, with its language's template, no magnitude limit and keys
+    /// that don't work
+    pub(crate) fn for_test(name: &str, language: Language) -> Self {
+        Self {
+            name: name.to_string(),
+            language,
+            min_magnitude: None,
+            template: Template::parse(language.default_template()).unwrap(),
+            token: Access {
+                consumer: KeyPair::new("", ""),
+                access: KeyPair::new("", ""),
+            },
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+use crate::export::{export, ExportFormat};
+use crate::server::AppState;
+use crate::time::parse_date_or_time;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+/// how many earthquakes /quakes returns if `limit` isn't given, and the most it'll return
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// the /quakes query
+/// includes:
+/// * since - only earthquakes at or after this (Taiwan time, ex: 2024-04-01 or 2024-04-01T12:00:00)
+/// * until - only earthquakes before this (same format)
+/// * min_mag - only earthquakes at least this big
+/// * limit - the most to return (newest first)
+/// * format - geojson, quakeml or csv instead of the usual json
+#[derive(Deserialize)]
+pub struct QuakeQuery {
+    since: Option<String>,
+    until: Option<String>,
+    min_mag: Option<f64>,
+    limit: Option<usize>,
+    format: Option<ExportFormat>,
+}
+
+/// the /quakes/{id} query
+/// includes:
+/// * format - geojson, quakeml or csv instead of the usual json
+#[derive(Deserialize)]
+pub struct FormatQuery {
+    format: Option<ExportFormat>,
+}
+
+/// one archived earthquake: its id (for the other urls) and everything CWB sent
+#[derive(Serialize)]
+pub struct ArchivedQuake {
+    pub id: String,
+    #[serde(flatten)]
+    pub earthquake: Earthquake,
+}
+
+impl From<Earthquake> for ArchivedQuake {
+    fn from(earthquake: Earthquake) -> Self {
+        Self {
+            id: EqDb::archive_id(&earthquake),
+            earthquake,
+        }
+    }
+}
+
+/// a failed request: the status code, and the message sent back as {"error": ...}
+pub struct ApiError {
+    code: StatusCode,
+    error: String,
+}
+
+impl ApiError {
+    pub fn new(code: StatusCode, error: impl ToString) -> Self {
+        Self {
+            code,
+            error: error.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct Body {
+            error: String,
+        }
+
+        (self.code, Json(Body { error: self.error })).into_response()
+    }
+}
+
+/// /quakes, /quakes/{id} and /quakes/{id}/map.png (the first two can also be
+/// exported with ?format=geojson, quakeml or csv)
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/quakes", get(list_quakes))
+        .route("/quakes/{id}", get(get_quake))
+        .route("/quakes/{id}/map.png", get(get_quake_map))
+}
+
+/// the archived earthquakes matching the query, newest first
+fn query_quakes(db: &EqDb, query: &QuakeQuery) -> Result<Vec<Earthquake>, ApiError> {
+    let parse = |time: &Option<String>| match time {
+        Some(time) => parse_date_or_time(time)
+            .map(Some)
+            .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e)),
+        None => Ok(None),
+    };
+    let since = parse(&query.since)?;
+    let until = parse(&query.until)?;
+
+    let mut earthquakes = db
+        .search_earthquakes(since, until, query.min_mag)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    earthquakes.reverse();
+    earthquakes.truncate(query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT));
+
+    Ok(earthquakes)
+}
+
+/// the earthquake with `id`, or a 404
+fn find_quake(db: &EqDb, id: &str) -> Result<Earthquake, ApiError> {
+    match db.get_earthquake(id) {
+        Ok(Some(eq)) => Ok(eq),
+        Ok(None) => Err(ApiError::new(StatusCode::NOT_FOUND, format!("no earthquake with the id {}", id))),
+        Err(e) => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+/// the earthquakes as json (with their ids), or exported as `format`
+fn quakes_response(earthquakes: Vec<Earthquake>, format: Option<ExportFormat>) -> Response {
+    match format {
+        Some(format) => ([(header::CONTENT_TYPE, format.content_type())], export(&earthquakes, format)).into_response(),
+        None => {
+            let quakes: Vec<ArchivedQuake> = earthquakes.into_iter().map(ArchivedQuake::from).collect();
+            Json(quakes).into_response()
+        }
+    }
+}
+
+/// the archived earthquakes, newest first
+async fn list_quakes(State(state): State<AppState>, Query(query): Query<QuakeQuery>) -> Result<Response, ApiError> {
+    let earthquakes = query_quakes(&state.db, &query)?;
+
+    Ok(quakes_response(earthquakes, query.format))
+}
+
+/// one archived earthquake
+async fn get_quake(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<FormatQuery>,
+) -> Result<Response, ApiError> {
+    let eq = find_quake(&state.db, &id)?;
+
+    Ok(match query.format {
+        Some(_) => quakes_response(vec![eq], query.format),
+        None => Json(ArchivedQuake::from(eq)).into_response(),
+    })
+}
+
+/// the earthquake's map, drawn the same way as when it was tweeted
+async fn get_quake_map(State(state): State<AppState>, Path(id): Path<String>) -> Result<Response, ApiError> {
+    let eq = find_quake(&state.db, &id)?;
+
+    // drawing takes a while, so keep it off the threads handling requests
+    let renderer = state.renderer.clone();
+    let png = tokio::task::spawn_blocking(move || renderer.render_png(&eq))
+        .await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
@@ -0,0 +1,20 @@
+use crate::feed::atom_feed;
+use crate::server::api::ApiError;
+use crate::server::AppState;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+
+/// /feed.atom
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/feed.atom", get(feed))
+}
+
+/// the Atom feed of posted earthquakes (the same as FEED_FILE)
+async fn feed(State(state): State<AppState>) -> Result<impl IntoResponse, ApiError> {
+    let feed = atom_feed(&state.db, &state.feed).map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(([(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")], feed))
+}
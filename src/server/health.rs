@@ -0,0 +1,97 @@
+use crate::server::AppState;
+use crate::status::StatusSnapshot;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+
+/// the /healthz response
+/// includes:
+/// * status - "ok", or "error" if the database can't be read
+/// * last_successful_poll - the last time both CWB requests worked
+/// * last_publish - the last time anything was posted
+/// * sled - "ok" or the database error
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    last_successful_poll: Option<String>,
+    last_publish: Option<String>,
+    sled: String,
+}
+
+/// the /readyz response
+#[derive(Serialize)]
+struct Ready {
+    ready: bool,
+    polling_failing_since: Option<String>,
+}
+
+/// the /status response: the bot's status plus the last_time it's polling from (the
+/// oldest of the profiles')
+#[derive(Serialize)]
+struct Status {
+    last_time: String,
+    #[serde(flatten)]
+    status: StatusSnapshot,
+}
+
+/// /healthz, /readyz and /status
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/status", get(status))
+}
+
+/// the process is up and the database works
+async fn healthz(State(state): State<AppState>) -> (StatusCode, Json<Health>) {
+    let snapshot = state.status.snapshot();
+    let (code, status, sled) = match state.db.check() {
+        Ok(_) => (StatusCode::OK, "ok", "ok".to_string()),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, "error", e.to_string()),
+    };
+
+    (
+        code,
+        Json(Health {
+            status,
+            last_successful_poll: snapshot.last_successful_poll,
+            last_publish: snapshot.last_publish,
+            sled,
+        }),
+    )
+}
+
+/// fails once polling CWB has been failing for longer than the threshold
+async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Ready>) {
+    let ready = !state
+        .status
+        .polling_failing_longer_than(state.options.poll_error_threshold);
+    let code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        code,
+        Json(Ready {
+            ready,
+            polling_failing_since: state.status.snapshot().polling_failing_since,
+        }),
+    )
+}
+
+/// everything in the status, plus last_time
+async fn status(State(state): State<AppState>) -> Json<Status> {
+    let status = state.status.snapshot();
+
+    Json(Status {
+        last_time: match status.last_times.values().min() {
+            Some(last_time) => last_time.clone(),
+            None => state.db.get_last_time(),
+        },
+        status,
+    })
+}
@@ -0,0 +1,108 @@
+use crate::db::EqDb;
+use crate::feed::FeedOptions;
+use crate::status::BotStatus;
+use crate::taiwan_map::MapRenderer;
+use axum::Router;
+use chrono::Duration;
+use log::info;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+mod api;
+mod feed;
+mod health;
+mod metrics;
+mod stream;
+
+pub use crate::server::stream::{quake_channel, QuakeSender};
+
+/// readiness fails once polling has been failing this long, if HEALTH_POLL_ERROR_MINUTES
+/// isn't set
+const DEFAULT_POLL_ERROR_MINUTES: i64 = 10;
+
+/// settings for the http server
+/// includes:
+/// * addr - where to listen
+/// * poll_error_threshold - how long polling can fail before the bot isn't ready
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    pub addr: SocketAddr,
+    pub poll_error_threshold: Duration,
+}
+
+impl ServerOptions {
+    /// read the options from the environment (or .env). there's no server (None)
+    /// unless HTTP_ADDR is set, and a setting that's there but wrong is an error
+    /// * HTTP_ADDR - an address and port like 0.0.0.0:8080
+    /// * HEALTH_POLL_ERROR_MINUTES - a number of minutes (10 if it isn't set)
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let addr = match std::env::var("HTTP_ADDR") {
+            Ok(addr) => addr,
+            Err(_) => return Ok(None),
+        };
+        let addr = addr.parse().map_err(|_| {
+            format!("HTTP_ADDR should be an address and port like 0.0.0.0:8080, not {}", addr)
+        })?;
+
+        let mut poll_error_minutes = DEFAULT_POLL_ERROR_MINUTES;
+        if let Ok(minutes) = std::env::var("HEALTH_POLL_ERROR_MINUTES") {
+            match minutes.parse::<i64>() {
+                Ok(minutes) if minutes > 0 => poll_error_minutes = minutes,
+                _ => {
+                    return Err(format!(
+                        "HEALTH_POLL_ERROR_MINUTES should be a number of minutes, not {}",
+                        minutes
+                    ))
+                }
+            }
+        }
+
+        Ok(Some(Self {
+            addr,
+            poll_error_threshold: Duration::minutes(poll_error_minutes),
+        }))
+    }
+}
+
+/// what every request handler can get at
+#[derive(Clone)]
+pub struct AppState {
+    pub db: EqDb,
+    pub renderer: Arc<MapRenderer>,
+    pub status: Arc<BotStatus>,
+    pub options: ServerOptions,
+    pub feed: FeedOptions,
+    pub quakes: QuakeSender,
+}
+
+/// run the http server until it fails (it's meant to be spawned next to the bot)
+pub async fn serve(
+    options: ServerOptions,
+    db: EqDb,
+    renderer: Arc<MapRenderer>,
+    status: Arc<BotStatus>,
+    feed: FeedOptions,
+    quakes: QuakeSender,
+) -> std::io::Result<()> {
+    let addr = options.addr;
+    let state = AppState {
+        db,
+        renderer,
+        status,
+        options,
+        feed,
+        quakes,
+    };
+    let app = Router::new()
+        .merge(health::routes())
+        .merge(metrics::routes())
+        .merge(api::routes())
+        .merge(feed::routes())
+        .merge(stream::routes())
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("http server listening on {}", addr);
+
+    axum::serve(listener, app).await
+}
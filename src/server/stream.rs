@@ -0,0 +1,52 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::server::api::ArchivedQuake;
+use crate::server::AppState;
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::Router;
+use futures_util::stream::{self, Stream};
+use log::warn;
+use std::convert::Infallible;
+use tokio::sync::broadcast;
+
+/// how many earthquakes a slow client can fall behind before it misses some
+const CHANNEL_CAPACITY: usize = 64;
+
+/// the polling loop sends every new earthquake here, and each /quakes/stream client
+/// gets its own receiver
+pub type QuakeSender = broadcast::Sender<Earthquake>;
+
+/// the channel for new earthquakes (nothing is kept until a client connects)
+pub fn quake_channel() -> QuakeSender {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+/// /quakes/stream
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/quakes/stream", get(quake_stream))
+}
+
+/// server-sent events: a `quake` event (the same json as /quakes/{id}) for each new
+/// earthquake as soon as the bot gets it
+async fn quake_stream(State(state): State<AppState>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = stream::unfold(state.quakes.subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(eq) => {
+                    let event = Event::default()
+                        .event("quake")
+                        .json_data(ArchivedQuake::from(eq))
+                        .unwrap_or_else(|e| Event::default().event("error").data(e.to_string()));
+                    return Some((Ok(event), receiver));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("a /quakes/stream client fell behind and missed {} earthquakes", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
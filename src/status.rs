@@ -0,0 +1,144 @@
+use crate::metrics::METRICS;
+use crate::time::{get_local_date_time, CwbTime};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// what the bot has been doing, updated by the polling loop and read by the http server
+///
+/// the times are Taiwan time, like everything else in the bot. the matching metrics
+/// (outbox depth and the last poll and publish times) are kept up to date here too
+pub struct BotStatus {
+    inner: Mutex<StatusInner>,
+}
+
+struct StatusInner {
+    started: NaiveDateTime,
+    last_successful_poll: Option<NaiveDateTime>,
+    last_publish: Option<NaiveDateTime>,
+    polling_failing_since: Option<NaiveDateTime>,
+    queue_depth: usize,
+    unpublished_since: Option<NaiveDateTime>,
+    last_times: BTreeMap<String, String>,
+}
+
+/// a copy of the status at one moment (times are formatted with the T)
+/// includes:
+/// * started - when the bot started
+/// * last_successful_poll - the last time both CWB requests worked
+/// * last_publish - the last time anything was posted
+/// * polling_failing_since - when the CWB requests started failing (None if they're working)
+/// * queue_depth - earthquakes from the last poll that haven't been posted yet
+/// * unpublished_since - when earthquakes started waiting to be posted (None if they all were)
+/// * last_times - each profile's last_time (where it's posting from)
+#[derive(Serialize, Debug, Clone)]
+pub struct StatusSnapshot {
+    pub started: String,
+    pub last_successful_poll: Option<String>,
+    pub last_publish: Option<String>,
+    pub polling_failing_since: Option<String>,
+    pub queue_depth: usize,
+    pub unpublished_since: Option<String>,
+    pub last_times: BTreeMap<String, String>,
+}
+
+impl Default for BotStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BotStatus {
+    /// nothing has happened yet
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(StatusInner {
+                started: get_local_date_time(),
+                last_successful_poll: None,
+                last_publish: None,
+                polling_failing_since: None,
+                queue_depth: 0,
+                unpublished_since: None,
+                last_times: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// the CWB requests worked
+    pub fn poll_succeeded(&self) {
+        let mut inner = self.lock();
+        inner.last_successful_poll = Some(get_local_date_time());
+        inner.polling_failing_since = None;
+        METRICS.last_successful_poll.set(Utc::now().timestamp());
+    }
+
+    /// the CWB requests failed (the first failure in a row is when it started failing)
+    pub fn poll_failed(&self) {
+        let mut inner = self.lock();
+        if inner.polling_failing_since.is_none() {
+            inner.polling_failing_since = Some(get_local_date_time());
+        }
+    }
+
+    /// something was posted
+    pub fn published(&self) {
+        self.lock().last_publish = Some(get_local_date_time());
+        METRICS.last_publish.set(Utc::now().timestamp());
+    }
+
+    /// how many earthquakes are waiting to be posted (the first time there are some is
+    /// when they started waiting)
+    pub fn set_queue_depth(&self, queue_depth: usize) {
+        let mut inner = self.lock();
+        inner.queue_depth = queue_depth;
+        if queue_depth == 0 {
+            inner.unpublished_since = None;
+        } else if inner.unpublished_since.is_none() {
+            inner.unpublished_since = Some(get_local_date_time());
+        }
+        METRICS.outbox_depth.set(queue_depth as i64);
+    }
+
+    /// whether polling has been failing for longer than `threshold`
+    pub fn polling_failing_longer_than(&self, threshold: Duration) -> bool {
+        match self.lock().polling_failing_since {
+            Some(since) => get_local_date_time() - since > threshold,
+            None => false,
+        }
+    }
+
+    /// the profile called `name` is posting from `last_time`
+    pub fn set_last_time(&self, name: &str, last_time: String) {
+        self.lock().last_times.insert(name.to_string(), last_time);
+    }
+
+    /// whether earthquakes have been waiting to be posted for longer than `threshold`
+    pub fn unpublished_longer_than(&self, threshold: Duration) -> bool {
+        match self.lock().unpublished_since {
+            Some(since) => get_local_date_time() - since > threshold,
+            None => false,
+        }
+    }
+
+    /// copy the status out
+    pub fn snapshot(&self) -> StatusSnapshot {
+        let inner = self.lock();
+        let format = |time: NaiveDateTime| CwbTime::from(time).format_with_t();
+
+        StatusSnapshot {
+            started: format(inner.started),
+            last_successful_poll: inner.last_successful_poll.map(format),
+            last_publish: inner.last_publish.map(format),
+            polling_failing_since: inner.polling_failing_since.map(format),
+            queue_depth: inner.queue_depth,
+            unpublished_since: inner.unpublished_since.map(format),
+            last_times: inner.last_times.clone(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StatusInner> {
+        // the status is only ever overwritten, so it's fine to use after a panic
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,339 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::time::CwbTime;
+use chrono::{Duration, NaiveDateTime};
+
+/// how long the window is if SWARM_WINDOW_MINUTES isn't set
+const DEFAULT_WINDOW_MINUTES: i64 = 60;
+/// how big an earthquake has to be to always get its own post, if
+/// SWARM_SINGLE_MAGNITUDE isn't set
+const DEFAULT_SINGLE_MAGNITUDE: f64 = 6.0;
+
+/// when to post one summary thread instead of a tweet for every earthquake
+/// includes:
+/// * min_quakes - how many earthquakes have to be in the window to count as a swarm
+/// * window - the longest time between the first and last earthquake of a swarm
+/// * single_magnitude - earthquakes at least this big are always posted on their own
+#[derive(Debug, Clone)]
+pub struct SwarmOptions {
+    pub min_quakes: usize,
+    pub window: Duration,
+    pub single_magnitude: f64,
+}
+
+impl SwarmOptions {
+    /// read the options from the environment (or .env). this is off (None) unless
+    /// SWARM_MIN_QUAKES is set, and a setting that's there but wrong is an error
+    /// * SWARM_MIN_QUAKES - a number, at least 2
+    /// * SWARM_WINDOW_MINUTES - a number of minutes (60 if it isn't set)
+    /// * SWARM_SINGLE_MAGNITUDE - a magnitude (6.0 if it isn't set)
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let min_quakes = match std::env::var("SWARM_MIN_QUAKES") {
+            Ok(min_quakes) => min_quakes,
+            Err(_) => return Ok(None),
+        };
+        let min_quakes = match min_quakes.parse::<usize>() {
+            Ok(min_quakes) if min_quakes >= 2 => min_quakes,
+            _ => {
+                return Err(format!(
+                    "SWARM_MIN_QUAKES should be a number (2 or more), not {}",
+                    min_quakes
+                ))
+            }
+        };
+
+        let mut window_minutes = DEFAULT_WINDOW_MINUTES;
+        if let Ok(window) = std::env::var("SWARM_WINDOW_MINUTES") {
+            match window.parse::<i64>() {
+                Ok(window) if window > 0 => window_minutes = window,
+                _ => {
+                    return Err(format!(
+                        "SWARM_WINDOW_MINUTES should be a number of minutes, not {}",
+                        window
+                    ))
+                }
+            }
+        }
+
+        let mut single_magnitude = DEFAULT_SINGLE_MAGNITUDE;
+        if let Ok(magnitude) = std::env::var("SWARM_SINGLE_MAGNITUDE") {
+            match magnitude.parse::<f64>() {
+                Ok(magnitude) if magnitude.is_finite() => single_magnitude = magnitude,
+                _ => {
+                    return Err(format!(
+                        "SWARM_SINGLE_MAGNITUDE should be a magnitude, not {}",
+                        magnitude
+                    ))
+                }
+            }
+        }
+
+        Ok(Some(Self {
+            min_quakes,
+            window: Duration::minutes(window_minutes),
+            single_magnitude,
+        }))
+    }
+}
+
+/// something to post
+pub enum Post {
+    /// one earthquake, posted on its own
+    Single(Earthquake),
+    /// a new swarm, posted as one summary thread
+    /// includes:
+    /// * swarm - every earthquake (oldest first) the summary is about, including ones
+    ///   that were already posted on their own
+    /// * replies - the ones (oldest first) that weren't posted yet, each posted as a reply
+    Thread {
+        swarm: Vec<Earthquake>,
+        replies: Vec<Earthquake>,
+    },
+    /// one more earthquake in the swarm whose thread is already up, posted as a reply to it
+    Reply(Earthquake),
+}
+
+impl Post {
+    /// the earthquakes this posts (the ones in a summary that were already posted aren't
+    /// posted again)
+    pub fn earthquakes(&self) -> &[Earthquake] {
+        match self {
+            Post::Single(eq) | Post::Reply(eq) => std::slice::from_ref(eq),
+            Post::Thread { replies, .. } => replies,
+        }
+    }
+}
+
+/// post every earthquake on its own, in origin time order (for when swarms are off)
+pub fn singles(mut earthquakes: Vec<Earthquake>) -> Vec<Post> {
+    earthquakes.sort_by_key(origin_time);
+    earthquakes.into_iter().map(Post::Single).collect()
+}
+
+/// the earthquake's origin time
+pub(crate) fn origin_time(eq: &Earthquake) -> NaiveDateTime {
+    CwbTime::new_time_string_without_t(&eq.earthquake_info.origin_time).get_date_time()
+}
+
+/// find the swarms in `earthquakes` (the new ones) and work out how to post them, in
+/// origin time order. nothing waits for a swarm's window to close
+///
+/// * an earthquake at least `single_magnitude` big is posted on its own
+/// * if the profile has a swarm thread up (`thread` is the origin time of its newest
+///   earthquake), an earthquake less than a window after it is a reply to it
+/// * an earthquake is in a new swarm if it's in a window with at least `min_quakes`
+///   earthquakes in it, counting the ones in `posted` (already posted on their own) as
+///   well as the new ones. swarm earthquakes that are less than a window apart are one
+///   swarm, which is posted as a thread (when its newest earthquake comes up) that's
+///   about all of them, with a reply for each new one
+/// * the rest are posted on their own
+pub fn split_swarm(
+    mut earthquakes: Vec<Earthquake>,
+    posted: &[Earthquake],
+    thread: Option<NaiveDateTime>,
+    options: &SwarmOptions,
+) -> Vec<Post> {
+    earthquakes.sort_by_key(origin_time);
+    let times: Vec<NaiveDateTime> = earthquakes.iter().map(origin_time).collect();
+    let mut all_times: Vec<NaiveDateTime> = posted.iter().map(origin_time).chain(times.iter().copied()).collect();
+    all_times.sort();
+    let single: Vec<bool> = earthquakes
+        .iter()
+        .map(|eq| eq.earthquake_info.earthquake_magnitude.magnitude_value >= options.single_magnitude)
+        .collect();
+
+    // the ones that carry on the thread that's up
+    let mut reply = vec![false; times.len()];
+    let mut thread = thread;
+    for i in (0..times.len()).filter(|i| !single[*i]) {
+        if let Some(newest) = thread {
+            if times[i] - newest <= options.window {
+                reply[i] = true;
+                thread = Some(times[i]);
+            }
+        }
+    }
+
+    // whether each of the others is in a window (starting at any earthquake) with enough in it
+    let in_swarm: Vec<bool> = (0..times.len())
+        .map(|i| {
+            !single[i]
+                && !reply[i]
+                && all_times
+                    .iter()
+                    .filter(|start| **start <= times[i] && times[i] - **start <= options.window)
+                    .any(|start| {
+                        all_times.iter().filter(|other| *other >= start && **other - *start <= options.window).count()
+                            >= options.min_quakes
+                    })
+        })
+        .collect();
+
+    // group the swarm earthquakes into swarms (by index)
+    let mut swarms: Vec<Vec<usize>> = Vec::new();
+    for i in (0..times.len()).filter(|i| in_swarm[*i]) {
+        match swarms.last_mut() {
+            Some(swarm) if times[i] - times[swarm[swarm.len() - 1]] <= options.window => swarm.push(i),
+            _ => swarms.push(vec![i]),
+        }
+    }
+
+    let mut earthquakes: Vec<Option<Earthquake>> = earthquakes.into_iter().map(Some).collect();
+    let mut posts = Vec::new();
+    for i in 0..earthquakes.len() {
+        if !in_swarm[i] {
+            let post = if reply[i] { Post::Reply } else { Post::Single };
+            posts.extend(earthquakes[i].take().map(post));
+            continue;
+        }
+
+        // post the swarm once its newest earthquake comes up, with the posted ones
+        // from its window in the summary
+        let swarm = swarms.iter().find(|swarm| swarm.contains(&i)).unwrap();
+        if swarm[swarm.len() - 1] == i {
+            let (from, to) = (times[swarm[0]] - options.window, times[i]);
+            let replies: Vec<Earthquake> = swarm.iter().filter_map(|j| earthquakes[*j].take()).collect();
+            let mut swarm: Vec<Earthquake> = posted
+                .iter()
+                .filter(|eq| (from..=to).contains(&origin_time(eq)))
+                .cloned()
+                .chain(replies.iter().cloned())
+                .collect();
+            swarm.sort_by_key(origin_time);
+            posts.push(Post::Thread { swarm, replies });
+        }
+    }
+
+    posts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3 earthquakes in an hour is a swarm, and M6 or bigger is posted on its own
+    fn options() -> SwarmOptions {
+        SwarmOptions {
+            min_quakes: 3,
+            window: Duration::minutes(60),
+            single_magnitude: 6.0,
+        }
+    }
+
+    fn at(time: &str) -> NaiveDateTime {
+        CwbTime::new_time_string_without_t(time).get_date_time()
+    }
+
+    fn quakes(times: &[&str]) -> Vec<Earthquake> {
+        times.iter().map(|time| Earthquake::for_test(time, 4.0)).collect()
+    }
+
+    fn origin_times(eqs: &[Earthquake]) -> Vec<&str> {
+        eqs.iter().map(|eq| eq.earthquake_info.origin_time.as_str()).collect()
+    }
+
+    /// what's posted, as the kind of post and the origin times of what it posts
+    fn posted(posts: &[Post]) -> Vec<(&'static str, Vec<&str>)> {
+        posts
+            .iter()
+            .map(|post| match post {
+                Post::Single(_) => ("single", origin_times(post.earthquakes())),
+                Post::Thread { .. } => ("thread", origin_times(post.earthquakes())),
+                Post::Reply(_) => ("reply", origin_times(post.earthquakes())),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_earthquake_on_its_own_is_a_single() {
+        let posts = split_swarm(quakes(&["2024-04-03 07:58:09"]), &[], None, &options());
+
+        assert_eq!(posted(&posts), vec![("single", vec!["2024-04-03 07:58:09"])]);
+    }
+
+    #[test]
+    fn swarm_from_earlier_polls_only_replies_with_the_new_ones() {
+        // one earthquake per poll, with the earlier ones already posted on their own
+        let already_posted = quakes(&["2024-04-03 08:00:00", "2024-04-03 08:10:00"]);
+        let posts = split_swarm(quakes(&["2024-04-03 08:20:00"]), &already_posted, None, &options());
+
+        assert_eq!(posted(&posts), vec![("thread", vec!["2024-04-03 08:20:00"])]);
+        match &posts[0] {
+            Post::Thread { swarm, .. } => assert_eq!(
+                origin_times(swarm),
+                vec!["2024-04-03 08:00:00", "2024-04-03 08:10:00", "2024-04-03 08:20:00"]
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn two_earthquakes_arent_a_swarm_of_three() {
+        let already_posted = quakes(&["2024-04-03 08:00:00"]);
+        let posts = split_swarm(quakes(&["2024-04-03 08:20:00"]), &already_posted, None, &options());
+
+        assert_eq!(posted(&posts), vec![("single", vec!["2024-04-03 08:20:00"])]);
+    }
+
+    #[test]
+    fn earthquakes_after_a_thread_is_up_are_replies_to_it() {
+        let new = quakes(&["2024-04-03 08:30:00", "2024-04-03 09:20:00", "2024-04-03 10:30:00"]);
+        let posts = split_swarm(new, &[], Some(at("2024-04-03 08:20:00")), &options());
+
+        // the window goes from the newest reply, and the thread is over after a gap
+        assert_eq!(
+            posted(&posts),
+            vec![
+                ("reply", vec!["2024-04-03 08:30:00"]),
+                ("reply", vec!["2024-04-03 09:20:00"]),
+                ("single", vec!["2024-04-03 10:30:00"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn big_earthquakes_in_a_swarm_are_posted_on_their_own() {
+        let mut new = quakes(&["2024-04-03 08:00:00", "2024-04-03 08:01:00", "2024-04-03 08:03:00"]);
+        new.insert(1, Earthquake::for_test("2024-04-03 08:00:30", 6.4));
+
+        let posts = split_swarm(new.clone(), &[], None, &options());
+        assert_eq!(
+            posted(&posts),
+            vec![
+                ("single", vec!["2024-04-03 08:00:30"]),
+                ("thread", vec!["2024-04-03 08:00:00", "2024-04-03 08:01:00", "2024-04-03 08:03:00"]),
+            ]
+        );
+
+        let posts = split_swarm(new, &[], Some(at("2024-04-03 07:50:00")), &options());
+        assert_eq!(
+            posted(&posts),
+            vec![
+                ("reply", vec!["2024-04-03 08:00:00"]),
+                ("single", vec!["2024-04-03 08:00:30"]),
+                ("reply", vec!["2024-04-03 08:01:00"]),
+                ("reply", vec!["2024-04-03 08:03:00"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn posts_are_in_origin_time_order() {
+        let new = quakes(&[
+            "2024-04-03 09:00:00",
+            "2024-04-03 05:00:00",
+            "2024-04-03 06:00:00",
+            "2024-04-03 06:10:00",
+            "2024-04-03 06:20:00",
+        ]);
+
+        let posts = split_swarm(new, &[], None, &options());
+        assert_eq!(
+            posted(&posts),
+            vec![
+                ("single", vec!["2024-04-03 05:00:00"]),
+                ("thread", vec!["2024-04-03 06:00:00", "2024-04-03 06:10:00", "2024-04-03 06:20:00"]),
+                ("single", vec!["2024-04-03 09:00:00"]),
+            ]
+        );
+    }
+}
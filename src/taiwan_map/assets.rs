@@ -0,0 +1,245 @@
+use crate::taiwan_map::canvas::Canvas;
+use crate::taiwan_map::counties::{parse_counties, County};
+use crate::taiwan_map::heatmap::LandMask;
+use crate::taiwan_map::projection::Georeference;
+use crate::taiwan_map::raster::RasterCanvas;
+use crate::taiwan_map::{
+    COUNTIES_LOC, EPICENTER_ICON_SIZE, EPI_PIC_LOC, MAP_FONT_LOC, MAP_GEOREF_LOC, MAP_LOC,
+};
+use ab_glyph::{Font, FontArc};
+use image::{DynamicImage, GenericImageView, ImageError};
+use serde::Deserialize;
+use std::fmt;
+
+/// the base map and epicenter icon baked into the binary (only with the `embed-assets` feature)
+#[cfg(feature = "embed-assets")]
+const MAP_BYTES: &[u8] = include_bytes!("../../img/eq-map.png");
+#[cfg(feature = "embed-assets")]
+const MAP_GEOREF_STR: &str = include_str!("../../img/eq-map.json");
+#[cfg(feature = "embed-assets")]
+const COUNTIES_STR: &str = include_str!("../../img/counties.geojson");
+#[cfg(feature = "embed-assets")]
+const EPI_PIC_BYTES: &[u8] = include_bytes!("../../img/eq-epi.png");
+/// the map font baked into the binary (only with the `embed-font` feature, and the
+/// font has to be at `MAP_FONT_LOC` when building)
+#[cfg(feature = "embed-font")]
+const MAP_FONT_BYTES: &[u8] = include_bytes!("../../img/map-font.ttf");
+
+/// characters the map is always labeled with, which the font has to have
+/// (a font with only latin letters would leave them blank)
+const MAP_FONT_CHECK_TEXT: &str = "規模深度震度時間";
+
+/// errors that can happen when loading the map images
+#[derive(Debug)]
+pub enum MapAssetError {
+    /// the image couldn't be read or decoded
+    Decode(&'static str, ImageError),
+    /// the base map's sidecar file or the county file couldn't be read
+    #[cfg_attr(feature = "embed-assets", allow(dead_code))]
+    Read(&'static str, std::io::Error),
+    /// the base map's sidecar file isn't a valid `Georeference`
+    Georeference(&'static str, serde_json::Error),
+    /// the sidecar file was read, but its size or bounds can't be projected onto
+    BadGeoreference(&'static str, String),
+    /// the county file isn't valid GeoJSON
+    Counties(&'static str, serde_json::Error),
+    /// the font file isn't there
+    #[cfg_attr(feature = "embed-font", allow(dead_code))]
+    NoFont(&'static str),
+    /// the font file is there, but it isn't a font
+    Font(&'static str),
+    /// the font doesn't have the Chinese characters the map is labeled with
+    NoChinese(&'static str),
+    /// the image was decoded, but it's not the size the drawing code expects
+    WrongSize {
+        asset: &'static str,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+}
+
+impl fmt::Display for MapAssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapAssetError::Decode(asset, e) => write!(f, "couldn't load {}: {}", asset, e),
+            MapAssetError::Read(asset, e) => write!(f, "couldn't read {}: {}", asset, e),
+            MapAssetError::Georeference(asset, e) => {
+                write!(f, "{} isn't a valid georeference: {}", asset, e)
+            }
+            MapAssetError::BadGeoreference(asset, e) => {
+                write!(f, "{} has a georeference that can't be used: {}", asset, e)
+            }
+            MapAssetError::Counties(asset, e) => {
+                write!(f, "{} isn't valid county GeoJSON: {}", asset, e)
+            }
+            MapAssetError::NoFont(asset) => write!(
+                f,
+                "{} wasn't found. the map text needs a font with Chinese characters there \
+                 (like Noto Sans TC, see env.example)",
+                asset
+            ),
+            MapAssetError::Font(asset) => write!(f, "{} isn't a valid font", asset),
+            MapAssetError::NoChinese(asset) => write!(
+                f,
+                "{} doesn't have Chinese characters (use a font like Noto Sans TC)",
+                asset
+            ),
+            MapAssetError::WrongSize {
+                asset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} should be {}x{} but is {}x{}",
+                asset, expected.0, expected.1, found.0, found.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MapAssetError {}
+
+/// the base map's sidecar file
+/// includes:
+/// * georef - where the base map is in the world
+/// * legend_area - (optional) x, y, width and height of a legend that's part of the
+///   base map image. it gets painted over since the renderer draws its own legend
+#[derive(Deserialize)]
+struct Sidecar {
+    #[serde(flatten)]
+    georef: Georeference,
+    #[serde(default)]
+    legend_area: Option<[u32; 4]>,
+}
+
+/// the decoded images needed to draw a map, plus where the base map is in the world,
+/// the county boundaries (and the land they cover) and the font for the text
+pub struct MapAssets {
+    pub base_map: DynamicImage,
+    pub georef: Georeference,
+    pub epicenter_icon: DynamicImage,
+    pub counties: Vec<County>,
+    pub land: LandMask,
+    pub font: FontArc,
+}
+
+impl MapAssets {
+    /// decode both images and make sure they're the size the drawing code expects (the
+    /// base map has to match the size in its sidecar file, which has to have bounds that
+    /// can be projected onto), and load the font. the font has to be there and have
+    /// Chinese characters, so a map never goes out without its time, magnitude and depth
+    pub fn load() -> Result<Self, MapAssetError> {
+        let sidecar: Sidecar = serde_json::from_str(&load_text(MAP_GEOREF_LOC)?)
+            .map_err(|e| MapAssetError::Georeference(MAP_GEOREF_LOC, e))?;
+        let georef = sidecar.georef;
+        georef
+            .validate()
+            .map_err(|e| MapAssetError::BadGeoreference(MAP_GEOREF_LOC, e))?;
+
+        let mut base_map = load_image(MAP_LOC)?;
+        check_size(MAP_LOC, &base_map, (georef.width, georef.height))?;
+        if let Some(legend_area) = sidecar.legend_area {
+            base_map = erase_area(base_map, legend_area);
+        }
+
+        let epicenter_icon = load_image(EPI_PIC_LOC)?;
+        check_size(
+            EPI_PIC_LOC,
+            &epicenter_icon,
+            (EPICENTER_ICON_SIZE, EPICENTER_ICON_SIZE),
+        )?;
+
+        let counties = parse_counties(&load_text(COUNTIES_LOC)?)
+            .map_err(|e| MapAssetError::Counties(COUNTIES_LOC, e))?;
+        let land = LandMask::from_counties(&counties, &georef);
+
+        let font = load_font()?;
+        if MAP_FONT_CHECK_TEXT.chars().any(|c| font.glyph_id(c).0 == 0) {
+            return Err(MapAssetError::NoChinese(MAP_FONT_LOC));
+        }
+
+        Ok(Self {
+            base_map,
+            georef,
+            epicenter_icon,
+            counties,
+            land,
+            font,
+        })
+    }
+}
+
+/// decode the image that was compiled into the binary
+#[cfg(feature = "embed-assets")]
+fn load_image(asset: &'static str) -> Result<DynamicImage, MapAssetError> {
+    let bytes = match asset {
+        MAP_LOC => MAP_BYTES,
+        _ => EPI_PIC_BYTES,
+    };
+
+    image::load_from_memory(bytes).map_err(|e| MapAssetError::Decode(asset, e))
+}
+
+/// read and decode the image from the img folder
+#[cfg(not(feature = "embed-assets"))]
+fn load_image(asset: &'static str) -> Result<DynamicImage, MapAssetError> {
+    image::open(asset).map_err(|e| MapAssetError::Decode(asset, e))
+}
+
+/// the sidecar or county file that was compiled into the binary
+#[cfg(feature = "embed-assets")]
+fn load_text(asset: &'static str) -> Result<String, MapAssetError> {
+    let text = match asset {
+        COUNTIES_LOC => COUNTIES_STR,
+        _ => MAP_GEOREF_STR,
+    };
+
+    Ok(text.to_string())
+}
+
+/// read the sidecar or county file from the img folder
+#[cfg(not(feature = "embed-assets"))]
+fn load_text(asset: &'static str) -> Result<String, MapAssetError> {
+    std::fs::read_to_string(asset).map_err(|e| MapAssetError::Read(asset, e))
+}
+
+/// the font that was compiled into the binary
+#[cfg(feature = "embed-font")]
+fn load_font() -> Result<FontArc, MapAssetError> {
+    FontArc::try_from_slice(MAP_FONT_BYTES).map_err(|_| MapAssetError::Font(MAP_FONT_LOC))
+}
+
+/// read the font from the img folder
+#[cfg(not(feature = "embed-font"))]
+fn load_font() -> Result<FontArc, MapAssetError> {
+    let bytes = std::fs::read(MAP_FONT_LOC).map_err(|_| MapAssetError::NoFont(MAP_FONT_LOC))?;
+
+    FontArc::try_from_vec(bytes).map_err(|_| MapAssetError::Font(MAP_FONT_LOC))
+}
+
+/// paint over part of the base map with the color of the pixel just outside its top
+/// left corner (the legend is in the sea, so this makes it more sea)
+fn erase_area(base_map: DynamicImage, [x, y, width, height]: [u32; 4]) -> DynamicImage {
+    let color = base_map.get_pixel(x.saturating_sub(1), y.saturating_sub(1));
+    let mut canvas = RasterCanvas::new(base_map);
+    canvas.fill_rect(x as f64, y as f64, width as f64, height as f64, color);
+    canvas.into_image()
+}
+
+fn check_size(
+    asset: &'static str,
+    image: &DynamicImage,
+    expected: (u32, u32),
+) -> Result<(), MapAssetError> {
+    let found = image.dimensions();
+
+    if found == expected {
+        Ok(())
+    } else {
+        Err(MapAssetError::WrongSize {
+            asset,
+            expected,
+            found,
+        })
+    }
+}
@@ -0,0 +1,29 @@
+use ab_glyph::FontArc;
+use image::{DynamicImage, Rgba};
+
+/// something a map can be drawn on
+///
+/// the map layout is worked out once and drawn with these, so every canvas ends up
+/// with everything in the same place. positions and sizes are in image pixels, with
+/// 0, 0 at the top left corner. anything off the canvas is skipped
+pub trait Canvas {
+    /// width and height in pixels
+    fn dimensions(&self) -> (u32, u32);
+
+    /// fill a rectangle with `color`
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64, color: Rgba<u8>);
+
+    /// fill the area inside `rings` (even-odd, so a ring inside another is a hole),
+    /// mixing `opacity` (0 to 1) of `color` with what's underneath
+    fn fill_polygon(&mut self, rings: &[Vec<(f64, f64)>], color: Rgba<u8>, opacity: f32);
+
+    /// draw a 1 pixel line around each of the `rings`
+    fn stroke_polygon(&mut self, rings: &[Vec<(f64, f64)>], color: Rgba<u8>);
+
+    /// draw `image` at its own size with its top left corner at x, y, using its alpha
+    fn draw_image(&mut self, image: &DynamicImage, x: f64, y: f64);
+
+    /// draw `text` with its top left corner at x, y
+    /// (the font is needed for the layout even if the canvas doesn't draw the glyphs itself)
+    fn draw_text(&mut self, font: &FontArc, text: &str, x: f64, y: f64, size: f32, color: Rgba<u8>);
+}
@@ -1,29 +1,84 @@
-use crate::taiwan_map::{
-    BASE_LAT, BASE_LONG, DEGREE_TO_LAT, DEGREE_TO_LONG, PIC_HEIGHT, PIC_WIDTH,
-};
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::geo::GeoPoint;
+use crate::taiwan_map::view::View;
 
 /// for each earthquake, the CWB will have stations with the intensity
 /// as well as the the real life coordinates of each station
+///
+/// x and y are signed so markers can be worked out around a point right at the edge
+/// (half of the marker is off the image then, and gets clipped when it's drawn)
 pub struct Coords {
-    pub x: u32,
-    pub y: u32,
-    pub intensity: u8,
+    pub x: i32,
+    pub y: i32,
+    /// None if the intensity couldn't be read (or this isn't a station)
+    pub intensity: Option<SeismicIntensity>,
 }
 
 impl Coords {
-    pub fn from_coordinates(long: f64, lat: f64, intensity: u8) -> Option<Self> {
-        let y: i16 = PIC_HEIGHT as i16 - ((long - BASE_LONG) * DEGREE_TO_LONG) as i16;
-        let x: i16 = ((lat - BASE_LAT) * DEGREE_TO_LAT) as i16;
+    /// convert a real life location to a pixel in the rendered image (see `View`)
+    /// returns None if it's off the map
+    pub fn from_geo_point(view: &View, point: GeoPoint, intensity: Option<SeismicIntensity>) -> Option<Self> {
+        let (x, y) = view.project(point);
+        // floor, not `as`, so a point just left of or above the image isn't rounded onto it
+        let (x, y) = (x.floor() as i32, y.floor() as i32);
+        let (width, height) = view.output_size();
 
         // check if the x or y are too low or high, and return None if so
-        if x < 0 || x > PIC_WIDTH as i16 || y < 0 || y > PIC_HEIGHT as i16 {
+        // (pixels go from 0 to width - 1, so width itself is off the image)
+        if x < 0 || x >= width as i32 || y < 0 || y >= height as i32 {
             return None;
         }
 
-        Some(Self {
-            x: x as u32,
-            y: y as u32,
-            intensity,
-        })
+        Some(Self { x, y, intensity })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taiwan_map::projection::Georeference;
+
+    fn full_view() -> View {
+        let georef: Georeference = serde_json::from_str(include_str!("../../img/eq-map.json")).unwrap();
+        View::full(&georef)
+    }
+
+    /// the pixel a location lands on, if it's on the map
+    fn pixel(lat: f64, lon: f64) -> Option<(i32, i32)> {
+        Coords::from_geo_point(&full_view(), GeoPoint::new(lat, lon), None).map(|coords| (coords.x, coords.y))
+    }
+
+    #[test]
+    fn stations_land_on_their_pixels() {
+        // CWB's Taipei and Hualien stations
+        assert_eq!(pixel(25.0377, 121.5149), Some((592, 121)));
+        assert_eq!(pixel(23.9751, 121.6133), Some((618, 418)));
+    }
+
+    #[test]
+    fn top_left_corner_is_on_the_map() {
+        assert_eq!(pixel(25.4732, 119.2021), Some((0, 0)));
+    }
+
+    #[test]
+    fn right_and_bottom_edges_are_off_the_map() {
+        // the east edge is x == width and the south edge is y == height, one past the last pixel
+        assert_eq!(pixel(24.0, 122.90777), None);
+        assert_eq!(pixel(21.8898, 121.0), None);
+        assert_eq!(pixel(21.8898, 122.90777), None);
+
+        // just inside them is the last pixel
+        assert_eq!(pixel(21.8899, 122.9077), Some((949, 999)));
+    }
+
+    #[test]
+    fn points_outside_the_bounds_are_off_the_map() {
+        // west of the map, north of it, and just left of and above the first pixel
+        assert_eq!(pixel(24.0, 118.0), None);
+        assert_eq!(pixel(26.0, 121.0), None);
+        assert_eq!(pixel(24.0, 119.2020), None);
+        assert_eq!(pixel(25.4733, 121.0), None);
+        // the other side of the world
+        assert_eq!(pixel(-24.0, -121.0), None);
     }
 }
@@ -0,0 +1,304 @@
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::cwb_api::structs::Earthquake;
+use crate::geo::GeoPoint;
+use crate::taiwan_map::canvas::Canvas;
+use crate::taiwan_map::options::Palette;
+use crate::taiwan_map::view::View;
+use image::Rgba;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// how strongly the county colors cover the base map (0 to 1)
+const COUNTY_OPACITY: f32 = 0.8;
+const COUNTY_BORDER_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// one county's boundary
+/// includes:
+/// * name - the county's name, the same as CWB's CountyName (ex: 花蓮縣)
+/// * rings - every outline and hole, in any order (they're filled even-odd)
+pub struct County {
+    pub name: String,
+    pub rings: Vec<Vec<GeoPoint>>,
+}
+
+/// just the parts of a GeoJSON FeatureCollection the county file uses
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    properties: FeatureProperties,
+    geometry: Geometry,
+}
+
+#[derive(Deserialize)]
+struct FeatureProperties {
+    name: String,
+}
+
+/// GeoJSON positions are [lon, lat]
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "coordinates")]
+enum Geometry {
+    Polygon(Vec<Vec<[f64; 2]>>),
+    MultiPolygon(Vec<Vec<Vec<[f64; 2]>>>),
+}
+
+/// read the county boundaries from a GeoJSON FeatureCollection, where each feature
+/// has a `name` property and a Polygon or MultiPolygon geometry
+pub fn parse_counties(geojson: &str) -> Result<Vec<County>, serde_json::Error> {
+    let collection: FeatureCollection = serde_json::from_str(geojson)?;
+
+    let to_ring = |ring: Vec<[f64; 2]>| {
+        ring.into_iter()
+            .map(|[lon, lat]| GeoPoint::new(lat, lon))
+            .collect::<Vec<GeoPoint>>()
+    };
+
+    Ok(collection
+        .features
+        .into_iter()
+        .map(|feature| County {
+            name: feature.properties.name,
+            rings: match feature.geometry {
+                Geometry::Polygon(rings) => rings.into_iter().map(to_ring).collect(),
+                Geometry::MultiPolygon(polygons) => {
+                    polygons.into_iter().flatten().map(to_ring).collect()
+                }
+            },
+        })
+        .collect())
+}
+
+/// the strongest intensity in each county, by name
+///
+/// this uses each area's AreaIntensity, or its strongest readable station if that can't
+/// be read. an area can list more than one county, and a county can be in more than
+/// one area, so the strongest one wins
+pub fn county_intensities(eq: &Earthquake) -> HashMap<String, SeismicIntensity> {
+    let mut intensities: HashMap<String, SeismicIntensity> = HashMap::new();
+
+    for area in &eq.intensity.shaking_area {
+        let intensity = match area.intensity() {
+            Ok(intensity) => Some(intensity),
+            Err(_) => area
+                .eq_station
+                .iter()
+                .filter_map(|station| station.intensity().ok())
+                .max(),
+        };
+        let intensity = match intensity {
+            Some(intensity) => intensity,
+            None => continue,
+        };
+
+        for county in area.counties() {
+            let strongest = intensities.entry(county).or_insert(intensity);
+            *strongest = (*strongest).max(intensity);
+        }
+    }
+
+    intensities
+}
+
+/// fill each county that's in `intensities` with its intensity's color, then outline
+/// every county so the borders stay sharp
+pub fn draw_counties(
+    canvas: &mut impl Canvas,
+    view: &View,
+    counties: &[County],
+    intensities: &HashMap<String, SeismicIntensity>,
+    palette: Palette,
+) {
+    for county in counties {
+        if let Some(intensity) = intensities.get(&county.name) {
+            let rings = project_rings(view, county);
+            canvas.fill_polygon(&rings, palette.color(*intensity), COUNTY_OPACITY);
+        }
+    }
+
+    for county in counties {
+        canvas.stroke_polygon(&project_rings(view, county), COUNTY_BORDER_COLOR);
+    }
+}
+
+/// the county's rings in image pixels
+fn project_rings(view: &View, county: &County) -> Vec<Vec<(f64, f64)>> {
+    county
+        .rings
+        .iter()
+        .map(|ring| ring.iter().map(|point| view.project(*point)).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taiwan_map::projection::{BoundingBox, Georeference, Projection};
+    use crate::taiwan_map::raster::RasterCanvas;
+    use image::{DynamicImage, GenericImageView, RgbaImage};
+
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /// a 100x100 map of 120°E to 121°E and 23°N to 24°N (100 pixels a degree)
+    fn small_map() -> Georeference {
+        Georeference {
+            width: 100,
+            height: 100,
+            projection: Projection::Equirectangular,
+            bounds: BoundingBox { west: 120.0, east: 121.0, south: 23.0, north: 24.0 },
+        }
+    }
+
+    /// a square county with its edges through the middle of pixels 20 and 80
+    fn square_county(name: &str) -> County {
+        let (west, east, south, north) = (120.205, 120.805, 23.195, 23.795);
+        County {
+            name: name.to_string(),
+            rings: vec![vec![
+                GeoPoint::new(north, west),
+                GeoPoint::new(north, east),
+                GeoPoint::new(south, east),
+                GeoPoint::new(south, west),
+            ]],
+        }
+    }
+
+    /// draw `county` on a white map, filled in if it has an intensity
+    fn draw(county: &County, intensities: &HashMap<String, SeismicIntensity>) -> DynamicImage {
+        let georef = small_map();
+        let mut canvas = RasterCanvas::new(DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 100, WHITE)));
+        draw_counties(
+            &mut canvas,
+            &View::full(&georef),
+            std::slice::from_ref(county),
+            intensities,
+            Palette::Cwb,
+        );
+        canvas.into_image()
+    }
+
+    #[test]
+    fn counties_are_read_from_polygons_and_multipolygons() {
+        let geojson = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "properties": { "name": "花蓮縣" },
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [
+                            [[121.0, 23.0], [122.0, 23.0], [122.0, 24.0], [121.0, 23.0]],
+                            [[121.5, 23.4], [121.8, 23.4], [121.8, 23.7], [121.5, 23.4]]
+                        ]
+                    }
+                },
+                {
+                    "type": "Feature",
+                    "properties": { "name": "澎湖縣" },
+                    "geometry": {
+                        "type": "MultiPolygon",
+                        "coordinates": [
+                            [[[119.5, 23.5], [119.7, 23.5], [119.7, 23.7], [119.5, 23.5]]],
+                            [[[119.4, 23.2], [119.5, 23.2], [119.5, 23.3], [119.4, 23.2]]]
+                        ]
+                    }
+                }
+            ]
+        }"#;
+
+        let counties = parse_counties(geojson).unwrap();
+        assert_eq!(counties.len(), 2);
+
+        // the outline and its hole
+        assert_eq!(counties[0].name, "花蓮縣");
+        assert_eq!(counties[0].rings.len(), 2);
+        // positions are [lon, lat]
+        let first = counties[0].rings[0][0];
+        assert_eq!((first.lat, first.lon), (23.0, 121.0));
+
+        // each island is its own ring
+        assert_eq!(counties[1].name, "澎湖縣");
+        assert_eq!(counties[1].rings.len(), 2);
+        let island = counties[1].rings[1][0];
+        assert_eq!((island.lat, island.lon), (23.2, 119.4));
+    }
+
+    #[test]
+    fn bad_county_files_are_an_error() {
+        assert!(parse_counties(r#"{ "features": [{ "properties": {}, "geometry": null }] }"#).is_err());
+    }
+
+    #[test]
+    fn county_is_filled_inside_and_outlined_on_its_edge() {
+        let county = square_county("花蓮縣");
+        let intensities = HashMap::from([("花蓮縣".to_string(), SeismicIntensity::FiveWeak)]);
+        let image = draw(&county, &intensities);
+
+        // inside it's mostly the intensity's color over the white map
+        let inside = image.get_pixel(50, 50);
+        let color = Palette::Cwb.color(SeismicIntensity::FiveWeak);
+        for channel in 0..3 {
+            let expected = color[channel] as f32 * COUNTY_OPACITY + 255.0 * (1.0 - COUNTY_OPACITY);
+            assert!((inside[channel] as f32 - expected).abs() <= 1.0, "{:?}", inside);
+        }
+
+        // outside is left alone
+        assert_eq!(image.get_pixel(10, 10), WHITE);
+        assert_eq!(image.get_pixel(90, 50), WHITE);
+
+        // and each edge is the border color
+        for (x, y) in [(20, 50), (80, 50), (50, 20), (50, 80)] {
+            assert_eq!(image.get_pixel(x, y), COUNTY_BORDER_COLOR, "at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn counties_without_an_intensity_are_only_outlined() {
+        let image = draw(&square_county("臺東縣"), &HashMap::new());
+
+        assert_eq!(image.get_pixel(50, 50), WHITE);
+        assert_eq!(image.get_pixel(20, 50), COUNTY_BORDER_COLOR);
+    }
+
+    #[test]
+    fn strongest_area_wins_for_a_county() {
+        let eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2)
+            .with_area("花蓮縣", "4級", &["4級"])
+            .with_area("花蓮縣", "6強", &["6強", "5弱"])
+            .with_area("花蓮縣", "5弱", &["5弱"]);
+
+        let intensities = county_intensities(&eq);
+        assert_eq!(intensities.len(), 1);
+        assert_eq!(intensities["花蓮縣"], SeismicIntensity::SixStrong);
+    }
+
+    #[test]
+    fn unreadable_area_intensity_uses_its_strongest_station() {
+        let eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2)
+            .with_area("宜蘭縣", "", &["3級", "x級", "5強", "4級"])
+            .with_area("臺東縣", "", &["?"]);
+
+        let intensities = county_intensities(&eq);
+        assert_eq!(intensities["宜蘭縣"], SeismicIntensity::FiveStrong);
+        // nothing readable at all, so it's left out
+        assert!(!intensities.contains_key("臺東縣"));
+    }
+
+    #[test]
+    fn areas_with_several_counties_count_for_each_of_them() {
+        let eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2)
+            .with_area("花蓮縣、宜蘭縣", "5弱", &["5弱"])
+            .with_area("台北市", "4級", &["4級"]);
+
+        let intensities = county_intensities(&eq);
+        assert_eq!(intensities["花蓮縣"], SeismicIntensity::FiveWeak);
+        assert_eq!(intensities["宜蘭縣"], SeismicIntensity::FiveWeak);
+        // 台 is written 臺, the way the county file has it
+        assert_eq!(intensities["臺北市"], SeismicIntensity::Four);
+        assert!(!intensities.contains_key("台北市"));
+    }
+}
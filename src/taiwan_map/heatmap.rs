@@ -0,0 +1,247 @@
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::geo::GeoPoint;
+use crate::taiwan_map::counties::County;
+use crate::taiwan_map::projection::Georeference;
+use crate::taiwan_map::raster::polygon_coverage;
+use crate::taiwan_map::view::View;
+use crate::taiwan_map::blend;
+use crate::taiwan_map::options::Palette;
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+
+/// the field is worked out every this many pixels, then filled in between
+const GRID_STEP: u32 = 4;
+/// inverse distance weighting power (higher means each station's area is flatter)
+const IDW_POWER: f64 = 2.0;
+/// don't guess the shaking further than this from the nearest station
+const MAX_STATION_DISTANCE_KM: f64 = 25.0;
+/// how strongly the shading covers the base map (0 to 1)
+const HEATMAP_OPACITY: f32 = 0.45;
+const CONTOUR_COLOR: Rgba<u8> = Rgba([40, 40, 40, 255]);
+const CONTOUR_OPACITY: f32 = 0.8;
+/// a base map pixel is land if the counties cover at least this much of it
+const MIN_LAND_COVERAGE: f32 = 0.5;
+
+/// which pixels of the base map are land (inside a county), worked out once when the
+/// map assets are loaded
+pub struct LandMask {
+    width: u32,
+    height: u32,
+    land: Vec<bool>,
+}
+
+impl LandMask {
+    /// fill in the counties' outlines on a mask the size of the base map
+    pub fn from_counties(counties: &[County], georef: &Georeference) -> Self {
+        let polygons: Vec<Vec<Vec<(f64, f64)>>> = counties
+            .iter()
+            .map(|county| {
+                county
+                    .rings
+                    .iter()
+                    .map(|ring| ring.iter().map(|point| georef.project(*point)).collect())
+                    .collect()
+            })
+            .collect();
+        let land = polygon_coverage(&polygons, (georef.width, georef.height))
+            .into_iter()
+            .map(|amount| amount >= MIN_LAND_COVERAGE)
+            .collect();
+
+        Self {
+            width: georef.width,
+            height: georef.height,
+            land,
+        }
+    }
+
+    /// true if the base map pixel at (`x`, `y`) is land (nothing off the base map is)
+    fn is_land(&self, (x, y): (f64, f64)) -> bool {
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return false;
+        }
+
+        self.land[(y as u32 * self.width + x as u32) as usize]
+    }
+}
+
+/// shade the land by intensity, guessing between stations with inverse distance
+/// weighting, and draw lines where the intensity level changes
+///
+/// only the `land` is shaded (the sea, and anywhere off the counties, is left alone).
+/// this draws straight onto the base map, so do it before the markers
+pub fn draw_heatmap(
+    map_image: &mut DynamicImage,
+    view: &View,
+    stations: &[(GeoPoint, SeismicIntensity)],
+    land: &LandMask,
+    palette: Palette,
+) {
+    if stations.is_empty() {
+        return;
+    }
+
+    let (width, height) = map_image.dimensions();
+
+    // work out the field on a coarse grid, it's too slow to do for every pixel
+    let grid_width = width / GRID_STEP + 2;
+    let grid_height = height / GRID_STEP + 2;
+    let mut grid = Vec::with_capacity((grid_width * grid_height) as usize);
+    for gy in 0..grid_height {
+        for gx in 0..grid_width {
+            let point = view.unproject((gx * GRID_STEP) as f64, (gy * GRID_STEP) as f64);
+            grid.push(interpolate(point, stations));
+        }
+    }
+    let grid_at = |gx: u32, gy: u32| grid[(gy * grid_width + gx) as usize];
+
+    // fill in every pixel from the grid around it, and round it to a level
+    // (None if it's sea, too far from a station, or intensity 0)
+    let mut levels: Vec<Option<usize>> = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            if !land.is_land(view.to_base_map(x as f64 + 0.5, y as f64 + 0.5)) {
+                levels.push(None);
+                continue;
+            }
+
+            // bilinear interpolation between the four grid points around the pixel
+            let (gx, gy) = (x / GRID_STEP, y / GRID_STEP);
+            let fx = (x % GRID_STEP) as f64 / GRID_STEP as f64;
+            let fy = (y % GRID_STEP) as f64 / GRID_STEP as f64;
+            let mix = |pick: fn(&Sample) -> f64| {
+                let top = pick(&grid_at(gx, gy)) * (1.0 - fx) + pick(&grid_at(gx + 1, gy)) * fx;
+                let bottom =
+                    pick(&grid_at(gx, gy + 1)) * (1.0 - fx) + pick(&grid_at(gx + 1, gy + 1)) * fx;
+                top * (1.0 - fy) + bottom * fy
+            };
+
+            // too far from any station to guess
+            let value = if mix(|sample| sample.nearest_km) > MAX_STATION_DISTANCE_KM {
+                None
+            } else {
+                Some(mix(|sample| sample.value))
+            };
+
+            let level = value
+                .map(|v| (v.round().max(0.0) as usize).min(SeismicIntensity::ALL.len() - 1))
+                .filter(|level| *level > 0);
+            levels.push(level);
+        }
+    }
+    let level_at = |x: u32, y: u32| levels[(y * width + x) as usize];
+
+    for y in 0..height {
+        for x in 0..width {
+            let level = match level_at(x, y) {
+                Some(level) => level,
+                None => continue,
+            };
+
+            // a contour goes wherever the level changes to the right or below
+            let right = if x + 1 < width { level_at(x + 1, y) } else { Some(level) };
+            let below = if y + 1 < height { level_at(x, y + 1) } else { Some(level) };
+            let on_contour = [right, below]
+                .iter()
+                .any(|neighbor| matches!(neighbor, Some(n) if *n != level));
+
+            let (color, opacity) = if on_contour {
+                (CONTOUR_COLOR, CONTOUR_OPACITY)
+            } else {
+                (palette.color(SeismicIntensity::ALL[level]), HEATMAP_OPACITY)
+            };
+            let blended = blend(map_image.get_pixel(x, y), color, opacity);
+            map_image.put_pixel(x, y, blended);
+        }
+    }
+}
+
+/// the field at one grid point
+/// includes:
+/// * value - the intensity as a level number (0 to 9), can be in between levels
+/// * nearest_km - how far away the closest station is
+#[derive(Clone, Copy)]
+struct Sample {
+    value: f64,
+    nearest_km: f64,
+}
+
+/// inverse distance weighted intensity at `point`
+fn interpolate(point: GeoPoint, stations: &[(GeoPoint, SeismicIntensity)]) -> Sample {
+    let mut weighted_sum = 0.0;
+    let mut total_weight = 0.0;
+    let mut nearest = f64::MAX;
+
+    for (station, intensity) in stations {
+        let distance = point.distance_km(*station);
+        let value = *intensity as usize as f64;
+
+        // right on top of a station
+        if distance < 0.01 {
+            return Sample {
+                value,
+                nearest_km: 0.0,
+            };
+        }
+
+        let weight = 1.0 / distance.powf(IDW_POWER);
+        weighted_sum += weight * value;
+        total_weight += weight;
+        nearest = nearest.min(distance);
+    }
+
+    Sample {
+        value: weighted_sum / total_weight,
+        nearest_km: nearest,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taiwan_map::counties::parse_counties;
+
+    fn georef() -> Georeference {
+        serde_json::from_str(include_str!("../../img/eq-map.json")).unwrap()
+    }
+
+    fn land_mask() -> LandMask {
+        let counties = parse_counties(include_str!("../../img/counties.geojson")).unwrap();
+        LandMask::from_counties(&counties, &georef())
+    }
+
+    #[test]
+    fn counties_are_land_and_the_sea_is_not() {
+        let land = land_mask();
+        let georef = georef();
+
+        // Taipei and the middle of the island
+        assert!(land.is_land(georef.project(GeoPoint::new(25.0377, 121.5149))));
+        assert!(land.is_land(georef.project(GeoPoint::new(23.7, 121.0))));
+        // the Taiwan Strait and the Pacific off Hualien
+        assert!(!land.is_land(georef.project(GeoPoint::new(24.0, 119.8))));
+        assert!(!land.is_land(georef.project(GeoPoint::new(23.9, 122.3))));
+        // off the base map
+        assert!(!land.is_land((-1.0, 10.0)));
+        assert!(!land.is_land((10.0, 1000.0)));
+    }
+
+    #[test]
+    fn only_land_is_shaded() {
+        let georef = georef();
+        let view = View::full(&georef);
+        let white = Rgba([255, 255, 255, 255]);
+        let mut map_image = DynamicImage::new_rgba8(georef.width, georef.height);
+        for (x, y) in (0..georef.width).flat_map(|x| (0..georef.height).map(move |y| (x, y))) {
+            map_image.put_pixel(x, y, white);
+        }
+
+        // a strong station on the Hualien coast
+        let hualien = GeoPoint::new(23.9751, 121.6133);
+        draw_heatmap(&mut map_image, &view, &[(hualien, SeismicIntensity::FiveWeak)], &land_mask(), Palette::Cwb);
+
+        let (x, y) = view.project(GeoPoint::new(23.98, 121.55));
+        assert_ne!(map_image.get_pixel(x as u32, y as u32), white);
+        let (x, y) = view.project(GeoPoint::new(23.98, 121.75));
+        assert_eq!(map_image.get_pixel(x as u32, y as u32), white);
+    }
+}
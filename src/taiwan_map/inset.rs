@@ -0,0 +1,104 @@
+use crate::taiwan_map::canvas::Canvas;
+use crate::taiwan_map::view::View;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, Rgba};
+
+/// width of the whole-Taiwan overview drawn in the corner of zoomed maps
+const INSET_WIDTH: u32 = 190;
+/// gap between the inset and the edge of the image
+const INSET_MARGIN: u32 = 10;
+const INSET_BORDER: u32 = 2;
+/// how far in from the image edge the tip of the off-map arrow is
+const ARROW_MARGIN: f64 = 20.0;
+const ARROW_LENGTH: f64 = 80.0;
+const ARROW_SHAFT_WIDTH: f64 = 10.0;
+const ARROW_HEAD_LENGTH: f64 = 30.0;
+const ARROW_HEAD_WIDTH: f64 = 36.0;
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const RED: Rgba<u8> = Rgba([220, 0, 0, 255]);
+
+/// draw a small copy of the whole base map in the top left corner, with a red
+/// rectangle showing which part of it the zoomed map is showing
+pub fn draw_inset(canvas: &mut impl Canvas, base_map: &DynamicImage, view: &View) {
+    let (base_width, base_height) = base_map.dimensions();
+    let inset_height = INSET_WIDTH * base_height / base_width;
+    let inset = imageops::resize(base_map, INSET_WIDTH, inset_height, FilterType::Triangle);
+
+    // black frame first, then the overview on top of it
+    canvas.fill_rect(
+        (INSET_MARGIN - INSET_BORDER) as f64,
+        (INSET_MARGIN - INSET_BORDER) as f64,
+        (INSET_WIDTH + INSET_BORDER * 2) as f64,
+        (inset_height + INSET_BORDER * 2) as f64,
+        BLACK,
+    );
+    canvas.draw_image(
+        &DynamicImage::ImageRgba8(inset),
+        INSET_MARGIN as f64,
+        INSET_MARGIN as f64,
+    );
+
+    // outline the zoom window
+    let inset_scale = INSET_WIDTH as f64 / base_width as f64;
+    let (x, y, width, height) = view.window();
+    let x = (INSET_MARGIN + (x * inset_scale) as u32) as f64;
+    let y = (INSET_MARGIN + (y * inset_scale) as u32) as f64;
+    let width = ((width * inset_scale) as u32).max(INSET_BORDER * 2) as f64;
+    let height = ((height * inset_scale) as u32).max(INSET_BORDER * 2) as f64;
+    let border = INSET_BORDER as f64;
+
+    canvas.fill_rect(x, y, width, border, RED);
+    canvas.fill_rect(x, y + height - border, width, border, RED);
+    canvas.fill_rect(x, y, border, height, RED);
+    canvas.fill_rect(x + width - border, y, border, height, RED);
+}
+
+/// the epicenter isn't in the picture, so draw an arrow at the edge of the image
+/// pointing towards `target` (where the epicenter would be in image pixels)
+pub fn draw_offmap_arrow(canvas: &mut impl Canvas, target: (f64, f64)) {
+    let (width, height) = canvas.dimensions();
+    let center = (width as f64 / 2.0, height as f64 / 2.0);
+    let (dx, dy) = (target.0 - center.0, target.1 - center.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length == 0.0 {
+        return;
+    }
+    let unit = (dx / length, dy / length);
+    let normal = (-unit.1, unit.0);
+
+    // walk from the center towards the target until just inside the image edge
+    let to_edge_x = (center.0 - ARROW_MARGIN) / unit.0.abs();
+    let to_edge_y = (center.1 - ARROW_MARGIN) / unit.1.abs();
+    let to_edge = to_edge_x.min(to_edge_y);
+
+    let tip = (center.0 + unit.0 * to_edge, center.1 + unit.1 * to_edge);
+    let head_base = (
+        tip.0 - unit.0 * ARROW_HEAD_LENGTH,
+        tip.1 - unit.1 * ARROW_HEAD_LENGTH,
+    );
+    let tail = (tip.0 - unit.0 * ARROW_LENGTH, tip.1 - unit.1 * ARROW_LENGTH);
+
+    let offset = |point: (f64, f64), distance: f64| {
+        (point.0 + normal.0 * distance, point.1 + normal.1 * distance)
+    };
+
+    // shaft
+    let half_shaft = ARROW_SHAFT_WIDTH / 2.0;
+    let shaft = vec![
+        offset(tail, half_shaft),
+        offset(head_base, half_shaft),
+        offset(head_base, -half_shaft),
+        offset(tail, -half_shaft),
+    ];
+    canvas.fill_polygon(&[shaft], RED, 1.0);
+
+    // head
+    let half_head = ARROW_HEAD_WIDTH / 2.0;
+    let head = vec![
+        tip,
+        offset(head_base, half_head),
+        offset(head_base, -half_head),
+    ];
+    canvas.fill_polygon(&[head], RED, 1.0);
+}
@@ -0,0 +1,254 @@
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::cwb_api::structs::Earthquake;
+use crate::taiwan_map::canvas::Canvas;
+use crate::taiwan_map::markers::{draw_symbol, SYMBOL_RADIUS};
+use crate::taiwan_map::options::{MarkerStyle, Palette};
+use crate::taiwan_map::summary::{draw_circle, magnitude_radius, time_color};
+use crate::taiwan_map::text::text_width;
+use ab_glyph::FontArc;
+use image::Rgba;
+
+/// gap between the panels and the edge of the image
+const PANEL_MARGIN: u32 = 10;
+const PANEL_PADDING: u32 = 12;
+const PANEL_BORDER: u32 = 2;
+
+const TIME_TEXT_SIZE: f32 = 30.0;
+const DETAIL_TEXT_SIZE: f32 = 24.0;
+const LOCATION_TEXT_SIZE: f32 = 20.0;
+const LINE_GAP: u32 = 6;
+
+const LEGEND_TITLE: &str = "震度 Intensity";
+const LEGEND_TITLE_SIZE: f32 = 26.0;
+const LEGEND_LABEL_SIZE: f32 = 18.0;
+const LEGEND_SWATCH_WIDTH: u32 = 36;
+const LEGEND_SWATCH_HEIGHT: u32 = 18;
+/// the intensities shown in the color key
+const LEGEND_LEVELS: [SeismicIntensity; 10] = SeismicIntensity::ALL;
+
+const TIME_KEY_TITLE: &str = "時間 Time";
+const TIME_KEY_WIDTH: u32 = 360;
+/// how many steps the time color bar is drawn in
+const TIME_KEY_STEPS: u32 = 60;
+/// the magnitudes shown next to the time colors
+const MAGNITUDE_KEY_LEVELS: [f64; 3] = [3.0, 5.0, 7.0];
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// draw the origin time, magnitude, depth and location in the top right corner
+pub fn draw_title(canvas: &mut impl Canvas, font: &FontArc, eq: &Earthquake) {
+    let info = &eq.earthquake_info;
+    let lines = [
+        (info.origin_time.clone(), TIME_TEXT_SIZE),
+        (
+            format!(
+                "規模 M{:.1}  深度 {:.1} km",
+                info.earthquake_magnitude.magnitude_value, info.focal_depth
+            ),
+            DETAIL_TEXT_SIZE,
+        ),
+        (info.epicenter.location.clone(), LOCATION_TEXT_SIZE),
+    ];
+
+    draw_title_panel(canvas, font, &lines);
+}
+
+/// draw the intensity color key in the bottom right corner, with a row of the station
+/// symbols under the colors if they're being used
+pub fn draw_legend(canvas: &mut impl Canvas, font: &FontArc, marker_style: MarkerStyle, palette: Palette) {
+    let swatches_width = LEGEND_SWATCH_WIDTH * LEGEND_LEVELS.len() as u32;
+    let title_height = LEGEND_TITLE_SIZE as u32 + LINE_GAP;
+    let symbols_height = match marker_style {
+        MarkerStyle::Square => 0,
+        MarkerStyle::Symbol => LINE_GAP + (SYMBOL_RADIUS * 2.0) as u32,
+    };
+    let labels_height = LINE_GAP + LEGEND_LABEL_SIZE as u32;
+
+    let (pic_width, pic_height) = canvas.dimensions();
+    let panel_width = swatches_width + PANEL_PADDING * 2;
+    let panel_height =
+        title_height + LEGEND_SWATCH_HEIGHT + symbols_height + labels_height + PANEL_PADDING * 2;
+    let panel_x = pic_width.saturating_sub(panel_width + PANEL_MARGIN);
+    let panel_y = pic_height.saturating_sub(panel_height + PANEL_MARGIN);
+    draw_panel(canvas, panel_x, panel_y, panel_width, panel_height);
+
+    let left = panel_x + PANEL_PADDING;
+    let mut y = panel_y + PANEL_PADDING;
+
+    let title_x = left as f64 + (swatches_width as f64 - text_width(font, LEGEND_TITLE, LEGEND_TITLE_SIZE)) / 2.0;
+    canvas.draw_text(font, LEGEND_TITLE, title_x, y as f64, LEGEND_TITLE_SIZE, BLACK);
+    y += title_height;
+
+    // outline the colors so the white 0 level still shows up on the white panel
+    canvas.fill_rect(
+        (left - 1) as f64,
+        (y - 1) as f64,
+        (swatches_width + 2) as f64,
+        (LEGEND_SWATCH_HEIGHT + 2) as f64,
+        BLACK,
+    );
+
+    for (i, level) in LEGEND_LEVELS.iter().enumerate() {
+        let swatch_x = left + LEGEND_SWATCH_WIDTH * i as u32;
+        let color = palette.color(*level);
+        canvas.fill_rect(
+            swatch_x as f64,
+            y as f64,
+            LEGEND_SWATCH_WIDTH as f64,
+            LEGEND_SWATCH_HEIGHT as f64,
+            color,
+        );
+
+        if marker_style == MarkerStyle::Symbol {
+            let center = (
+                swatch_x as f64 + LEGEND_SWATCH_WIDTH as f64 / 2.0,
+                (y + LEGEND_SWATCH_HEIGHT + LINE_GAP) as f64 + SYMBOL_RADIUS,
+            );
+            draw_symbol(canvas, font, center, Some(*level), false, palette);
+        }
+
+        let label = level.to_string();
+        let label_x =
+            swatch_x as f64 + (LEGEND_SWATCH_WIDTH as f64 - text_width(font, &label, LEGEND_LABEL_SIZE)) / 2.0;
+        let label_y = (y + LEGEND_SWATCH_HEIGHT + symbols_height + LINE_GAP) as f64;
+        canvas.draw_text(font, &label, label_x, label_y, LEGEND_LABEL_SIZE, BLACK);
+    }
+}
+
+/// draw how many earthquakes there were, when the first and last happened and the
+/// biggest one in the top right corner (for the swarm summary map, `earthquakes`
+/// oldest first). `off_map` epicenters that couldn't be drawn are noted too
+pub fn draw_summary_title(canvas: &mut impl Canvas, font: &FontArc, earthquakes: &[Earthquake], off_map: usize) {
+    let (first, last, biggest) = match (
+        earthquakes.first(),
+        earthquakes.last(),
+        Earthquake::biggest(earthquakes),
+    ) {
+        (Some(first), Some(last), Some(biggest)) => (first, last, biggest),
+        _ => return,
+    };
+
+    let mut lines = vec![
+        (format!("{} 次地震 earthquakes", earthquakes.len()), TIME_TEXT_SIZE),
+        (format!("{} ～", first.earthquake_info.origin_time), DETAIL_TEXT_SIZE),
+        (last.earthquake_info.origin_time.clone(), DETAIL_TEXT_SIZE),
+        (
+            format!(
+                "最大規模 M{:.1}",
+                biggest.earthquake_info.earthquake_magnitude.magnitude_value
+            ),
+            DETAIL_TEXT_SIZE,
+        ),
+        (biggest.earthquake_info.epicenter.location.clone(), LOCATION_TEXT_SIZE),
+    ];
+    if off_map > 0 {
+        lines.push((format!("{} 個震央不在地圖上", off_map), LOCATION_TEXT_SIZE));
+    }
+
+    draw_title_panel(canvas, font, &lines);
+}
+
+/// draw the time colors (labeled with the first and last times) and a few magnitude
+/// circles in the bottom right corner, for the swarm summary map
+pub fn draw_summary_legend(canvas: &mut impl Canvas, font: &FontArc, earthquakes: &[Earthquake]) {
+    let title_height = LEGEND_TITLE_SIZE as u32 + LINE_GAP;
+    let labels_height = LINE_GAP + LEGEND_LABEL_SIZE as u32;
+    let biggest_radius = magnitude_radius(MAGNITUDE_KEY_LEVELS[MAGNITUDE_KEY_LEVELS.len() - 1]);
+    let magnitudes_height = LINE_GAP * 2 + (biggest_radius * 2.0) as u32;
+
+    let (pic_width, pic_height) = canvas.dimensions();
+    let panel_width = TIME_KEY_WIDTH + PANEL_PADDING * 2;
+    let panel_height =
+        title_height + LEGEND_SWATCH_HEIGHT + labels_height + magnitudes_height + PANEL_PADDING * 2;
+    let panel_x = pic_width.saturating_sub(panel_width + PANEL_MARGIN);
+    let panel_y = pic_height.saturating_sub(panel_height + PANEL_MARGIN);
+    draw_panel(canvas, panel_x, panel_y, panel_width, panel_height);
+
+    let left = panel_x + PANEL_PADDING;
+    let mut y = panel_y + PANEL_PADDING;
+
+    let title_x = left as f64 + (TIME_KEY_WIDTH as f64 - text_width(font, TIME_KEY_TITLE, LEGEND_TITLE_SIZE)) / 2.0;
+    canvas.draw_text(font, TIME_KEY_TITLE, title_x, y as f64, LEGEND_TITLE_SIZE, BLACK);
+    y += title_height;
+
+    // the colors, first to last, in a bar
+    canvas.fill_rect(
+        (left - 1) as f64,
+        (y - 1) as f64,
+        (TIME_KEY_WIDTH + 2) as f64,
+        (LEGEND_SWATCH_HEIGHT + 2) as f64,
+        BLACK,
+    );
+    let step_width = TIME_KEY_WIDTH as f64 / TIME_KEY_STEPS as f64;
+    for step in 0..TIME_KEY_STEPS {
+        canvas.fill_rect(
+            left as f64 + step_width * step as f64,
+            y as f64,
+            step_width,
+            LEGEND_SWATCH_HEIGHT as f64,
+            time_color(step as f64 / (TIME_KEY_STEPS - 1) as f64),
+        );
+    }
+    y += LEGEND_SWATCH_HEIGHT;
+
+    if let (Some(first), Some(last)) = (earthquakes.first(), earthquakes.last()) {
+        // just the month, day, hour and minute (ex: 04-03 07:58)
+        let short_time = |eq: &Earthquake| eq.earthquake_info.origin_time.chars().skip(5).take(11).collect::<String>();
+        let first_label = short_time(first);
+        let last_label = short_time(last);
+        let label_y = (y + LINE_GAP) as f64;
+        canvas.draw_text(font, &first_label, left as f64, label_y, LEGEND_LABEL_SIZE, BLACK);
+        let last_x = (left + TIME_KEY_WIDTH) as f64 - text_width(font, &last_label, LEGEND_LABEL_SIZE);
+        canvas.draw_text(font, &last_label, last_x, label_y, LEGEND_LABEL_SIZE, BLACK);
+    }
+    y += labels_height + LINE_GAP;
+
+    // a circle for a few magnitudes, each with its label after it
+    let center_y = y as f64 + biggest_radius;
+    let item_width = TIME_KEY_WIDTH as f64 / MAGNITUDE_KEY_LEVELS.len() as f64;
+    for (i, magnitude) in MAGNITUDE_KEY_LEVELS.iter().enumerate() {
+        let radius = magnitude_radius(*magnitude);
+        let center_x = left as f64 + item_width * i as f64 + biggest_radius;
+        draw_circle(canvas, (center_x, center_y), radius, WHITE);
+
+        let label = format!("M{}", magnitude);
+        let label_y = center_y - LEGEND_LABEL_SIZE as f64 / 2.0;
+        canvas.draw_text(font, &label, center_x + biggest_radius + LINE_GAP as f64, label_y, LEGEND_LABEL_SIZE, BLACK);
+    }
+}
+
+/// draw `lines` (each with its text size) in a panel in the top right corner, sized to
+/// fit the widest one
+fn draw_title_panel(canvas: &mut impl Canvas, font: &FontArc, lines: &[(String, f32)]) {
+    let text_width = lines
+        .iter()
+        .map(|(text, size)| text_width(font, text, *size))
+        .fold(0.0, f64::max) as u32;
+    let text_height = lines.iter().map(|(_, size)| *size as u32 + LINE_GAP).sum::<u32>().saturating_sub(LINE_GAP);
+
+    let (pic_width, _) = canvas.dimensions();
+    let panel_width = text_width + PANEL_PADDING * 2;
+    let panel_height = text_height + PANEL_PADDING * 2;
+    let panel_x = pic_width.saturating_sub(panel_width + PANEL_MARGIN);
+    let panel_y = PANEL_MARGIN;
+    draw_panel(canvas, panel_x, panel_y, panel_width, panel_height);
+
+    let mut y = panel_y + PANEL_PADDING;
+    for (text, size) in lines {
+        canvas.draw_text(font, text, (panel_x + PANEL_PADDING) as f64, y as f64, *size, BLACK);
+        y += *size as u32 + LINE_GAP;
+    }
+}
+
+/// white box with a black border
+fn draw_panel(canvas: &mut impl Canvas, x: u32, y: u32, width: u32, height: u32) {
+    canvas.fill_rect(x as f64, y as f64, width as f64, height as f64, BLACK);
+    canvas.fill_rect(
+        (x + PANEL_BORDER) as f64,
+        (y + PANEL_BORDER) as f64,
+        width.saturating_sub(PANEL_BORDER * 2) as f64,
+        height.saturating_sub(PANEL_BORDER * 2) as f64,
+        WHITE,
+    );
+}
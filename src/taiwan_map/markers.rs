@@ -0,0 +1,143 @@
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::taiwan_map::canvas::Canvas;
+use crate::taiwan_map::options::Palette;
+use crate::taiwan_map::text::{text_ascent, text_width};
+use crate::taiwan_map::UNREADABLE_INTENSITY_COLOR;
+use ab_glyph::FontArc;
+use image::Rgba;
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+/// the farthest any symbol reaches from its center (pixels)
+pub const SYMBOL_RADIUS: f64 = 16.0;
+const SYMBOL_BORDER: f64 = 2.0;
+const SYMBOL_TEXT_SIZE: f32 = 12.0;
+/// points around the circle symbol
+const CIRCLE_POINTS: usize = 40;
+/// inner corners of the star are this far from the middle (the outer ones are 1)
+const STAR_INNER_RADIUS: f64 = 0.55;
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// the outline of each symbol
+#[derive(Clone, Copy)]
+enum Shape {
+    Circle,
+    Square,
+    Diamond,
+    Triangle,
+    Star,
+}
+
+/// draw a station as a shape for its intensity with the level written on it. with
+/// `outline`, the middle is white instead of the intensity's color, the same as the
+/// square markers
+pub fn draw_symbol(
+    canvas: &mut impl Canvas,
+    font: &FontArc,
+    center: (f64, f64),
+    intensity: Option<SeismicIntensity>,
+    outline: bool,
+    palette: Palette,
+) {
+    let shape = match intensity {
+        Some(SeismicIntensity::Three) | Some(SeismicIntensity::Four) => Shape::Square,
+        Some(SeismicIntensity::FiveWeak) | Some(SeismicIntensity::FiveStrong) => Shape::Diamond,
+        Some(SeismicIntensity::SixWeak) | Some(SeismicIntensity::SixStrong) => Shape::Triangle,
+        Some(SeismicIntensity::Seven) => Shape::Star,
+        _ => Shape::Circle,
+    };
+    let color = match intensity {
+        Some(intensity) => palette.color(intensity),
+        None => UNREADABLE_INTENSITY_COLOR,
+    };
+
+    // black edge, then the color, then (for an outline) white in the middle
+    canvas.fill_polygon(&[shape_points(shape, center, 0.0)], BLACK, 1.0);
+    canvas.fill_polygon(&[shape_points(shape, center, SYMBOL_BORDER)], color, 1.0);
+    let background = if outline {
+        canvas.fill_polygon(&[shape_points(shape, center, SYMBOL_BORDER * 2.0)], WHITE, 1.0);
+        WHITE
+    } else {
+        color
+    };
+
+    let label = match intensity {
+        Some(intensity) => intensity.short_label(),
+        None => "?",
+    };
+    let label_width = text_width(font, label, SYMBOL_TEXT_SIZE);
+    // digits are about 0.72 of the font size tall, so put their middle on the center
+    let baseline = center.1 + SYMBOL_TEXT_SIZE as f64 * 0.36;
+    canvas.draw_text(
+        font,
+        label,
+        center.0 - label_width / 2.0,
+        baseline - text_ascent(font, SYMBOL_TEXT_SIZE),
+        SYMBOL_TEXT_SIZE,
+        readable_text_color(background),
+    );
+}
+
+/// black on light colors and white on dark ones
+pub fn readable_text_color(background: Rgba<u8>) -> Rgba<u8> {
+    let luminance =
+        0.299 * background[0] as f64 + 0.587 * background[1] as f64 + 0.114 * background[2] as f64;
+
+    if luminance > 140.0 {
+        BLACK
+    } else {
+        WHITE
+    }
+}
+
+/// how far the corners of each shape are from its center (the pointy shapes are
+/// bigger so they look about as heavy as the circle)
+fn shape_radius(shape: Shape) -> f64 {
+    match shape {
+        Shape::Circle => 11.0,
+        Shape::Square => 13.5,
+        Shape::Diamond => 14.0,
+        Shape::Triangle => SYMBOL_RADIUS,
+        Shape::Star => SYMBOL_RADIUS,
+    }
+}
+
+/// the corners of `shape` around `center`, with every edge moved `inset` pixels in
+/// (so the same inset makes the same width border on every shape)
+fn shape_points(shape: Shape, center: (f64, f64), inset: f64) -> Vec<(f64, f64)> {
+    // corners around (0, 0) with the farthest ones 1 away, starting at the top
+    let around = |count: usize, radius: &dyn Fn(usize) -> f64| -> Vec<(f64, f64)> {
+        (0..count)
+            .map(|i| {
+                let angle = -PI / 2.0 + 2.0 * PI * i as f64 / count as f64;
+                (radius(i) * angle.cos(), radius(i) * angle.sin())
+            })
+            .collect()
+    };
+    let unit = match shape {
+        Shape::Circle => around(CIRCLE_POINTS, &|_| 1.0),
+        Shape::Square => {
+            let half = FRAC_1_SQRT_2;
+            vec![(-half, -half), (half, -half), (half, half), (-half, half)]
+        }
+        Shape::Diamond => around(4, &|_| 1.0),
+        Shape::Triangle => around(3, &|_| 1.0),
+        Shape::Star => around(10, &|i| if i % 2 == 0 { 1.0 } else { STAR_INNER_RADIUS }),
+    };
+
+    // how close the nearest edge is to the middle
+    let inner = unit
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            let b = unit[(i + 1) % unit.len()];
+            (a.0 * b.1 - a.1 * b.0).abs() / ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+        })
+        .fold(f64::MAX, f64::min);
+
+    let scale = (shape_radius(shape) - inset / inner).max(0.0);
+    unit.iter()
+        .map(|(x, y)| (center.0 + x * scale, center.1 + y * scale))
+        .collect()
+}
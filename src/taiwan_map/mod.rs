@@ -1,92 +1,357 @@
+mod assets;
+mod canvas;
 mod coordinates;
+mod counties;
+mod heatmap;
+mod inset;
+mod legend;
+mod markers;
+mod options;
+mod projection;
+mod raster;
+mod report;
+mod summary;
+mod svg;
+mod text;
+mod view;
 
-use crate::cwb_api::structs::{Earthquake, IntensityConversionErrors};
+pub use crate::taiwan_map::assets::MapAssetError;
+pub use crate::taiwan_map::counties::county_intensities;
+pub use crate::taiwan_map::options::{LowIntensityStyle, MapStyle, MarkerStyle, Palette, RenderOptions};
+pub use crate::taiwan_map::report::RenderReport;
+
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::cwb_api::structs::Earthquake;
+use crate::geo::GeoPoint;
+use crate::metrics::METRICS;
+use crate::taiwan_map::assets::MapAssets;
+use crate::taiwan_map::canvas::Canvas;
 use crate::taiwan_map::coordinates::Coords;
-use image::{open, DynamicImage, GenericImage, GenericImageView};
-use log::error;
-
-const DEGREE_TO_LONG: f64 = 279.065;
-const DEGREE_TO_LAT: f64 = 256.364;
-const BASE_LONG: f64 = 21.8898;
-const BASE_LAT: f64 = 119.2021;
-const PIC_WIDTH: u32 = 950;
-const PIC_HEIGHT: u32 = 1000;
+use crate::taiwan_map::counties::draw_counties;
+use crate::taiwan_map::heatmap::draw_heatmap;
+use crate::taiwan_map::inset::{draw_inset, draw_offmap_arrow};
+use crate::taiwan_map::legend::{draw_legend, draw_summary_legend, draw_summary_title, draw_title};
+use crate::taiwan_map::markers::draw_symbol;
+use crate::taiwan_map::raster::RasterCanvas;
+use crate::taiwan_map::report::UnreadableStation;
+use crate::taiwan_map::summary::draw_summary_epicenters;
+use crate::taiwan_map::svg::SvgCanvas;
+use crate::taiwan_map::view::View;
+use image::{DynamicImage, ImageOutputFormat, ImageResult};
+use log::warn;
+use std::io::Cursor;
+use tracing::info_span;
+
 const EQ_STATION_BOX_SIZE: u32 = 12;
 const EQ_STATION_BOX_BORDER: u32 = 2;
 const EPICENTER_ICON_SIZE: u32 = 40;
+/// stations whose intensity string couldn't be read are gray
+const UNREADABLE_INTENSITY_COLOR: image::Rgba<u8> = image::Rgba([100, 100, 100, 255]);
 
 pub const MAP_LOC: &str = "img/eq-map.png";
+/// where `MAP_LOC` is in the world (see `projection::Georeference`)
+pub const MAP_GEOREF_LOC: &str = "img/eq-map.json";
 pub const EPI_PIC_LOC: &str = "img/eq-epi.png";
+/// county boundaries (GeoJSON) for the counties map style. traced from `MAP_LOC`, so
+/// only the counties on the base map are there (not Kinmen or Matsu)
+pub const COUNTIES_LOC: &str = "img/counties.geojson";
+/// font for the text on the map. it isn't included in the repo, so put a font that
+/// has Chinese characters here (like Noto Sans TC). the bot won't start without it
+pub const MAP_FONT_LOC: &str = "img/map-font.ttf";
+
+/// draws earthquakes on the Taiwan map
+///
+/// the base map and epicenter icon are decoded once when this is created, then every
+/// render just copies the base map, so make one of these at startup and share it
+pub struct MapRenderer {
+    assets: MapAssets,
+    options: RenderOptions,
+}
+
+impl MapRenderer {
+    /// load the map images (from the img folder, or from the binary with the
+    /// `embed-assets` feature) and check they're the expected size
+    pub fn new(options: RenderOptions) -> Result<Self, MapAssetError> {
+        Ok(Self {
+            assets: MapAssets::load()?,
+            options,
+        })
+    }
+
+    /// draw the epicenter and every station on a copy of the base map
+    pub fn render(&self, eq: &Earthquake) -> DynamicImage {
+        let (map_image, report) = self.render_with_report(eq);
+
+        if !report.unreadable.is_empty() {
+            warn!(
+                "couldn't read {} station intensities for the {} earthquake",
+                report.unreadable.len(),
+                eq.earthquake_info.origin_time
+            );
+        }
+
+        map_image
+    }
+
+    /// same as `render`, but also returns what happened to each station
+    ///
+    /// the map zooms in on the epicenter and felt stations when they're all in a small
+    /// area (with an overview of Taiwan in the corner), and if the epicenter is off the
+    /// map an arrow points towards it instead. the time, magnitude, depth and an
+    /// intensity color key are drawn on top. with the `heatmap` option, the land
+    /// under the stations is shaded by intensity too, and with the counties style each
+    /// county is colored instead of drawing the stations
+    pub fn render_with_report(&self, eq: &Earthquake) -> (DynamicImage, RenderReport) {
+        let (canvas, report) = self.render_scene(eq, RasterCanvas::new);
+
+        (canvas.into_image(), report)
+    }
+
+    /// render the earthquake and encode it as a png (for uploading)
+    pub fn render_png(&self, eq: &Earthquake) -> ImageResult<Vec<u8>> {
+        encode_png(&self.render(eq))
+    }
+
+    /// the same map as `render`, as an svg document (for the website and printing)
+    ///
+    /// the base map and epicenter icon are embedded pngs, and the rest is vector shapes
+    /// and text laid out exactly like the png
+    pub fn render_svg(&self, eq: &Earthquake) -> String {
+        let (canvas, _) = self.render_scene(eq, SvgCanvas::with_background);
+
+        canvas.finish()
+    }
+
+    /// one map of a swarm of earthquakes (`earthquakes` oldest first), with a numbered
+    /// circle on each epicenter sized by its magnitude and colored by its time
+    ///
+    /// the map zooms in on the epicenters like `render` does, and the stations aren't
+    /// drawn since each earthquake shook different ones
+    pub fn render_summary(&self, earthquakes: &[Earthquake]) -> DynamicImage {
+        self.render_summary_scene(earthquakes, RasterCanvas::new).into_image()
+    }
+
+    /// render the swarm summary map and encode it as a png (for uploading)
+    pub fn render_summary_png(&self, earthquakes: &[Earthquake]) -> ImageResult<Vec<u8>> {
+        encode_png(&self.render_summary(earthquakes))
+    }
+
+    /// the same map as `render_summary`, as an svg document
+    pub fn render_summary_svg(&self, earthquakes: &[Earthquake]) -> String {
+        self.render_summary_scene(earthquakes, SvgCanvas::with_background).finish()
+    }
+
+    /// work out the view around every epicenter and draw the summary map on the
+    /// canvas `make_canvas` makes from the cropped base map
+    fn render_summary_scene<C: Canvas>(
+        &self,
+        earthquakes: &[Earthquake],
+        make_canvas: impl FnOnce(DynamicImage) -> C,
+    ) -> C {
+        let _span = info_span!("render", kind = "summary", quakes = earthquakes.len()).entered();
+        let _timer = METRICS.render_duration.with_label_values(&["summary"]).start_timer();
+        let epicenters: Vec<GeoPoint> = earthquakes
+            .iter()
+            .map(|eq| eq.earthquake_info.epicenter.point())
+            .collect();
+        let view = View::around(&self.assets.georef, &epicenters);
+
+        let mut canvas = make_canvas(view.crop_base_map(&self.assets.base_map));
+
+        let font = &self.assets.font;
+        let off_map = draw_summary_epicenters(&mut canvas, font, &view, earthquakes);
+        if off_map > 0 {
+            warn!("{} of the {} swarm epicenters weren't on the map", off_map, earthquakes.len());
+        }
+
+        if view.is_zoomed() {
+            draw_inset(&mut canvas, &self.assets.base_map, &view);
+        }
+
+        draw_summary_title(&mut canvas, font, earthquakes, off_map);
+        draw_summary_legend(&mut canvas, font, earthquakes);
+
+        canvas
+    }
+
+    /// work out the view and draw the whole map on the canvas `make_canvas` makes
+    /// from the cropped base map
+    fn render_scene<C: Canvas>(
+        &self,
+        eq: &Earthquake,
+        make_canvas: impl FnOnce(DynamicImage) -> C,
+    ) -> (C, RenderReport) {
+        let _span = info_span!("render", kind = "quake").entered();
+        let _timer = METRICS.render_duration.with_label_values(&["quake"]).start_timer();
+        let mut report = RenderReport::default();
+        let epicenter = eq.earthquake_info.epicenter.point();
+
+        // get every station's intensity first so the felt ones can decide the zoom
+        // (a station that can't be read stays on the map, but gets noted in the report)
+        let mut stations: Vec<(GeoPoint, Option<SeismicIntensity>)> = Vec::new();
+        for station in eq.intensity.shaking_area.iter().flat_map(|area| area.eq_station.iter()) {
+            match station.intensity() {
+                Ok(intensity) => stations.push((station.point(), Some(intensity))),
+                Err(error) => {
+                    report.unreadable.push(UnreadableStation {
+                        station_name: station.station_name.clone(),
+                        station_intensity: station.station_intensity.clone(),
+                        error,
+                    });
+                    stations.push((station.point(), None));
+                }
+            }
+        }
+
+        let mut focus = vec![epicenter];
+        focus.extend(
+            stations
+                .iter()
+                .filter(|(_, intensity)| *intensity >= Some(SeismicIntensity::One))
+                .map(|(point, _)| *point),
+        );
+        let view = View::around(&self.assets.georef, &focus);
+
+        let mut background = view.crop_base_map(&self.assets.base_map);
+
+        // the heatmap goes right on the base map so everything else is drawn over it
+        if self.options.style == MapStyle::Stations && self.options.heatmap {
+            let readable: Vec<(GeoPoint, SeismicIntensity)> = stations
+                .iter()
+                .filter_map(|(point, intensity)| intensity.map(|intensity| (*point, intensity)))
+                .collect();
+            draw_heatmap(
+                &mut background,
+                &view,
+                &readable,
+                &self.assets.land,
+                self.options.palette,
+            );
+        }
+
+        let mut canvas = make_canvas(background);
+
+        if self.options.style == MapStyle::Counties {
+            let mut intensities = county_intensities(eq);
+            if !self.options.show_zero {
+                intensities.retain(|_, intensity| *intensity > SeismicIntensity::Zero);
+            }
+
+            for name in intensities.keys() {
+                if self.assets.counties.iter().any(|county| &county.name == name) {
+                    report.counties_filled += 1;
+                } else {
+                    report.unknown_counties.push(name.clone());
+                }
+            }
+            report.unknown_counties.sort();
+
+            draw_counties(
+                &mut canvas,
+                &view,
+                &self.assets.counties,
+                &intensities,
+                self.options.palette,
+            );
 
-impl Earthquake {
-    /// this will just take an earthquake as an argument, then it'll
-    /// return an EqMap with a completed image
-    pub fn mark_image_with_eq_data(&self) {
-        let mut map_image = open(MAP_LOC).expect("couldn't open eq-map.png");
+            // the counties take the place of the stations
+            stations.clear();
+        }
 
         // first mark the epicenter (looks nicer with the station on top if they overlap)
-        let epicenter_coords = Coords::from_coordinates(
-            self.earthquake_info.epicenter.long,
-            self.earthquake_info.epicenter.lat,
-            0, // this won't matter, so marking it 0
+        let epicenter_coords = Coords::from_geo_point(
+            &view,
+            epicenter,
+            None, // this won't matter, so marking it None
         );
-        if let Some(epicenter_coords) = epicenter_coords {
-            mark_epicenter(&mut map_image, epicenter_coords);
+        match epicenter_coords {
+            Some(epicenter_coords) => {
+                mark_epicenter(&mut canvas, &self.assets.epicenter_icon, epicenter_coords)
+            }
+            None => {
+                report.epicenter_off_map = true;
+                draw_offmap_arrow(&mut canvas, view.project(epicenter));
+            }
         }
 
-        // iterate through the areas, which I guess mean cities/counties
-        for area in self.intensity.shaking_area.iter() {
-            // iterate through the station in those areas
-            for station in &area.eq_station {
-                // get intensity (default to 1 if there's an error)
-                let intensity = match station.convert_station_intensity_to_u8() {
-                    Ok(num) => num,
-                    Err(e) => {
-                        // todo later just delete this since I don't really care if it works
-                        // this is just here in case something breaks, but it likely won't break
-                        // but in the meantime, just log some stuff if there are some errors
-                        error!("There was an error with this intensity: {}", station.station_intensity);
-                        match e {
-                            IntensityConversionErrors::ParseError(ee) => {
-                                error!("Error text: {}", ee);
-                            },
-                            IntensityConversionErrors::OutOfBounds(ee) => {
-                                error!("Parsed to this value: {}", ee);
-                            }
-                        }
+        for (point, intensity) in stations {
+            // decide if (and how) the station gets drawn
+            let mut outline = false;
+            if let Some(intensity) = intensity {
+                if intensity == SeismicIntensity::Zero && !self.options.show_zero {
+                    report.hidden_zero += 1;
+                    continue;
+                }
 
-                        0
-                    },
-                };
+                if intensity < self.options.low_intensity_below {
+                    match self.options.low_intensity_style {
+                        LowIntensityStyle::Fill => {}
+                        LowIntensityStyle::Outline => outline = true,
+                        LowIntensityStyle::Omit => {
+                            report.omitted_low += 1;
+                            continue;
+                        }
+                    }
+                }
+            }
 
-                // get coordinates
-                if let Some(coords) = Coords::from_coordinates(
-                    station.station_lat,
-                    station.station_long,
-                    intensity,
-                ) {
+            // get coordinates
+            match Coords::from_geo_point(&view, point, intensity) {
+                Some(coords) => {
                     // *********
                     // the coordinates are within the image bounds
                     // *********
-                    add_station_to_map(&mut map_image, coords);
+                    match self.options.marker_style {
+                        MarkerStyle::Square => {
+                            add_station_to_map(&mut canvas, coords, outline, self.options.palette)
+                        }
+                        MarkerStyle::Symbol => draw_symbol(
+                            &mut canvas,
+                            &self.assets.font,
+                            (coords.x as f64, coords.y as f64),
+                            coords.intensity,
+                            outline,
+                            self.options.palette,
+                        ),
+                    }
+                    report.drawn += 1;
                 }
+                None => report.off_map += 1,
             }
         }
 
-        let _ = map_image.save("temp.png");
+        if view.is_zoomed() {
+            draw_inset(&mut canvas, &self.assets.base_map, &view);
+        }
+
+        let font = &self.assets.font;
+        draw_title(&mut canvas, font, eq);
+        draw_legend(&mut canvas, font, self.options.marker_style, self.options.palette);
+
+        (canvas, report)
     }
 }
 
+/// encode a rendered map as a png
+pub fn encode_png(map_image: &DynamicImage) -> ImageResult<Vec<u8>> {
+    let mut png = Cursor::new(Vec::new());
+    map_image.write_to(&mut png, ImageOutputFormat::Png)?;
+
+    Ok(png.into_inner())
+}
+
 /// add a color coded square on the map to indicate the intensity of the shaking at the station
-fn add_station_to_map(map_image: &mut DynamicImage, coordinates: Coords) {
+/// (with `outline`, only the edge of the square is colored)
+fn add_station_to_map(canvas: &mut impl Canvas, coordinates: Coords, outline: bool, palette: Palette) {
     // make a black box that'll go behind the colored box
-    let back_box_size = EQ_STATION_BOX_SIZE + (EQ_STATION_BOX_BORDER * 2);
+    // (this is all signed, since the box can start left of or above the image)
+    let back_box_size = (EQ_STATION_BOX_SIZE + (EQ_STATION_BOX_BORDER * 2)) as i32;
     let box_start_x = coordinates.x - back_box_size / 2;
     let box_end_x = box_start_x + back_box_size;
     let box_start_y = coordinates.y - back_box_size / 2;
     let box_end_y = box_start_y + back_box_size;
     make_box(
-        map_image,
+        canvas,
         box_start_x,
         box_end_x,
         box_start_y,
@@ -95,104 +360,215 @@ fn add_station_to_map(map_image: &mut DynamicImage, coordinates: Coords) {
     );
 
     // do the same thing again, but with the regular sized box
-    let box_size = EQ_STATION_BOX_SIZE;
+    let box_size = EQ_STATION_BOX_SIZE as i32;
     let box_start_x = coordinates.x - box_size / 2;
     let box_end_x = box_start_x + box_size;
     let box_start_y = coordinates.y - box_size / 2;
     let box_end_y = box_start_y + box_size;
     make_box(
-        map_image,
+        canvas,
         box_start_x,
         box_end_x,
         box_start_y,
         box_end_y,
-        get_rgba_from_intensity(coordinates.intensity).expect("intensity out of expected range"),
+        match coordinates.intensity {
+            Some(intensity) => palette.color(intensity),
+            None => UNREADABLE_INTENSITY_COLOR,
+        },
     );
+
+    // for an outline, put a black box inside the colored one, then white in the middle
+    if outline {
+        let border = EQ_STATION_BOX_BORDER as i32;
+        let inner_size = box_size - border * 2;
+        let inner_start_x = box_start_x + border;
+        let inner_start_y = box_start_y + border;
+        make_box(
+            canvas,
+            inner_start_x,
+            inner_start_x + inner_size,
+            inner_start_y,
+            inner_start_y + inner_size,
+            image::Rgba([0, 0, 0, 255]),
+        );
+        make_box(
+            canvas,
+            inner_start_x + 1,
+            inner_start_x + inner_size - 1,
+            inner_start_y + 1,
+            inner_start_y + inner_size - 1,
+            image::Rgba([255, 255, 255, 255]),
+        );
+    }
+}
+
+/// mix `color` over `under`, with `coverage` (0 to 1) of the pixel covered
+fn blend(under: image::Rgba<u8>, color: image::Rgba<u8>, coverage: f32) -> image::Rgba<u8> {
+    let weight = coverage.clamp(0.0, 1.0) * color[3] as f32 / 255.0;
+    let mix = |i: usize| (color[i] as f32 * weight + under[i] as f32 * (1.0 - weight)) as u8;
+
+    image::Rgba([mix(0), mix(1), mix(2), 255])
 }
 
 /// takes a start and end pixel for x and y, then makes a square with the color provided
+/// (the end pixels are part of the square, and any part that's off the image is skipped)
 fn make_box(
-    map_image: &mut DynamicImage,
-    box_start_x: u32,
-    box_end_x: u32,
-    box_start_y: u32,
-    box_end_y: u32,
+    canvas: &mut impl Canvas,
+    box_start_x: i32,
+    box_end_x: i32,
+    box_start_y: i32,
+    box_end_y: i32,
     color: image::Rgba<u8>,
 ) {
-    // iterate through x and y and put a pixel
-    for x in box_start_x..=box_end_x {
-        for y in box_start_y..=box_end_y {
-            if (0..PIC_WIDTH).contains(&x) && (0..PIC_HEIGHT).contains(&y) {
-                map_image.put_pixel(x, y, color);
-            }
-        }
-    }
+    canvas.fill_rect(
+        box_start_x as f64,
+        box_start_y as f64,
+        (box_end_x - box_start_x + 1) as f64,
+        (box_end_y - box_start_y + 1) as f64,
+        color,
+    );
 }
 
-fn get_rgba_from_intensity(intensity: u8) -> Option<image::Rgba<u8>> {
+/// the color CWB gives each level on its seismic intensity scale (地震震度分級), from
+/// white for 0 through cyan, green, yellow, orange and reds to purple for 7
+fn get_rgba_from_intensity(intensity: SeismicIntensity) -> image::Rgba<u8> {
     match intensity {
-        8 => Some(image::Rgba([13, 0, 140, 255])),
-        7 => Some(image::Rgba([13, 0, 140, 255])),
-        6 => Some(image::Rgba([240, 0, 255, 255])),
-        5 => Some(image::Rgba([255, 0, 0, 255])),
-        4 => Some(image::Rgba([255, 130, 0, 255])),
-        3 => Some(image::Rgba([255, 220, 0, 255])),
-        2 => Some(image::Rgba([0, 140, 0, 255])),
-        1 => Some(image::Rgba([0, 190, 0, 255])),
-        // 0 is if there's an error parsing the station intensity string
-        0 => Some(image::Rgba([100, 100, 100, 255])),
-        _ => None,
+        SeismicIntensity::Seven => image::Rgba([0xB5, 0x1F, 0xEA, 255]),
+        SeismicIntensity::SixStrong => image::Rgba([0x9A, 0x4C, 0x86, 255]),
+        SeismicIntensity::SixWeak => image::Rgba([0x9D, 0x46, 0x46, 255]),
+        SeismicIntensity::FiveStrong => image::Rgba([0xC4, 0x3F, 0x3B, 255]),
+        SeismicIntensity::FiveWeak => image::Rgba([0xFD, 0x52, 0x33, 255]),
+        SeismicIntensity::Four => image::Rgba([0xFE, 0x85, 0x32, 255]),
+        SeismicIntensity::Three => image::Rgba([0xFE, 0xFD, 0x32, 255]),
+        SeismicIntensity::Two => image::Rgba([0x34, 0xFF, 0x32, 255]),
+        SeismicIntensity::One => image::Rgba([0x33, 0xFF, 0xDD, 255]),
+        SeismicIntensity::Zero => image::Rgba([0xFF, 0xFF, 0xFF, 255]),
     }
 }
 
-fn mark_epicenter(map_image: &mut DynamicImage, coords: Coords) {
-    let epi_image = open(EPI_PIC_LOC).expect("couldn't find eq-epi.png");
+/// viridis (a colormap made to be read the same with any color vision) from light to
+/// dark, with white for 0
+fn color_blind_rgba_from_intensity(intensity: SeismicIntensity) -> image::Rgba<u8> {
+    match intensity {
+        SeismicIntensity::Seven => image::Rgba([68, 1, 84, 255]),
+        SeismicIntensity::SixStrong => image::Rgba([72, 40, 120, 255]),
+        SeismicIntensity::SixWeak => image::Rgba([59, 82, 139, 255]),
+        SeismicIntensity::FiveStrong => image::Rgba([44, 114, 142, 255]),
+        SeismicIntensity::FiveWeak => image::Rgba([33, 145, 140, 255]),
+        SeismicIntensity::Four => image::Rgba([53, 183, 121, 255]),
+        SeismicIntensity::Three => image::Rgba([94, 201, 98, 255]),
+        SeismicIntensity::Two => image::Rgba([170, 220, 50, 255]),
+        SeismicIntensity::One => image::Rgba([253, 231, 37, 255]),
+        SeismicIntensity::Zero => image::Rgba([255, 255, 255, 255]),
+    }
+}
 
-    let box_size = EPICENTER_ICON_SIZE;
+fn mark_epicenter(canvas: &mut impl Canvas, epi_image: &DynamicImage, coords: Coords) {
+    // signed, since the icon can start left of or above the image
+    let box_size = EPICENTER_ICON_SIZE as i32;
     let box_start_x = coords.x - box_size / 2;
-    let box_end_x = box_start_x + box_size;
     let box_start_y = coords.y - box_size / 2;
-    let box_end_y = box_start_y + box_size;
 
-    for (x_icon, x_map) in (box_start_x..box_end_x).enumerate() {
-        for (y_icon, y_map) in (box_start_y..box_end_y).enumerate() {
-            // skip this iteration if x or y aren't in the image bounds
-            if !(0..PIC_WIDTH).contains(&x_map) || !(0..PIC_HEIGHT).contains(&y_map) {
-                // println!("trying to write to {}, {} - skipping", x_map, y_map);
-                continue;
-            }
+    canvas.draw_image(epi_image, box_start_x as f64, box_start_y as f64);
+}
 
-            // first get the pixel from the epicenter icon, check if it's transparent or not
-            // then manually use its A value to make it transparent
-            let mut icon_pixel = epi_image.get_pixel(x_icon as u32, y_icon as u32);
-            let icon_rgba = icon_pixel;
-            let map_rgba = map_image.get_pixel(x_map, y_map);
-
-            if icon_rgba[3] == 0 {
-                // this pixel is transparent, so no need to continue processing. Just
-                // loop again
-                continue;
-            } else if icon_rgba[3] != 255 {
-                // not completely transparent or opaque, so process
-                // if A == 255, no processing, but will just directly copy to the map image.
-                // get "weight" of both the map and icon. Use 255 as the max since it's the max
-                let icon_weight: f64 = icon_rgba[3] as f64 / 255.0;
-                let map_weight: f64 = 1.0 - icon_weight;
-
-                // apply weights to the rgb values
-                let target_r: u8 =
-                    ((icon_rgba[0] as f64 * icon_weight) + (map_rgba[0] as f64 * map_weight)) as u8;
-                let target_g: u8 =
-                    ((icon_rgba[1] as f64 * icon_weight) + (map_rgba[1] as f64 * map_weight)) as u8;
-                let target_b: u8 =
-                    ((icon_rgba[2] as f64 * icon_weight) + (map_rgba[2] as f64 * map_weight)) as u8;
-                let target_a: u8 = 255;
-
-                // modify the target pixel to the target values
-                icon_pixel = image::Rgba([target_r, target_g, target_b, target_a]);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taiwan_map::raster::RasterCanvas;
+    use image::GenericImageView;
+    use std::collections::HashSet;
+
+    const RED: image::Rgba<u8> = image::Rgba([255, 0, 0, 255]);
+
+    /// a white 100x100 canvas
+    fn blank_canvas() -> RasterCanvas {
+        RasterCanvas::new(DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            100,
+            100,
+            image::Rgba([255, 255, 255, 255]),
+        )))
+    }
 
-            map_image.put_pixel(x_map, y_map, icon_pixel);
+    /// the smallest box around every red pixel (left, top, right, bottom), if there are any
+    fn red_area(canvas: RasterCanvas) -> Option<(u32, u32, u32, u32)> {
+        let image = canvas.into_image();
+        let red: Vec<(u32, u32)> = image.pixels().filter(|(_, _, pixel)| *pixel == RED).map(|(x, y, _)| (x, y)).collect();
+        if red.is_empty() {
+            return None;
         }
+        let count = red.len() as u32;
+
+        let area = (
+            red.iter().map(|p| p.0).min().unwrap(),
+            red.iter().map(|p| p.1).min().unwrap(),
+            red.iter().map(|p| p.0).max().unwrap(),
+            red.iter().map(|p| p.1).max().unwrap(),
+        );
+        // the red pixels are a solid rectangle
+        assert_eq!(count, (area.2 - area.0 + 1) * (area.3 - area.1 + 1));
+        Some(area)
+    }
+
+    /// the epicenter icon centered on (x, y)
+    fn epicenter_at(x: i32, y: i32) -> Option<(u32, u32, u32, u32)> {
+        let icon = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(EPICENTER_ICON_SIZE, EPICENTER_ICON_SIZE, RED));
+        let mut canvas = blank_canvas();
+        mark_epicenter(&mut canvas, &icon, Coords { x, y, intensity: None });
+        red_area(canvas)
+    }
+
+    #[test]
+    fn epicenter_on_every_corner_and_edge_is_clipped() {
+        // the icon is 40x40, so it starts 20 left of and above the epicenter
+        assert_eq!(epicenter_at(50, 50), Some((30, 30, 69, 69)));
+        assert_eq!(epicenter_at(0, 0), Some((0, 0, 19, 19)));
+        assert_eq!(epicenter_at(99, 99), Some((79, 79, 99, 99)));
+        assert_eq!(epicenter_at(99, 0), Some((79, 0, 99, 19)));
+        assert_eq!(epicenter_at(0, 99), Some((0, 79, 19, 99)));
+        assert_eq!(epicenter_at(50, 0), Some((30, 0, 69, 19)));
+        assert_eq!(epicenter_at(0, 50), Some((0, 30, 19, 69)));
+    }
+
+    #[test]
+    fn epicenter_off_the_image_is_clipped_or_skipped() {
+        // partly on the image
+        assert_eq!(epicenter_at(-10, -10), Some((0, 0, 9, 9)));
+        assert_eq!(epicenter_at(110, 50), Some((90, 30, 99, 69)));
+        // not on it at all
+        assert_eq!(epicenter_at(-20, 50), None);
+        assert_eq!(epicenter_at(50, 120), None);
+        assert_eq!(epicenter_at(-500, -500), None);
+    }
+
+    #[test]
+    fn boxes_on_every_corner_and_edge_are_clipped() {
+        let box_at = |start_x: i32, end_x: i32, start_y: i32, end_y: i32| {
+            let mut canvas = blank_canvas();
+            make_box(&mut canvas, start_x, end_x, start_y, end_y, RED);
+            red_area(canvas)
+        };
+
+        // the end pixels are part of the box
+        assert_eq!(box_at(0, 0, 0, 0), Some((0, 0, 0, 0)));
+        assert_eq!(box_at(99, 99, 99, 99), Some((99, 99, 99, 99)));
+        assert_eq!(box_at(0, 4, 95, 99), Some((0, 95, 4, 99)));
+        // hanging off the edges
+        assert_eq!(box_at(-3, 3, -3, 3), Some((0, 0, 3, 3)));
+        assert_eq!(box_at(97, 103, 97, 103), Some((97, 97, 99, 99)));
+        // completely off them
+        assert_eq!(box_at(-10, -1, 0, 10), None);
+        assert_eq!(box_at(100, 110, 0, 10), None);
+        assert_eq!(box_at(0, 10, 100, 110), None);
+    }
+
+    #[test]
+    fn every_intensity_gets_its_own_cwb_color() {
+        let colors: HashSet<_> = SeismicIntensity::ALL
+            .iter()
+            .map(|level| get_rgba_from_intensity(*level))
+            .collect();
+
+        assert_eq!(colors.len(), SeismicIntensity::ALL.len());
     }
 }
@@ -0,0 +1,184 @@
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::taiwan_map::{color_blind_rgba_from_intensity, get_rgba_from_intensity};
+use image::Rgba;
+
+/// how stations below `RenderOptions::low_intensity_below` are drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LowIntensityStyle {
+    /// a filled box, same as every other station
+    Fill,
+    /// just the edge of the box, so stronger stations stand out
+    Outline,
+    /// not drawn at all
+    Omit,
+}
+
+/// how the stations are marked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarkerStyle {
+    /// a colored square (the intensity is only shown by the color)
+    Square,
+    /// a shape for each group of levels (circle for 0 to 2, square for 3 and 4, diamond
+    /// for 5, triangle for 6 and star for 7) with the level written on it, so it can
+    /// be read without telling the colors apart
+    Symbol,
+}
+
+/// the colors used for each intensity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Palette {
+    /// CWB's own colors for each level
+    Cwb,
+    /// yellow through green and blue to dark purple, which stays in order for every
+    /// kind of color blindness (and in black and white)
+    ColorBlind,
+}
+
+impl Palette {
+    /// the color for `intensity`
+    pub fn color(self, intensity: SeismicIntensity) -> Rgba<u8> {
+        match self {
+            Palette::Cwb => get_rgba_from_intensity(intensity),
+            Palette::ColorBlind => color_blind_rgba_from_intensity(intensity),
+        }
+    }
+}
+
+/// what the map shows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapStyle {
+    /// a colored box for every station
+    Stations,
+    /// each county filled by the strongest intensity CWB reported there
+    Counties,
+}
+
+/// choices about which stations end up on the map
+/// includes:
+/// * show_zero - draw stations that reported intensity 0 (not felt)
+/// * low_intensity_style - how to draw stations weaker than low_intensity_below
+/// * low_intensity_below - the intensity where `low_intensity_style` stops applying
+/// * heatmap - shade the land between stations by how strong the shaking probably was
+/// * style - stations or counties (only show_zero matters for counties)
+/// * marker_style - how each station is marked
+/// * palette - the intensity colors
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub show_zero: bool,
+    pub low_intensity_style: LowIntensityStyle,
+    pub low_intensity_below: SeismicIntensity,
+    pub heatmap: bool,
+    pub style: MapStyle,
+    pub marker_style: MarkerStyle,
+    pub palette: Palette,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            show_zero: false,
+            low_intensity_style: LowIntensityStyle::Fill,
+            low_intensity_below: SeismicIntensity::One,
+            heatmap: false,
+            style: MapStyle::Stations,
+            marker_style: MarkerStyle::Square,
+            palette: Palette::Cwb,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// read the options from the environment (or .env), using the default for
+    /// anything that isn't set (a setting that's there but wrong is an error)
+    /// * MAP_SHOW_ZERO_INTENSITY - true or false
+    /// * MAP_LOW_INTENSITY_STYLE - fill, outline or omit
+    /// * MAP_LOW_INTENSITY_BELOW - an intensity like 2 or 5弱
+    /// * MAP_HEATMAP - true or false
+    /// * MAP_STYLE - stations or counties
+    /// * MAP_MARKER_STYLE - square or symbol
+    /// * MAP_PALETTE - cwb or colorblind
+    pub fn from_env() -> Result<Self, String> {
+        let mut options = Self::default();
+
+        if let Ok(show_zero) = std::env::var("MAP_SHOW_ZERO_INTENSITY") {
+            match show_zero.parse() {
+                Ok(show_zero) => options.show_zero = show_zero,
+                Err(_) => {
+                    return Err(format!(
+                        "MAP_SHOW_ZERO_INTENSITY should be true or false, not {}",
+                        show_zero
+                    ))
+                }
+            }
+        }
+
+        if let Ok(style) = std::env::var("MAP_LOW_INTENSITY_STYLE") {
+            match style.to_lowercase().as_str() {
+                "fill" => options.low_intensity_style = LowIntensityStyle::Fill,
+                "outline" => options.low_intensity_style = LowIntensityStyle::Outline,
+                "omit" => options.low_intensity_style = LowIntensityStyle::Omit,
+                _ => {
+                    return Err(format!(
+                        "MAP_LOW_INTENSITY_STYLE should be fill, outline or omit, not {}",
+                        style
+                    ))
+                }
+            }
+        }
+
+        if let Ok(below) = std::env::var("MAP_LOW_INTENSITY_BELOW") {
+            match below.parse() {
+                Ok(below) => options.low_intensity_below = below,
+                Err(e) => return Err(format!("MAP_LOW_INTENSITY_BELOW isn't an intensity: {}", e)),
+            }
+        }
+
+        if let Ok(heatmap) = std::env::var("MAP_HEATMAP") {
+            match heatmap.parse() {
+                Ok(heatmap) => options.heatmap = heatmap,
+                Err(_) => {
+                    return Err(format!(
+                        "MAP_HEATMAP should be true or false, not {}",
+                        heatmap
+                    ))
+                }
+            }
+        }
+
+        if let Ok(style) = std::env::var("MAP_STYLE") {
+            match style.to_lowercase().as_str() {
+                "stations" => options.style = MapStyle::Stations,
+                "counties" => options.style = MapStyle::Counties,
+                _ => return Err(format!("MAP_STYLE should be stations or counties, not {}", style)),
+            }
+        }
+
+        if let Ok(marker_style) = std::env::var("MAP_MARKER_STYLE") {
+            match marker_style.to_lowercase().as_str() {
+                "square" => options.marker_style = MarkerStyle::Square,
+                "symbol" => options.marker_style = MarkerStyle::Symbol,
+                _ => {
+                    return Err(format!(
+                        "MAP_MARKER_STYLE should be square or symbol, not {}",
+                        marker_style
+                    ))
+                }
+            }
+        }
+
+        if let Ok(palette) = std::env::var("MAP_PALETTE") {
+            match palette.to_lowercase().as_str() {
+                "cwb" => options.palette = Palette::Cwb,
+                "colorblind" => options.palette = Palette::ColorBlind,
+                _ => {
+                    return Err(format!(
+                        "MAP_PALETTE should be cwb or colorblind, not {}",
+                        palette
+                    ))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
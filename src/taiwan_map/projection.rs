@@ -0,0 +1,189 @@
+use crate::geo::GeoPoint;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+/// how latitude/longitude are flattened onto the base map image
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    /// latitude and longitude both scale linearly to pixels ("plate carrée")
+    Equirectangular,
+    /// spherical mercator used by most web map tiles (EPSG:3857)
+    WebMercator,
+}
+
+impl Projection {
+    /// project a latitude onto the vertical axis of this projection
+    /// (the horizontal axis is always linear in longitude for these projections)
+    fn project_lat(&self, lat: f64) -> f64 {
+        match self {
+            Projection::Equirectangular => lat,
+            Projection::WebMercator => {
+                let lat_rad = lat.to_radians();
+                (PI / 4.0 + lat_rad / 2.0).tan().ln()
+            }
+        }
+    }
+
+    /// opposite of `project_lat`
+    fn unproject_lat(&self, y: f64) -> f64 {
+        match self {
+            Projection::Equirectangular => y,
+            Projection::WebMercator => (2.0 * y.exp().atan() - PI / 2.0).to_degrees(),
+        }
+    }
+}
+
+/// the real life area covered by a base map, from edge to edge of the image
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub west: f64,
+    pub east: f64,
+    pub south: f64,
+    pub north: f64,
+}
+
+/// describes where a base map image is in the real world
+///
+/// every base map has one of these in a json file next to it (`eq-map.png` has
+/// `eq-map.json`), so a different map only needs a different sidecar file
+/// ex:
+/// {
+///     "width": 950,
+///     "height": 1000,
+///     "projection": "equirectangular",
+///     "bounds": { "west": 119.2021, "east": 122.9078, "south": 21.8898, "north": 25.4732 }
+/// }
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Georeference {
+    pub width: u32,
+    pub height: u32,
+    pub projection: Projection,
+    pub bounds: BoundingBox,
+}
+
+impl Georeference {
+    /// why this can't be projected onto, if it can't: an image with no pixels, bounds
+    /// that are empty or the wrong way around, or (for web mercator) that reach a pole
+    pub fn validate(&self) -> Result<(), String> {
+        let b = &self.bounds;
+
+        if self.width == 0 || self.height == 0 {
+            return Err(format!("the image is {}x{}", self.width, self.height));
+        }
+        if ![b.west, b.east, b.south, b.north].iter().all(|edge| edge.is_finite()) {
+            return Err("the bounds have to be numbers".to_string());
+        }
+        if b.east <= b.west {
+            return Err(format!("east ({}) has to be more than west ({})", b.east, b.west));
+        }
+        if b.north <= b.south {
+            return Err(format!("north ({}) has to be more than south ({})", b.north, b.south));
+        }
+        if self.projection == Projection::WebMercator && (b.north >= 90.0 || b.south <= -90.0) {
+            return Err("web mercator can't reach the poles".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// convert a real life location to a pixel position on the base map
+    /// the result isn't clamped, so it can be off the image
+    pub fn project(&self, point: GeoPoint) -> (f64, f64) {
+        let b = &self.bounds;
+
+        let x = (point.lon - b.west) / (b.east - b.west) * self.width as f64;
+
+        let top = self.projection.project_lat(b.north);
+        let bottom = self.projection.project_lat(b.south);
+        let y = (top - self.projection.project_lat(point.lat)) / (top - bottom)
+            * self.height as f64;
+
+        (x, y)
+    }
+
+    /// convert a pixel position on the base map back to a real life location
+    pub fn unproject(&self, x: f64, y: f64) -> GeoPoint {
+        let b = &self.bounds;
+
+        let lon = b.west + x / self.width as f64 * (b.east - b.west);
+
+        let top = self.projection.project_lat(b.north);
+        let bottom = self.projection.project_lat(b.south);
+        let lat = self
+            .projection
+            .unproject_lat(top - y / self.height as f64 * (top - bottom));
+
+        GeoPoint::new(lat, lon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the georeference of the base map that's in the repo
+    fn base_map() -> Georeference {
+        serde_json::from_str(include_str!("../../img/eq-map.json")).unwrap()
+    }
+
+    #[test]
+    fn base_map_is_valid() {
+        assert_eq!(base_map().validate(), Ok(()));
+    }
+
+    #[test]
+    fn empty_or_backwards_georeferences_are_invalid() {
+        let b = base_map().bounds;
+        let invalid = [
+            Georeference { width: 0, ..base_map() },
+            Georeference { height: 0, ..base_map() },
+            Georeference {
+                bounds: BoundingBox { east: b.west, ..b },
+                ..base_map()
+            },
+            Georeference {
+                bounds: BoundingBox { north: b.south - 1.0, ..b },
+                ..base_map()
+            },
+            Georeference {
+                bounds: BoundingBox { west: f64::NAN, ..b },
+                ..base_map()
+            },
+            Georeference {
+                projection: Projection::WebMercator,
+                bounds: BoundingBox { north: 90.0, ..b },
+                ..base_map()
+            },
+        ];
+
+        for georef in invalid {
+            assert!(georef.validate().is_err(), "{:?}", georef);
+        }
+    }
+
+    #[test]
+    fn corners_of_the_bounds_are_corners_of_the_image() {
+        let georef = base_map();
+        let b = georef.bounds;
+
+        assert_eq!(georef.project(GeoPoint::new(b.north, b.west)), (0.0, 0.0));
+        assert_eq!(georef.project(GeoPoint::new(b.south, b.east)), (950.0, 1000.0));
+    }
+
+    #[test]
+    fn unproject_undoes_project() {
+        for projection in [Projection::Equirectangular, Projection::WebMercator] {
+            let georef = Georeference {
+                projection,
+                ..base_map()
+            };
+            let hualien = GeoPoint::new(23.9751, 121.6133);
+            let (x, y) = georef.project(hualien);
+            let back = georef.unproject(x, y);
+
+            assert!((back.lat - hualien.lat).abs() < 1e-9);
+            assert!((back.lon - hualien.lon).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use crate::taiwan_map::blend;
+use crate::taiwan_map::canvas::Canvas;
+use crate::taiwan_map::text;
+use ab_glyph::FontArc;
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+
+/// how many rows each pixel row is split into when working out how much of each
+/// pixel a shape covers (more is smoother but slower)
+const SUBSAMPLES: u32 = 5;
+/// width of `stroke_polygon` lines
+const STROKE_WIDTH: f64 = 1.0;
+
+/// draws straight onto the pixels of an image (for the png maps)
+pub struct RasterCanvas {
+    image: DynamicImage,
+}
+
+impl RasterCanvas {
+    /// draw on top of `image`
+    pub fn new(image: DynamicImage) -> Self {
+        Self { image }
+    }
+
+    /// the finished image
+    pub fn into_image(self) -> DynamicImage {
+        self.image
+    }
+
+    /// mix `color` into every pixel the mask covers
+    fn blend_coverage(&mut self, coverage: &Coverage, color: Rgba<u8>, opacity: f32) {
+        for (x, y, amount) in coverage.pixels() {
+            let blended = blend(self.image.get_pixel(x, y), color, amount * opacity);
+            self.image.put_pixel(x, y, blended);
+        }
+    }
+}
+
+impl Canvas for RasterCanvas {
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64, color: Rgba<u8>) {
+        let (pic_width, pic_height) = self.image.dimensions();
+
+        let start_x = x.round().max(0.0) as u32;
+        let end_x = (x + width).round().clamp(0.0, pic_width as f64) as u32;
+        let start_y = y.round().max(0.0) as u32;
+        let end_y = (y + height).round().clamp(0.0, pic_height as f64) as u32;
+
+        for px in start_x..end_x {
+            for py in start_y..end_y {
+                self.image.put_pixel(px, py, color);
+            }
+        }
+    }
+
+    /// the edges are anti-aliased, so pixels the edge goes through are partly filled
+    fn fill_polygon(&mut self, rings: &[Vec<(f64, f64)>], color: Rgba<u8>, opacity: f32) {
+        if let Some(mut coverage) = Coverage::around(rings, self.image.dimensions(), 0.0) {
+            coverage.add_polygon(rings);
+            self.blend_coverage(&coverage, color, opacity);
+        }
+    }
+
+    fn stroke_polygon(&mut self, rings: &[Vec<(f64, f64)>], color: Rgba<u8>) {
+        let half_width = STROKE_WIDTH / 2.0;
+        let dimensions = self.image.dimensions();
+        let mut coverage = match Coverage::around(rings, dimensions, STROKE_WIDTH) {
+            Some(coverage) => coverage,
+            None => return,
+        };
+
+        // each edge is a thin rectangle (a bit longer so the corners meet), and where
+        // they overlap the most covered one wins so the joins aren't darker
+        for ring in rings {
+            for (i, start) in ring.iter().enumerate() {
+                let end = ring[(i + 1) % ring.len()];
+                let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+                let length = (dx * dx + dy * dy).sqrt();
+                if length == 0.0 {
+                    continue;
+                }
+
+                let along = (dx / length * half_width, dy / length * half_width);
+                let across = (-along.1, along.0);
+                let edge = vec![
+                    (start.0 - along.0 + across.0, start.1 - along.1 + across.1),
+                    (end.0 + along.0 + across.0, end.1 + along.1 + across.1),
+                    (end.0 + along.0 - across.0, end.1 + along.1 - across.1),
+                    (start.0 - along.0 - across.0, start.1 - along.1 - across.1),
+                ];
+
+                let edge = [edge];
+                if let Some(mut edge_coverage) = Coverage::around(&edge, dimensions, 0.0) {
+                    edge_coverage.add_polygon(&edge);
+                    coverage.max_with(&edge_coverage);
+                }
+            }
+        }
+
+        self.blend_coverage(&coverage, color, 1.0);
+    }
+
+    fn draw_image(&mut self, image: &DynamicImage, x: f64, y: f64) {
+        let (pic_width, pic_height) = self.image.dimensions();
+        let (x, y) = (x.round() as i64, y.round() as i64);
+
+        for (image_x, image_y, pixel) in image.pixels() {
+            let (map_x, map_y) = (x + image_x as i64, y + image_y as i64);
+            if map_x < 0 || map_y < 0 || map_x >= pic_width as i64 || map_y >= pic_height as i64 {
+                continue;
+            }
+
+            // transparent pixels are skipped, and partly transparent ones are mixed in
+            if pixel[3] == 0 {
+                continue;
+            }
+            let (map_x, map_y) = (map_x as u32, map_y as u32);
+            let blended = blend(self.image.get_pixel(map_x, map_y), pixel, 1.0);
+            self.image.put_pixel(map_x, map_y, blended);
+        }
+    }
+
+    fn draw_text(&mut self, font: &FontArc, text: &str, x: f64, y: f64, size: f32, color: Rgba<u8>) {
+        text::draw_text(&mut self.image, font, text, x, y, size, color);
+    }
+}
+
+/// how much (0 to 1) of each pixel of a `width` x `height` image the polygons (each one
+/// a list of rings) cover, row by row. where they overlap the most covered one wins
+pub fn polygon_coverage(polygons: &[Vec<Vec<(f64, f64)>>], (width, height): (u32, u32)) -> Vec<f32> {
+    let mut amounts = vec![0.0; (width * height) as usize];
+    for rings in polygons {
+        if let Some(mut coverage) = Coverage::around(rings, (width, height), 0.0) {
+            coverage.add_polygon(rings);
+            for (x, y, amount) in coverage.pixels() {
+                let index = (y * width + x) as usize;
+                amounts[index] = f32::max(amounts[index], amount);
+            }
+        }
+    }
+
+    amounts
+}
+
+/// how much (0 to 1) of each pixel in a rectangle of the image a shape covers
+struct Coverage {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    amounts: Vec<f32>,
+}
+
+impl Coverage {
+    /// an empty mask big enough for the rings (plus `padding`), cut down to the image.
+    /// None if the rings are completely off the image
+    fn around(rings: &[Vec<(f64, f64)>], (pic_width, pic_height): (u32, u32), padding: f64) -> Option<Self> {
+        let points = || rings.iter().flatten();
+        let min_x = points().map(|p| p.0).fold(f64::MAX, f64::min) - padding;
+        let max_x = points().map(|p| p.0).fold(f64::MIN, f64::max) + padding;
+        let min_y = points().map(|p| p.1).fold(f64::MAX, f64::min) - padding;
+        let max_y = points().map(|p| p.1).fold(f64::MIN, f64::max) + padding;
+
+        let x = min_x.floor().max(0.0);
+        let y = min_y.floor().max(0.0);
+        let end_x = max_x.ceil().min(pic_width as f64);
+        let end_y = max_y.ceil().min(pic_height as f64);
+        if end_x <= x || end_y <= y {
+            return None;
+        }
+
+        let (width, height) = ((end_x - x) as u32, (end_y - y) as u32);
+        Some(Self {
+            x: x as u32,
+            y: y as u32,
+            width,
+            height,
+            amounts: vec![0.0; (width * height) as usize],
+        })
+    }
+
+    /// add the area inside `rings` (even-odd, so a ring inside another is a hole)
+    ///
+    /// each pixel row is split into `SUBSAMPLES` thin rows, and each of those adds
+    /// exactly how much of each pixel is between its edge crossings
+    fn add_polygon(&mut self, rings: &[Vec<(f64, f64)>]) {
+        let left = self.x as f64;
+        let right = (self.x + self.width) as f64;
+
+        for row in 0..self.height {
+            for sub in 0..SUBSAMPLES {
+                let scan_y = (self.y + row) as f64 + (sub as f64 + 0.5) / SUBSAMPLES as f64;
+
+                // everywhere the thin row crosses an edge
+                let mut crossings: Vec<f64> = Vec::new();
+                for ring in rings {
+                    for (i, a) in ring.iter().enumerate() {
+                        let b = ring[(i + 1) % ring.len()];
+                        if (a.1 > scan_y) != (b.1 > scan_y) {
+                            let crossing = a.0 + (scan_y - a.1) * (b.0 - a.0) / (b.1 - a.1);
+                            // a NaN or infinite point (from a bad projection) can't be filled
+                            if crossing.is_finite() {
+                                crossings.push(crossing);
+                            }
+                        }
+                    }
+                }
+                crossings.sort_by(f64::total_cmp);
+
+                for span in crossings.chunks(2) {
+                    if let [start, end] = span {
+                        let (start, end) = (start.max(left), end.min(right));
+                        if end <= start {
+                            continue;
+                        }
+
+                        for px in start.floor() as u32..end.ceil() as u32 {
+                            let overlap = end.min(px as f64 + 1.0) - start.max(px as f64);
+                            let index = (row * self.width + px - self.x) as usize;
+                            self.amounts[index] += overlap as f32 / SUBSAMPLES as f32;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// keep the bigger amount of each pixel (anything in `other` outside this mask is
+    /// ignored)
+    fn max_with(&mut self, other: &Coverage) {
+        for (x, y, other_amount) in other.pixels() {
+            if x < self.x || y < self.y || x >= self.x + self.width || y >= self.y + self.height {
+                continue;
+            }
+
+            let index = ((y - self.y) * self.width + x - self.x) as usize;
+            self.amounts[index] = self.amounts[index].max(other_amount);
+        }
+    }
+
+    /// every pixel that's at least partly covered, with how much (0 to 1)
+    fn pixels(&self) -> impl Iterator<Item = (u32, u32, f32)> + '_ {
+        self.amounts
+            .iter()
+            .enumerate()
+            .filter(|(_, amount)| **amount > 0.0)
+            .map(move |(i, amount)| {
+                let i = i as u32;
+                (self.x + i % self.width, self.y + i / self.width, amount.min(1.0))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn blank_canvas(width: u32, height: u32) -> RasterCanvas {
+        RasterCanvas::new(DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(width, height, WHITE)))
+    }
+
+    /// every red pixel, left to right then top to bottom
+    fn red_pixels(canvas: RasterCanvas) -> Vec<(u32, u32)> {
+        let image = canvas.into_image();
+        let mut red: Vec<(u32, u32)> = image.pixels().filter(|(_, _, pixel)| *pixel == RED).map(|(x, y, _)| (x, y)).collect();
+        red.sort_by_key(|(x, y)| (*y, *x));
+        red
+    }
+
+    /// every pixel from (x, y) up to but not including (end_x, end_y), in the same order
+    fn pixels_between(x: u32, y: u32, end_x: u32, end_y: u32) -> Vec<(u32, u32)> {
+        (y..end_y).flat_map(|py| (x..end_x).map(move |px| (px, py))).collect()
+    }
+
+    fn red_square(size: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(size, size, RED))
+    }
+
+    #[test]
+    fn fill_rect_in_the_corners() {
+        let mut canvas = blank_canvas(10, 10);
+        canvas.fill_rect(0.0, 0.0, 3.0, 3.0, RED);
+        assert_eq!(red_pixels(canvas), pixels_between(0, 0, 3, 3));
+
+        let mut canvas = blank_canvas(10, 10);
+        canvas.fill_rect(9.0, 9.0, 3.0, 3.0, RED);
+        assert_eq!(red_pixels(canvas), vec![(9, 9)]);
+    }
+
+    #[test]
+    fn fill_rect_is_clipped_past_the_edges() {
+        // hanging off the top left, and off the right and bottom edges
+        let mut canvas = blank_canvas(10, 10);
+        canvas.fill_rect(-2.0, -2.0, 4.0, 4.0, RED);
+        assert_eq!(red_pixels(canvas), pixels_between(0, 0, 2, 2));
+
+        let mut canvas = blank_canvas(10, 10);
+        canvas.fill_rect(8.0, -5.0, 10.0, 7.0, RED);
+        assert_eq!(red_pixels(canvas), pixels_between(8, 0, 10, 2));
+
+        // completely off the image, on every side
+        for (x, y) in [(-20.0, 0.0), (0.0, -20.0), (10.0, 0.0), (0.0, 10.0), (30.0, 30.0)] {
+            let mut canvas = blank_canvas(10, 10);
+            canvas.fill_rect(x, y, 5.0, 5.0, RED);
+            assert_eq!(red_pixels(canvas), Vec::new(), "at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn draw_image_in_the_corners() {
+        let mut canvas = blank_canvas(10, 10);
+        canvas.draw_image(&red_square(3), 0.0, 0.0);
+        assert_eq!(red_pixels(canvas), pixels_between(0, 0, 3, 3));
+
+        let mut canvas = blank_canvas(10, 10);
+        canvas.draw_image(&red_square(3), 9.0, 9.0);
+        assert_eq!(red_pixels(canvas), vec![(9, 9)]);
+    }
+
+    #[test]
+    fn draw_image_is_clipped_past_the_edges() {
+        let mut canvas = blank_canvas(10, 10);
+        canvas.draw_image(&red_square(4), -2.0, -3.0);
+        assert_eq!(red_pixels(canvas), pixels_between(0, 0, 2, 1));
+
+        let mut canvas = blank_canvas(10, 10);
+        canvas.draw_image(&red_square(4), -1.0, 8.0);
+        assert_eq!(red_pixels(canvas), pixels_between(0, 8, 3, 10));
+
+        for (x, y) in [(-4.0, 0.0), (0.0, -4.0), (10.0, 0.0), (0.0, 10.0), (-30.0, 30.0)] {
+            let mut canvas = blank_canvas(10, 10);
+            canvas.draw_image(&red_square(4), x, y);
+            assert_eq!(red_pixels(canvas), Vec::new(), "at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn polygon_with_a_nan_point_does_not_panic() {
+        let mut canvas = blank_canvas(20, 20);
+        let square = vec![(2.0, 2.0), (10.0, 2.0), (10.0, 10.0), (2.0, 10.0)];
+        let broken = vec![(2.0, 2.0), (f64::NAN, 5.0), (10.0, 10.0), (2.0, 10.0)];
+
+        canvas.fill_polygon(&[square, broken.clone()], RED, 1.0);
+        canvas.stroke_polygon(&[broken], RED);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::cwb_api::intensity::IntensityConversionErrors;
+use std::fmt;
+
+/// a station whose intensity string couldn't be read
+pub struct UnreadableStation {
+    pub station_name: String,
+    pub station_intensity: String,
+    pub error: IntensityConversionErrors,
+}
+
+/// what happened to each station while drawing one earthquake's map
+/// (printed by the dry run so odd CWB data is easy to spot)
+///
+/// for the counties style, `counties_filled` and `unknown_counties` (names CWB used that
+/// aren't in the county file) are filled in instead of the station counts
+#[derive(Default)]
+pub struct RenderReport {
+    pub drawn: usize,
+    pub counties_filled: usize,
+    pub unknown_counties: Vec<String>,
+    pub hidden_zero: usize,
+    pub omitted_low: usize,
+    pub off_map: usize,
+    pub epicenter_off_map: bool,
+    pub unreadable: Vec<UnreadableStation>,
+}
+
+impl fmt::Display for RenderReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "stations: {} drawn, {} intensity 0 hidden, {} low intensity omitted, {} off the map",
+            self.drawn, self.hidden_zero, self.omitted_low, self.off_map
+        )?;
+
+        if self.counties_filled > 0 || !self.unknown_counties.is_empty() {
+            writeln!(f, "counties: {} filled", self.counties_filled)?;
+        }
+        if !self.unknown_counties.is_empty() {
+            writeln!(f, "counties not on the map: {}", self.unknown_counties.join(", "))?;
+        }
+
+        if self.epicenter_off_map {
+            writeln!(f, "the epicenter is off the map (drew an arrow instead)")?;
+        }
+
+        if !self.unreadable.is_empty() {
+            writeln!(f, "couldn't read {} station intensities:", self.unreadable.len())?;
+        }
+        for station in &self.unreadable {
+            writeln!(
+                f,
+                "  {} \"{}\": {}",
+                station.station_name, station.station_intensity, station.error
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::cwb_api::structs::Earthquake;
+use crate::taiwan_map::canvas::Canvas;
+use crate::taiwan_map::markers::readable_text_color;
+use crate::taiwan_map::text::{text_ascent, text_width};
+use crate::taiwan_map::view::View;
+use crate::time::CwbTime;
+use ab_glyph::FontArc;
+use image::Rgba;
+use std::f64::consts::PI;
+
+/// the smallest and biggest an epicenter circle can be (pixels)
+const MIN_EPICENTER_RADIUS: f64 = 10.0;
+const MAX_EPICENTER_RADIUS: f64 = 28.0;
+const EPICENTER_BORDER: f64 = 2.0;
+/// points around each circle
+const CIRCLE_POINTS: usize = 40;
+
+/// the time colors go from pale yellow (first) through orange to dark red (last)
+const TIME_COLORS: [[f64; 3]; 3] = [[255.0, 237.0, 160.0], [253.0, 141.0, 60.0], [189.0, 0.0, 38.0]];
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// how big an epicenter's circle is for `magnitude` (bigger earthquakes get bigger
+/// circles, and even the smallest has room for its number)
+pub fn magnitude_radius(magnitude: f64) -> f64 {
+    (3.5 * magnitude - 2.0).clamp(MIN_EPICENTER_RADIUS, MAX_EPICENTER_RADIUS)
+}
+
+/// the color for an earthquake `fraction` (0 to 1) of the way from the first
+/// earthquake to the last
+pub fn time_color(fraction: f64) -> Rgba<u8> {
+    let scaled = fraction.clamp(0.0, 1.0) * (TIME_COLORS.len() - 1) as f64;
+    let i = (scaled as usize).min(TIME_COLORS.len() - 2);
+    let t = scaled - i as f64;
+    let mix = |c: usize| (TIME_COLORS[i][c] + (TIME_COLORS[i + 1][c] - TIME_COLORS[i][c]) * t).round() as u8;
+
+    Rgba([mix(0), mix(1), mix(2), 255])
+}
+
+/// how far (0 to 1) each earthquake is from the first one to the last one in time
+/// (they're all 1 if they happened at the same time)
+pub fn time_fractions(earthquakes: &[Earthquake]) -> Vec<f64> {
+    let times: Vec<_> = earthquakes
+        .iter()
+        .map(|eq| CwbTime::new_time_string_without_t(&eq.earthquake_info.origin_time).get_date_time())
+        .collect();
+    let first = match times.iter().min() {
+        Some(first) => *first,
+        None => return Vec::new(),
+    };
+    let span = (*times.iter().max().unwrap() - first).num_seconds() as f64;
+
+    times
+        .iter()
+        .map(|time| {
+            if span > 0.0 {
+                (*time - first).num_seconds() as f64 / span
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+/// draw a numbered circle for each earthquake's epicenter (`earthquakes` should be
+/// oldest first, and that's the numbering). the circles are sized by magnitude and
+/// colored by time, and the biggest ones are drawn first so the small ones stay on top
+///
+/// returns how many epicenters weren't on the map
+pub fn draw_summary_epicenters(
+    canvas: &mut impl Canvas,
+    font: &FontArc,
+    view: &View,
+    earthquakes: &[Earthquake],
+) -> usize {
+    let fractions = time_fractions(earthquakes);
+    let (pic_width, pic_height) = canvas.dimensions();
+
+    let mut order: Vec<usize> = (0..earthquakes.len()).collect();
+    order.sort_by(|a, b| {
+        let magnitude = |i: usize| earthquakes[i].earthquake_info.earthquake_magnitude.magnitude_value;
+        magnitude(*b).partial_cmp(&magnitude(*a)).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut off_map = 0;
+    for i in order {
+        let info = &earthquakes[i].earthquake_info;
+        let center = view.project(info.epicenter.point());
+        if center.0 < 0.0 || center.1 < 0.0 || center.0 >= pic_width as f64 || center.1 >= pic_height as f64 {
+            off_map += 1;
+            continue;
+        }
+
+        let radius = magnitude_radius(info.earthquake_magnitude.magnitude_value);
+        let color = time_color(fractions[i]);
+        draw_numbered_circle(canvas, font, center, radius, color, i + 1);
+    }
+
+    off_map
+}
+
+/// a circle with a black edge and `number` written in the middle
+fn draw_numbered_circle(
+    canvas: &mut impl Canvas,
+    font: &FontArc,
+    center: (f64, f64),
+    radius: f64,
+    color: Rgba<u8>,
+    number: usize,
+) {
+    draw_circle(canvas, center, radius, color);
+
+    let label = number.to_string();
+    let size = radius.clamp(12.0, 20.0) as f32;
+    let label_width = text_width(font, &label, size);
+    // digits are about 0.72 of the font size tall, so put their middle on the center
+    let baseline = center.1 + size as f64 * 0.36;
+    canvas.draw_text(
+        font,
+        &label,
+        center.0 - label_width / 2.0,
+        baseline - text_ascent(font, size),
+        size,
+        readable_text_color(color),
+    );
+}
+
+/// a circle filled with `color`, with a black edge
+pub fn draw_circle(canvas: &mut impl Canvas, center: (f64, f64), radius: f64, color: Rgba<u8>) {
+    canvas.fill_polygon(&[circle_points(center, radius)], BLACK, 1.0);
+    canvas.fill_polygon(&[circle_points(center, radius - EPICENTER_BORDER)], color, 1.0);
+}
+
+fn circle_points(center: (f64, f64), radius: f64) -> Vec<(f64, f64)> {
+    (0..CIRCLE_POINTS)
+        .map(|i| {
+            let angle = 2.0 * PI * i as f64 / CIRCLE_POINTS as f64;
+            (center.0 + radius * angle.cos(), center.1 + radius * angle.sin())
+        })
+        .collect()
+}
@@ -0,0 +1,157 @@
+use crate::taiwan_map::canvas::Canvas;
+use crate::taiwan_map::encode_png;
+use crate::xml::escape_xml;
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use image::{DynamicImage, GenericImageView, Rgba};
+use log::warn;
+use std::fmt::Write;
+
+/// fonts asked for in the svg, first one that's installed wins. the layout is measured
+/// with the map font, so the text lines up best when that font is one of these
+const SVG_FONT_FAMILY: &str = "'Noto Sans TC', 'Noto Sans CJK TC', 'Microsoft JhengHei', 'PingFang TC', sans-serif";
+
+/// builds an svg of the map (for the website and printing)
+///
+/// the base map and epicenter icon are still pictures, so they're embedded as pngs,
+/// but everything drawn on top of them is a shape or text
+pub struct SvgCanvas {
+    width: u32,
+    height: u32,
+    body: String,
+}
+
+impl SvgCanvas {
+    /// an empty svg the same size as the png map would be
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            body: String::new(),
+        }
+    }
+
+    /// an svg with `background` (the cropped base map) already drawn on it
+    pub fn with_background(background: DynamicImage) -> Self {
+        let (width, height) = background.dimensions();
+        let mut canvas = Self::new(width, height);
+        canvas.draw_image(&background, 0.0, 0.0);
+
+        canvas
+    }
+
+    /// the finished svg document
+    pub fn finish(self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+             width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n{body}</svg>\n",
+            w = self.width,
+            h = self.height,
+            body = self.body
+        )
+    }
+}
+
+impl Canvas for SvgCanvas {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64, color: Rgba<u8>) {
+        // writing to a String can't fail
+        let _ = writeln!(
+            self.body,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" {}/>",
+            x,
+            y,
+            width,
+            height,
+            fill(color, 1.0)
+        );
+    }
+
+    fn fill_polygon(&mut self, rings: &[Vec<(f64, f64)>], color: Rgba<u8>, opacity: f32) {
+        let _ = writeln!(
+            self.body,
+            "<path d=\"{}\" fill-rule=\"evenodd\" {}/>",
+            path_data(rings),
+            fill(color, opacity)
+        );
+    }
+
+    fn stroke_polygon(&mut self, rings: &[Vec<(f64, f64)>], color: Rgba<u8>) {
+        let _ = writeln!(
+            self.body,
+            "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1\"/>",
+            path_data(rings),
+            rgb(color)
+        );
+    }
+
+    fn draw_image(&mut self, image: &DynamicImage, x: f64, y: f64) {
+        let png = match encode_png(image) {
+            Ok(png) => png,
+            Err(e) => {
+                warn!("couldn't add an image to the svg: {}", e);
+                return;
+            }
+        };
+
+        let (width, height) = image.dimensions();
+        let _ = writeln!(
+            self.body,
+            "<image x=\"{:.1}\" y=\"{:.1}\" width=\"{}\" height=\"{}\" xlink:href=\"data:image/png;base64,{}\"/>",
+            x,
+            y,
+            width,
+            height,
+            BASE64.encode(png)
+        );
+    }
+
+    fn draw_text(&mut self, font: &FontArc, text: &str, x: f64, y: f64, size: f32, color: Rgba<u8>) {
+        // svg puts text on its baseline, not its top
+        let baseline = y + font.as_scaled(PxScale::from(size)).ascent() as f64;
+
+        let _ = writeln!(
+            self.body,
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-family=\"{}\" font-size=\"{}\" {} xml:space=\"preserve\">{}</text>",
+            x,
+            baseline,
+            SVG_FONT_FAMILY,
+            size,
+            fill(color, 1.0),
+            escape_xml(text)
+        );
+    }
+}
+
+/// the rings as an svg path (each one closed)
+fn path_data(rings: &[Vec<(f64, f64)>]) -> String {
+    let mut data = String::new();
+    for ring in rings {
+        for (i, (x, y)) in ring.iter().enumerate() {
+            let command = if i == 0 { 'M' } else { 'L' };
+            let _ = write!(data, "{}{:.1},{:.1} ", command, x, y);
+        }
+        data.push_str("Z ");
+    }
+
+    data.trim_end().to_string()
+}
+
+fn rgb(color: Rgba<u8>) -> String {
+    format!("rgb({},{},{})", color[0], color[1], color[2])
+}
+
+/// fill attributes for `color`, including its alpha
+fn fill(color: Rgba<u8>, opacity: f32) -> String {
+    let opacity = opacity.clamp(0.0, 1.0) * color[3] as f32 / 255.0;
+    if opacity >= 1.0 {
+        format!("fill=\"{}\"", rgb(color))
+    } else {
+        format!("fill=\"{}\" fill-opacity=\"{:.2}\"", rgb(color), opacity)
+    }
+}
+
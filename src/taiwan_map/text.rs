@@ -0,0 +1,70 @@
+use crate::taiwan_map::blend;
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+
+/// draw `text` with its top left corner at x, y
+/// the glyph edges are blended into the map so the text is smooth
+pub fn draw_text(
+    map_image: &mut DynamicImage,
+    font: &FontArc,
+    text: &str,
+    x: f64,
+    y: f64,
+    size: f32,
+    color: Rgba<u8>,
+) {
+    let (pic_width, pic_height) = map_image.dimensions();
+    let scaled = font.as_scaled(PxScale::from(size));
+
+    let mut caret = point(x as f32, y as f32 + scaled.ascent());
+    let mut last_glyph = None;
+
+    for c in text.chars() {
+        let glyph_id = scaled.glyph_id(c);
+        if let Some(last) = last_glyph {
+            caret.x += scaled.kern(last, glyph_id);
+        }
+        let glyph = glyph_id.with_scale_and_position(size, caret);
+        caret.x += scaled.h_advance(glyph_id);
+        last_glyph = Some(glyph_id);
+
+        if let Some(outline) = font.outline_glyph(glyph) {
+            let bounds = outline.px_bounds();
+
+            outline.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i32 + gx as i32;
+                let py = bounds.min.y as i32 + gy as i32;
+                if px < 0 || py < 0 || px >= pic_width as i32 || py >= pic_height as i32 {
+                    return;
+                }
+
+                let (px, py) = (px as u32, py as u32);
+                let blended = blend(map_image.get_pixel(px, py), color, coverage);
+                map_image.put_pixel(px, py, blended);
+            });
+        }
+    }
+}
+
+/// how wide `text` will be when drawn
+pub fn text_width(font: &FontArc, text: &str, size: f32) -> f64 {
+    let scaled = font.as_scaled(PxScale::from(size));
+
+    let mut width = 0.0;
+    let mut last_glyph = None;
+    for c in text.chars() {
+        let glyph_id = scaled.glyph_id(c);
+        if let Some(last) = last_glyph {
+            width += scaled.kern(last, glyph_id);
+        }
+        width += scaled.h_advance(glyph_id);
+        last_glyph = Some(glyph_id);
+    }
+
+    width as f64
+}
+
+/// how far the top of the text box is above the baseline
+pub fn text_ascent(font: &FontArc, size: f32) -> f64 {
+    font.as_scaled(PxScale::from(size)).ascent() as f64
+}
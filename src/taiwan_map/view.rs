@@ -0,0 +1,230 @@
+use crate::geo::GeoPoint;
+use crate::taiwan_map::projection::Georeference;
+use image::imageops::{self, FilterType};
+use image::DynamicImage;
+
+/// extra base map pixels kept around the epicenter and stations when zooming
+const ZOOM_PADDING: f64 = 80.0;
+/// never zoom in so far that less than this much of the base map's width is shown
+const MIN_ZOOM_FRACTION: f64 = 0.4;
+/// if the zoom window would show more than this much of the base map, just show all of it
+const MAX_ZOOM_FRACTION: f64 = 0.8;
+
+/// the part of the base map that ends up in the rendered image
+///
+/// the rendered image is always the same size as the base map, so a zoomed view is a
+/// smaller window of the base map that gets scaled up to fill it. the window is in whole
+/// base map pixels, so what's projected through the view lines up with the cropped map
+pub struct View {
+    georef: Georeference,
+    /// top left corner of the window (base map pixels)
+    x: f64,
+    y: f64,
+    /// size of the window (base map pixels)
+    width: f64,
+    height: f64,
+}
+
+impl View {
+    /// show the whole base map
+    pub fn full(georef: &Georeference) -> Self {
+        Self {
+            georef: georef.clone(),
+            x: 0.0,
+            y: 0.0,
+            width: georef.width as f64,
+            height: georef.height as f64,
+        }
+    }
+
+    /// choose a window that fits all of the `focus` points (epicenter and felt stations)
+    ///
+    /// points that aren't on the base map are ignored, and if the points are spread
+    /// over most of Taiwan anyway this is just the full view
+    pub fn around(georef: &Georeference, focus: &[GeoPoint]) -> Self {
+        let map_width = georef.width as f64;
+        let map_height = georef.height as f64;
+
+        let on_map: Vec<(f64, f64)> = focus
+            .iter()
+            .map(|point| georef.project(*point))
+            .filter(|(x, y)| (0.0..map_width).contains(x) && (0.0..map_height).contains(y))
+            .collect();
+
+        if on_map.is_empty() {
+            return Self::full(georef);
+        }
+
+        let min_x = on_map.iter().map(|p| p.0).fold(f64::MAX, f64::min) - ZOOM_PADDING;
+        let max_x = on_map.iter().map(|p| p.0).fold(f64::MIN, f64::max) + ZOOM_PADDING;
+        let min_y = on_map.iter().map(|p| p.1).fold(f64::MAX, f64::min) - ZOOM_PADDING;
+        let max_y = on_map.iter().map(|p| p.1).fold(f64::MIN, f64::max) + ZOOM_PADDING;
+
+        // grow the window to the same shape as the base map so nothing gets stretched
+        let mut width = (max_x - min_x).max(map_width * MIN_ZOOM_FRACTION);
+        let mut height = (max_y - min_y).max(map_height * MIN_ZOOM_FRACTION);
+        if width / height < map_width / map_height {
+            width = height * map_width / map_height;
+        } else {
+            height = width * map_height / map_width;
+        }
+
+        if width >= map_width * MAX_ZOOM_FRACTION {
+            return Self::full(georef);
+        }
+
+        // center the window on the points, but slide it back onto the map if it hangs off
+        // (all in whole pixels, since that's what gets cropped)
+        let (width, height) = (width.round(), height.round());
+        let x = ((min_x + max_x) / 2.0 - width / 2.0).round().clamp(0.0, map_width - width);
+        let y = ((min_y + max_y) / 2.0 - height / 2.0).round().clamp(0.0, map_height - height);
+
+        Self {
+            georef: georef.clone(),
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// true if this only shows part of the base map
+    pub fn is_zoomed(&self) -> bool {
+        self.width < self.georef.width as f64
+    }
+
+    /// how much bigger the base map is drawn in this view, across and down (the window
+    /// is rounded, so they can be a tiny bit different)
+    fn scale(&self) -> (f64, f64) {
+        (
+            self.georef.width as f64 / self.width,
+            self.georef.height as f64 / self.height,
+        )
+    }
+
+    /// the window in base map pixels (x, y, width, height)
+    pub fn window(&self) -> (f64, f64, f64, f64) {
+        (self.x, self.y, self.width, self.height)
+    }
+
+    /// where a real life location ends up in the rendered image
+    /// (not clamped, so it can be off the image)
+    pub fn project(&self, point: GeoPoint) -> (f64, f64) {
+        let (x, y) = self.georef.project(point);
+        let (scale_x, scale_y) = self.scale();
+
+        ((x - self.x) * scale_x, (y - self.y) * scale_y)
+    }
+
+    /// the real life location of a pixel in the rendered image
+    pub fn unproject(&self, x: f64, y: f64) -> GeoPoint {
+        let (x, y) = self.to_base_map(x, y);
+        self.georef.unproject(x, y)
+    }
+
+    /// where a pixel of the rendered image is on the base map
+    pub fn to_base_map(&self, x: f64, y: f64) -> (f64, f64) {
+        let (scale_x, scale_y) = self.scale();
+
+        (x / scale_x + self.x, y / scale_y + self.y)
+    }
+
+    /// size of the rendered image
+    pub fn output_size(&self) -> (u32, u32) {
+        (self.georef.width, self.georef.height)
+    }
+
+    /// cut this view's window out of the base map and scale it up to the output size
+    /// (the window is already whole pixels, so `as` doesn't cut anything off)
+    pub fn crop_base_map(&self, base_map: &DynamicImage) -> DynamicImage {
+        if !self.is_zoomed() {
+            return base_map.clone();
+        }
+
+        let (out_width, out_height) = self.output_size();
+        let window = imageops::crop_imm(
+            base_map,
+            self.x as u32,
+            self.y as u32,
+            self.width as u32,
+            self.height as u32,
+        )
+        .to_image();
+
+        DynamicImage::ImageRgba8(imageops::resize(
+            &window,
+            out_width,
+            out_height,
+            FilterType::CatmullRom,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    /// the georeference of the base map that's in the repo
+    fn base_map() -> Georeference {
+        serde_json::from_str(include_str!("../../img/eq-map.json")).unwrap()
+    }
+
+    /// the smallest box around the pixels that are mostly red (left, top, right, bottom)
+    fn red_area(image: &DynamicImage) -> (u32, u32, u32, u32) {
+        let red: Vec<(u32, u32)> = image
+            .pixels()
+            .filter(|(_, _, pixel)| pixel[0] > 128 && pixel[1] < 128)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+
+        (
+            red.iter().map(|p| p.0).min().unwrap(),
+            red.iter().map(|p| p.1).min().unwrap(),
+            red.iter().map(|p| p.0).max().unwrap(),
+            red.iter().map(|p| p.1).max().unwrap(),
+        )
+    }
+
+    #[test]
+    fn window_is_whole_base_map_pixels() {
+        let georef = base_map();
+        let view = View::around(&georef, &[GeoPoint::new(23.9751, 121.6133)]);
+        assert!(view.is_zoomed());
+
+        let (x, y, width, height) = view.window();
+        for value in [x, y, width, height] {
+            assert_eq!(value, value.round());
+        }
+    }
+
+    #[test]
+    fn station_lands_on_the_same_spot_in_the_zoomed_and_full_maps() {
+        // a white base map with a red 10x10 block whose top left corner is the pixel
+        // CWB's Hualien station is on
+        let georef = base_map();
+        let hualien = GeoPoint::new(23.9751, 121.6133);
+        let (station_x, station_y) = georef.project(hualien);
+        let (block_x, block_y) = (station_x.floor() as u32, station_y.floor() as u32);
+        let mut image = RgbaImage::from_pixel(georef.width, georef.height, Rgba([255, 255, 255, 255]));
+        for x in block_x..block_x + 10 {
+            for y in block_y..block_y + 10 {
+                image.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+            }
+        }
+        let base = DynamicImage::ImageRgba8(image);
+
+        for view in [View::full(&georef), View::around(&georef, &[hualien])] {
+            // the block's corner (the station's pixel) is projected onto the corner of the
+            // block in the cropped map. the window is whole pixels, so that's a whole
+            // pixel too, and the station is inside the block
+            let (left, top, _, _) = red_area(&view.crop_base_map(&base));
+            let corner = view.project(georef.unproject(block_x as f64, block_y as f64));
+            let (station_x, station_y) = view.project(hualien);
+
+            assert!((left as f64 - corner.0).abs() < 0.25, "{} {:?}", left, corner);
+            assert!((top as f64 - corner.1).abs() < 0.25, "{} {:?}", top, corner);
+            assert!(station_x >= left as f64 && station_y >= top as f64);
+        }
+    }
+}
@@ -0,0 +1,579 @@
+use crate::cwb_api::intensity::SeismicIntensity;
+use crate::cwb_api::structs::Earthquake;
+use crate::taiwan_map::county_intensities;
+use crate::time::CwbTime;
+use chrono::NaiveDate;
+use std::fmt::Write;
+
+/// the longest a post can be (Twitter's limit, counted with `post_length`)
+const MAX_POST_LENGTH: usize = 280;
+/// how long Twitter counts every link as, however long it is (it shortens them to t.co)
+const LINK_LENGTH: usize = 23;
+/// what the end of a post that was too long is cut to
+const ELLIPSIS: char = '…';
+/// how {time} is written if the template doesn't say (ex: 2024-04-03 07:58:09)
+const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+/// every placeholder, for the error when a template has one that doesn't exist
+const FIELD_NAMES: &str = "time, magnitude, depth, lat, lon, location, report, max_intensity, intensities, link, hashtags";
+/// the comparisons an {if} can have (the two character ones first, so >= isn't read as >)
+const COMPARISONS: [(&str, Comparison); 6] = [
+    (">=", Comparison::AtLeast),
+    ("<=", Comparison::AtMost),
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    (">", Comparison::Greater),
+    ("<", Comparison::Less),
+];
+
+/// the text of a post, with placeholders for the earthquake's details and parts that
+/// are only there sometimes
+///
+/// a placeholder is a name in braces (ex: {magnitude}):
+/// * time - the origin time (Taiwan time). it can have a strftime format after a colon,
+///   like {time:%H:%M on %b %-d}
+/// * magnitude, depth (km), lat, lon - numbers
+/// * location - where the epicenter was, from CWB (in Chinese)
+/// * report - CWB's report (the post is cut to length like any other)
+/// * max_intensity - the strongest shaking in any county (ex: 5-), empty if there wasn't any
+/// * intensities - each county that felt it and how strongly, strongest first
+///   (ex: 花蓮縣 6+, 宜蘭縣 5-)
+/// * link - CWB's page about the earthquake
+/// * hashtags - the profile's hashtags
+///
+/// {if magnitude >= 6}...{end} (or {if ...}...{else}...{end}) is only there when the
+/// condition is true. magnitude, depth and max_intensity can be compared (with >=,
+/// <=, >, <, == or !=), and {if name} on its own checks that the placeholder isn't
+/// empty. {{ and }} are braces, and \n is a new line
+///
+/// a post that's longer than Twitter's 280 is cut short, with … at the end (Chinese
+/// characters count as 2, and links as 23)
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// a piece of a template
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(Field),
+    If {
+        condition: Condition,
+        then: Vec<Part>,
+        otherwise: Vec<Part>,
+    },
+}
+
+/// a placeholder (the time has its strftime format)
+#[derive(Debug, Clone)]
+enum Field {
+    Time(String),
+    Magnitude,
+    Depth,
+    Lat,
+    Lon,
+    Location,
+    Report,
+    MaxIntensity,
+    Intensities,
+    Link,
+    Hashtags,
+}
+
+/// what an {if} checks
+#[derive(Debug, Clone)]
+enum Condition {
+    /// the placeholder isn't empty
+    NotEmpty(Field),
+    /// the number compares to the value (false if the earthquake doesn't have it)
+    Compare(Number, Comparison, f64),
+}
+
+/// how an {if} compares a number
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    AtLeast,
+    AtMost,
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+}
+
+/// a placeholder that can be compared. max_intensity is compared by its place on the
+/// scale, so {if max_intensity >= 5-} works as expected
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Magnitude,
+    Depth,
+    MaxIntensity,
+}
+
+/// a template after its escapes are read: text, or what was in a pair of braces
+enum Token {
+    Text(String),
+    Tag(String),
+}
+
+/// how a run of parts ended
+enum BlockEnd {
+    End,
+    Else,
+    Finished,
+}
+
+/// what a placeholder gets filled in from, worked out once per post
+struct Values<'a> {
+    eq: &'a Earthquake,
+    hashtags: &'a str,
+    /// every county's strongest shaking, strongest first
+    intensities: Vec<(String, SeismicIntensity)>,
+}
+
+impl Template {
+    /// read a template, or say what's wrong with it
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut tokens = tokenize(template)?.into_iter();
+
+        match parse_parts(&mut tokens)? {
+            (parts, BlockEnd::Finished) => Ok(Self { parts }),
+            (_, BlockEnd::Else) => Err("there's an {else} without an {if}".to_string()),
+            (_, BlockEnd::End) => Err("there's an {end} without an {if}".to_string()),
+        }
+    }
+
+    /// the post for `eq`, with `hashtags` for {hashtags} (at most `MAX_POST_LENGTH` long)
+    pub fn render(&self, eq: &Earthquake, hashtags: &str) -> String {
+        let mut intensities: Vec<(String, SeismicIntensity)> = county_intensities(eq).into_iter().collect();
+        intensities.sort_by(|(a_county, a), (b_county, b)| b.cmp(a).then_with(|| a_county.cmp(b_county)));
+        let values = Values {
+            eq,
+            hashtags,
+            intensities,
+        };
+
+        let mut text = String::new();
+        render_parts(&self.parts, &values, &mut text);
+        cut_to_length(text)
+    }
+}
+
+/// how long Twitter counts `text` as: 1 for each Latin (and Greek, Cyrillic, etc)
+/// character and most punctuation, 2 for the rest (like Chinese), and `LINK_LENGTH`
+/// for each link
+pub fn post_length(text: &str) -> usize {
+    length_pieces(text).iter().map(|(_, length)| length).sum()
+}
+
+/// cut the post to `MAX_POST_LENGTH` (the end an ellipsis) if it's longer. it's only
+/// cut between characters, and links are left out instead of being cut
+pub fn cut_to_length(text: String) -> String {
+    if post_length(&text) <= MAX_POST_LENGTH {
+        return text;
+    }
+
+    let room = MAX_POST_LENGTH - char_length(ELLIPSIS);
+    let mut cut = String::new();
+    let mut length = 0;
+    for (piece, piece_length) in length_pieces(&text) {
+        if length + piece_length > room {
+            break;
+        }
+        cut.push_str(piece);
+        length += piece_length;
+    }
+    cut.push(ELLIPSIS);
+    cut
+}
+
+/// whether `text` was cut short (with … at the end) to fit in a post
+#[cfg(test)]
+pub(crate) fn is_cut_to_length(text: &str) -> bool {
+    text.ends_with(ELLIPSIS) && post_length(text) <= MAX_POST_LENGTH
+}
+
+/// the text split into links (anything from http:// or https:// to the next space)
+/// and characters, with how long Twitter counts each one as
+fn length_pieces(text: &str) -> Vec<(&str, usize)> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let piece = if rest.starts_with("http://") || rest.starts_with("https://") {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (&rest[..end], LINK_LENGTH)
+        } else {
+            (&rest[..c.len_utf8()], char_length(c))
+        };
+        rest = &rest[piece.0.len()..];
+        pieces.push(piece);
+    }
+
+    pieces
+}
+
+/// how long Twitter counts a character as (its ranges that count as 1, see twitter-text)
+fn char_length(c: char) -> usize {
+    match c as u32 {
+        0..=0x10FF | 0x2000..=0x200D | 0x2010..=0x201F | 0x2032..=0x2037 => 1,
+        _ => 2,
+    }
+}
+
+/// split the template into text and tags, reading {{, }} and \n
+fn tokenize(template: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '\\' if chars.peek() == Some(&'n') => {
+                chars.next();
+                text.push('\n');
+            }
+            '{' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => tag.push(c),
+                        None => return Err(format!("{{{} doesn't have a }} after it", tag)),
+                    }
+                }
+                if !text.is_empty() {
+                    tokens.push(Token::Text(std::mem::take(&mut text)));
+                }
+                tokens.push(Token::Tag(tag.trim().to_string()));
+            }
+            '}' => return Err("there's a } without a { (use }} for a brace)".to_string()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+
+    Ok(tokens)
+}
+
+/// read parts until an {else}, an {end} or the end of the template, and say which it was
+fn parse_parts(tokens: &mut impl Iterator<Item = Token>) -> Result<(Vec<Part>, BlockEnd), String> {
+    let mut parts = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let tag = match token {
+            Token::Text(text) => {
+                parts.push(Part::Text(text));
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+
+        match tag.as_str() {
+            "end" => return Ok((parts, BlockEnd::End)),
+            "else" => return Ok((parts, BlockEnd::Else)),
+            _ => {}
+        }
+
+        if let Some(condition_text) = tag.strip_prefix("if ") {
+            let condition_text = condition_text.trim();
+            let condition = Condition::parse(condition_text)?;
+            let no_end = || format!("{{if {}}} doesn't have an {{end}}", condition_text);
+            let (then, otherwise) = match parse_parts(tokens)? {
+                (then, BlockEnd::End) => (then, Vec::new()),
+                (then, BlockEnd::Else) => match parse_parts(tokens)? {
+                    (otherwise, BlockEnd::End) => (then, otherwise),
+                    (_, BlockEnd::Else) => return Err(format!("{{if {}}} has more than one {{else}}", condition_text)),
+                    (_, BlockEnd::Finished) => return Err(no_end()),
+                },
+                (_, BlockEnd::Finished) => return Err(no_end()),
+            };
+            parts.push(Part::If {
+                condition,
+                then,
+                otherwise,
+            });
+        } else {
+            parts.push(Part::Field(Field::parse(&tag)?));
+        }
+    }
+
+    Ok((parts, BlockEnd::Finished))
+}
+
+/// add the text of `parts` to `text`
+fn render_parts(parts: &[Part], values: &Values, text: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(part) => text.push_str(part),
+            Part::Field(field) => text.push_str(&field.value(values)),
+            Part::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                if condition.is_true(values) {
+                    render_parts(then, values, text);
+                } else {
+                    render_parts(otherwise, values, text);
+                }
+            }
+        }
+    }
+}
+
+impl Field {
+    /// read a placeholder's name (and the time's format)
+    fn parse(tag: &str) -> Result<Self, String> {
+        let (name, format) = match tag.split_once(':') {
+            Some((name, format)) => (name.trim(), Some(format)),
+            None => (tag, None),
+        };
+        if format.is_some() && name != "time" {
+            return Err(format!("only {{time}} can have a format, not {{{}}}", tag));
+        }
+
+        let field = match name {
+            "time" => {
+                let format = format.unwrap_or(DEFAULT_TIME_FORMAT);
+                // CWB's times don't have an offset, so things like %z can't be written
+                // either (chrono panics if it finds that out while posting)
+                let time = NaiveDate::from_ymd_opt(2024, 4, 3).unwrap().and_hms_opt(7, 58, 9).unwrap();
+                if write!(String::new(), "{}", time.format(format)).is_err() {
+                    return Err(format!("{} isn't a time format chrono can use", format));
+                }
+                Field::Time(format.to_string())
+            }
+            "magnitude" => Field::Magnitude,
+            "depth" => Field::Depth,
+            "lat" => Field::Lat,
+            "lon" => Field::Lon,
+            "location" => Field::Location,
+            "report" => Field::Report,
+            "max_intensity" => Field::MaxIntensity,
+            "intensities" => Field::Intensities,
+            "link" => Field::Link,
+            "hashtags" => Field::Hashtags,
+            _ => return Err(format!("there's no {{{}}} (it can be one of: {})", name, FIELD_NAMES)),
+        };
+
+        Ok(field)
+    }
+
+    /// the text that goes where the placeholder is
+    fn value(&self, values: &Values) -> String {
+        let info = &values.eq.earthquake_info;
+
+        match self {
+            Field::Time(format) => CwbTime::new_time_string_without_t(&info.origin_time)
+                .get_date_time()
+                .format(format)
+                .to_string(),
+            Field::Magnitude => format!("{:.1}", info.earthquake_magnitude.magnitude_value),
+            Field::Depth => info.focal_depth.to_string(),
+            Field::Lat => format!("{:.2}", info.epicenter.lat),
+            Field::Lon => format!("{:.2}", info.epicenter.lon),
+            Field::Location => info.epicenter.location.clone(),
+            Field::Report => values.eq.report_content.clone(),
+            Field::MaxIntensity => match values.intensities.first() {
+                Some((_, intensity)) => intensity.short_label().to_string(),
+                None => String::new(),
+            },
+            Field::Intensities => values
+                .intensities
+                .iter()
+                .filter(|(_, intensity)| *intensity != SeismicIntensity::Zero)
+                .map(|(county, intensity)| format!("{} {}", county, intensity.short_label()))
+                .collect::<Vec<String>>()
+                .join(", "),
+            Field::Link => values.eq.web.clone(),
+            Field::Hashtags => values.hashtags.to_string(),
+        }
+    }
+}
+
+impl Condition {
+    /// read what's after "if" (ex: magnitude >= 6)
+    fn parse(condition: &str) -> Result<Self, String> {
+        let comparison = COMPARISONS
+            .iter()
+            .find_map(|(symbol, comparison)| condition.find(symbol).map(|at| (at, *symbol, *comparison)));
+        let (at, symbol, comparison) = match comparison {
+            Some(comparison) => comparison,
+            None => return Ok(Condition::NotEmpty(Field::parse(condition)?)),
+        };
+
+        let name = condition[..at].trim();
+        let value = condition[at + symbol.len()..].trim();
+        let number = match name {
+            "magnitude" => Number::Magnitude,
+            "depth" => Number::Depth,
+            "max_intensity" => Number::MaxIntensity,
+            _ => {
+                return Err(format!(
+                    "{{if {}}} can't compare {} (only magnitude, depth and max_intensity)",
+                    condition, name
+                ))
+            }
+        };
+        let value = match number {
+            Number::MaxIntensity => SeismicIntensity::ALL
+                .iter()
+                .find(|intensity| intensity.short_label() == value || intensity.to_string() == value)
+                .map(|intensity| intensity_level(*intensity)),
+            _ => value.parse::<f64>().ok(),
+        };
+
+        match value {
+            Some(value) => Ok(Condition::Compare(number, comparison, value)),
+            None => Err(format!(
+                "{{if {}}} should compare to a number (or an intensity like 5- for max_intensity)",
+                condition
+            )),
+        }
+    }
+
+    /// whether the part of the {if} before {else} is used
+    fn is_true(&self, values: &Values) -> bool {
+        match self {
+            Condition::NotEmpty(field) => !field.value(values).is_empty(),
+            Condition::Compare(number, comparison, value) => {
+                let info = &values.eq.earthquake_info;
+                let number = match number {
+                    Number::Magnitude => info.earthquake_magnitude.magnitude_value,
+                    Number::Depth => info.focal_depth,
+                    Number::MaxIntensity => match values.intensities.first() {
+                        Some((_, intensity)) => intensity_level(*intensity),
+                        None => return false,
+                    },
+                };
+
+                match comparison {
+                    Comparison::AtLeast => number >= *value,
+                    Comparison::AtMost => number <= *value,
+                    Comparison::Equal => number == *value,
+                    Comparison::NotEqual => number != *value,
+                    Comparison::Greater => number > *value,
+                    Comparison::Less => number < *value,
+                }
+            }
+        }
+    }
+}
+
+/// where the intensity is on the scale (0 for Zero up to 9 for Seven)
+fn intensity_level(intensity: SeismicIntensity) -> f64 {
+    SeismicIntensity::ALL
+        .iter()
+        .position(|level| *level == intensity)
+        .unwrap_or(0) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// M7.2, 10 km deep at 23.99°N 121.81°E, with no shaking
+    fn render(template: &str) -> String {
+        let mut eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2);
+        eq.web = "https://scweb.cwa.gov.tw/".to_string();
+        Template::parse(template).unwrap().render(&eq, "#Taiwan")
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        assert_eq!(
+            render("M{magnitude} at {time:%H:%M}, {depth} km deep at {lat}°N {lon}°E {link} {hashtags}"),
+            "M7.2 at 07:58, 10 km deep at 23.99°N 121.81°E https://scweb.cwa.gov.tw/ #Taiwan"
+        );
+        assert_eq!(render("{time}"), "2024-04-03 07:58:09");
+        assert_eq!(render("{{magnitude}}\\n{ magnitude }"), "{magnitude}\n7.2");
+        assert_eq!(render("[{max_intensity}][{intensities}]"), "[][]");
+    }
+
+    #[test]
+    fn if_else_and_end_can_be_nested() {
+        let template = "{if magnitude >= 7}big{if depth < 30} and shallow{else} and deep{end}{else}small{end}!";
+        assert_eq!(render(template), "big and shallow!");
+
+        assert_eq!(render("{if magnitude < 7}small{else}{if depth > 30}deep{else}shallow{end}{end}"), "shallow");
+        assert_eq!(render("{if max_intensity}felt{else}not felt{end}"), "not felt");
+        assert_eq!(render("{if link}{link}{end}"), "https://scweb.cwa.gov.tw/");
+        assert_eq!(render("{if max_intensity >= 5-}strong{end}"), "");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_errors() {
+        assert!(Template::parse("{magnitud}").unwrap_err().contains("there's no {magnitud}"));
+        assert!(Template::parse("{if size >= 6}big{end}").is_err());
+        assert!(Template::parse("{depth:%H}").is_err());
+        assert!(Template::parse("{if magnitude >= big}big{end}").is_err());
+    }
+
+    #[test]
+    fn time_formats_that_cant_be_written_are_errors() {
+        assert!(Template::parse("{time:%H:%M %z}").unwrap_err().contains("isn't a time format"));
+        assert!(Template::parse("{time:%:z}").is_err());
+        assert!(Template::parse("{time:%Z}").is_err());
+        assert!(Template::parse("{time:%Q}").is_err());
+        assert_eq!(render("{time:%Y/%m/%d %a}"), "2024/04/03 Wed");
+    }
+
+    #[test]
+    fn unterminated_blocks_are_errors() {
+        assert!(Template::parse("{if magnitude >= 6}big").unwrap_err().contains("doesn't have an {end}"));
+        assert!(Template::parse("{if magnitude >= 6}big{else}small").is_err());
+        assert!(Template::parse("{if magnitude >= 6}a{else}b{else}c{end}").is_err());
+        assert!(Template::parse("big{end}").is_err());
+        assert!(Template::parse("big{else}").is_err());
+        assert!(Template::parse("M{magnitude").unwrap_err().contains("doesn't have a }"));
+        assert!(Template::parse("M}").is_err());
+    }
+
+    #[test]
+    fn long_posts_are_cut_to_the_limit_between_characters() {
+        let post = render(&"地震".repeat(200));
+        assert_eq!(post_length(&post), MAX_POST_LENGTH);
+        assert_eq!(post.chars().count(), MAX_POST_LENGTH / 2);
+        assert!(post.ends_with("地…"));
+
+        let post = render(&format!("{}{{magnitude}}", "a".repeat(MAX_POST_LENGTH - 3)));
+        assert_eq!(post_length(&post), MAX_POST_LENGTH);
+        assert!(post.ends_with("7.2"));
+    }
+
+    #[test]
+    fn long_reports_are_only_cut_with_the_post() {
+        let mut eq = Earthquake::for_test("2024-04-03 07:58:09", 7.2);
+        eq.report_content = "花蓮縣政府南南東方25.0公里".repeat(20);
+        let post = Template::parse("{report} {hashtags}").unwrap().render(&eq, "#Taiwan");
+        assert!(is_cut_to_length(&post));
+        assert!(post.ends_with('…'));
+
+        eq.report_content = "花蓮縣政府南南東方25.0公里".repeat(6);
+        let post = Template::parse("{report} {hashtags}").unwrap().render(&eq, "#Taiwan");
+        assert_eq!(post, format!("{} #Taiwan", eq.report_content));
+    }
+
+    #[test]
+    fn chinese_characters_count_twice_and_links_count_as_23() {
+        assert_eq!(post_length("M7.2 地震 – “Hualien”"), 5 + 4 + 12);
+        assert_eq!(post_length("花蓮 https://scweb.cwa.gov.tw/zh-tw/earthquake/details/2024040307580972019"), 5 + 23);
+
+        // a link fits however long it is, but isn't cut if it doesn't
+        let post = render(&format!("{}{{link}}{}", "地".repeat(120), "/a-very-long-page-name".repeat(5)));
+        assert_eq!(post, format!("{}https://scweb.cwa.gov.tw/{}", "地".repeat(120), "/a-very-long-page-name".repeat(5)));
+        let post = render(&format!("{} {{link}}", "地".repeat(130)));
+        assert_eq!(post, format!("{} …", "地".repeat(130)));
+        assert_eq!(post_length(&post), 263);
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::{Duration, NaiveDateTime, Utc};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
 use std::ops::Add;
 
 /// Format string for date with a T (like rfc-3339 without the timezone info
@@ -14,10 +14,27 @@ const FORMAT_WITHOUT_T: &str = "%Y-%m-%d %H:%M:%S";
 /// always use Taiwan local time, so add 8 hours to UTC
 /// couldn't figure out how to change the timezone the smart way :(
 pub fn get_local_date_time_with_t() -> String {
-    Utc::now()
-        .add(Duration::hours(8))
-        .format(FORMAT_WITH_T)
-        .to_string()
+    get_local_date_time().format(FORMAT_WITH_T).to_string()
+}
+
+/// the time right now in Taiwan
+pub fn get_local_date_time() -> NaiveDateTime {
+    Utc::now().naive_utc().add(Duration::hours(8))
+}
+
+/// read a date (2024-04-01, the start of that day) or a time with a T (2024-04-01T12:00:00)
+pub fn parse_date_or_time(date_time_string: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(date_time_string, FORMAT_WITH_T)
+        .or_else(|_| {
+            NaiveDate::parse_from_str(date_time_string, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        })
+        .map_err(|_| {
+            format!(
+                "{} should be a date (2024-04-01) or a time (2024-04-01T12:00:00)",
+                date_time_string
+            )
+        })
 }
 
 /// a struct to make doing time things easier
@@ -50,3 +67,9 @@ impl CwbTime {
         self.0
     }
 }
+
+impl From<NaiveDateTime> for CwbTime {
+    fn from(date_time: NaiveDateTime) -> Self {
+        Self(date_time)
+    }
+}
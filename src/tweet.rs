@@ -1,45 +1,193 @@
 use crate::cwb_api::structs::Earthquake;
+use crate::db::EqDb;
+use crate::digest::{post_digest, DigestPeriod};
+use crate::logging::quake_span;
+use crate::metrics::TWITTER_PUBLISHER;
+use crate::pipeline::{PostedThread, Publisher};
+use crate::profile::{Language, Profile};
+use crate::taiwan_map::MapRenderer;
+use crate::template::cut_to_length;
 
-use dotenv_codegen::dotenv;
 use egg_mode::media::{media_types, upload_media};
 use egg_mode::tweet::DraftTweet;
-use egg_mode::KeyPair;
-use egg_mode::Token::Access;
-use log::info;
+use chrono::NaiveDateTime;
+use egg_mode::Token;
+use log::{error, info};
+use std::sync::Arc;
+use tracing::{info_span, Instrument};
+
+/// posts to each profile's Twitter account, with the maps drawn by the shared renderer
+pub struct TwitterPublisher {
+    renderer: Arc<MapRenderer>,
+}
+
+impl TwitterPublisher {
+    pub fn new(renderer: Arc<MapRenderer>) -> Self {
+        Self { renderer }
+    }
+}
+
+impl Publisher for TwitterPublisher {
+    const NAME: &'static str = TWITTER_PUBLISHER;
+
+    async fn post(&self, profile: &Profile, eq: &Earthquake) -> Result<(), Box<dyn std::error::Error>> {
+        eq.tweet(profile, &self.renderer).await
+    }
+
+    async fn post_summary(
+        &self,
+        profile: &Profile,
+        swarm: &[Earthquake],
+        replies: &[Earthquake],
+    ) -> Result<PostedThread, Box<dyn std::error::Error>> {
+        tweet_summary(profile, swarm, replies, &self.renderer).await
+    }
+
+    async fn post_reply(&self, profile: &Profile, eq: &Earthquake, reply_to: u64) -> Result<u64, Box<dyn std::error::Error>> {
+        post_tweet(&profile.token, profile.quake_text(eq), None, Some(reply_to)).await
+    }
+
+    async fn post_digest(
+        &self,
+        profile: &Profile,
+        db: &EqDb,
+        period: DigestPeriod,
+        now: NaiveDateTime,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        post_digest(profile, db, &self.renderer, period, now).await
+    }
+}
 
 impl Earthquake {
-    /// this implementation of `Earthquake` uses the info in the struct, gathers the CWB
-    /// report content string for the tweet, then uses both epicenter and station intensity
-    /// info to make taiwan map
-    pub async fn tweet(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// this implementation of `Earthquake` uses the info in the struct, gets the
+    /// profile's text for the tweet, then uses both epicenter and station intensity
+    /// info to make taiwan map (drawn with the shared `MapRenderer`), and tweets it
+    /// to the profile's account
+    pub async fn tweet(&self, profile: &Profile, renderer: &MapRenderer) -> Result<(), Box<dyn std::error::Error>> {
         // get tweet text
-        let mut text = format!("{} #台灣 #地震 #Taiwan #earthquake", self.report_content);
-
-        // if there's a massive earthquake, need to shorten the text, otherwise egg-mode panics :(
-        if self.report_content.len() > 200 {
-            text = format!(
-                "{} #台灣 #地震 #Taiwan #earthquake",
-                &self.report_content[..200]
-            );
-        }
+        let text = profile.quake_text(self);
 
         // prepare the image for upload
-        self.mark_image_with_eq_data();
-        let img_64 = std::fs::read("temp.png")?;
+        let img_64 = renderer.render_png(self)?;
+
+        post_tweet(&profile.token, text, Some(&img_64), None).await?;
 
-        // create a token for twitter access
-        let consumer = KeyPair::new(dotenv!("API_KEY"), dotenv!("API_SECRET_KEY"));
-        let access = KeyPair::new(dotenv!("ACCESS_TOKEN"), dotenv!("ACCESS_TOKEN_SECRET"));
-        let token = Access { consumer, access };
+        Ok(())
+    }
+}
 
-        // draft the tweet
-        let mut tweet = DraftTweet::new(text);
+/// the first tweet of a swarm summary thread, in `language`: how many earthquakes there
+/// were, when, and the biggest one (`earthquakes` oldest first), then the hashtags (cut
+/// short like a post if it's too long)
+pub fn summary_text(earthquakes: &[Earthquake], language: Language) -> String {
+    let (first, last) = match (earthquakes.first(), earthquakes.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return language.hashtags().to_string(),
+    };
+    let biggest = Earthquake::biggest(earthquakes).unwrap_or(first);
 
-        info!("drafted the tweet");
+    let text = match language {
+        Language::Chinese => format!(
+            "{}～{} 共{}次地震，最大規模{:.1}，位於{}。地震報告見下 {}",
+            first.earthquake_info.origin_time,
+            last.earthquake_info.origin_time,
+            earthquakes.len(),
+            biggest.earthquake_info.earthquake_magnitude.magnitude_value,
+            biggest.earthquake_info.epicenter.location,
+            language.hashtags()
+        ),
+        Language::English => format!(
+            "{} earthquakes in Taiwan from {} to {} Taiwan time. The biggest was M{:.1} at {:.2}°N {:.2}°E. \
+             The reports are in the replies. {}",
+            earthquakes.len(),
+            first.earthquake_info.origin_time,
+            last.earthquake_info.origin_time,
+            biggest.earthquake_info.earthquake_magnitude.magnitude_value,
+            biggest.earthquake_info.epicenter.lat,
+            biggest.earthquake_info.epicenter.lon,
+            language.hashtags()
+        ),
+    };
 
+    cut_to_length(text)
+}
+
+/// post one thread for a swarm of earthquakes instead of a tweet for each: the summary
+/// text and map of `swarm` first, then the report of each of `replies` (the ones that
+/// weren't posted on their own) as a reply, in order. returns the id of the last tweet
+/// and how many of the replies went up
+///
+/// once the first tweet is up the swarm counts as tweeted, so a reply that fails is
+/// logged and ends the thread instead of returning an error (that would post the whole
+/// thread again next time). the replies after it are posted under the thread later
+pub async fn tweet_summary(
+    profile: &Profile,
+    swarm: &[Earthquake],
+    replies: &[Earthquake],
+    renderer: &MapRenderer,
+) -> Result<PostedThread, Box<dyn std::error::Error>> {
+    let img_64 = renderer.render_summary_png(swarm)?;
+    let text = summary_text(swarm, profile.language);
+    let mut thread = PostedThread {
+        last_post: post_tweet(&profile.token, text, Some(&img_64), None).await?,
+        replies: 0,
+    };
+
+    // each reply goes under the last one so the thread stays in order
+    for eq in replies {
+        match post_tweet(&profile.token, profile.quake_text(eq), None, Some(thread.last_post))
+            .instrument(quake_span(eq))
+            .await
+        {
+            Ok(id) => {
+                thread.last_post = id;
+                thread.replies += 1;
+            }
+            Err(e) => {
+                error!("couldn't finish the summary thread: {}", e);
+                break;
+            }
+        }
+    }
+
+    Ok(thread)
+}
+
+/// post a tweet with `text` and optionally a png and the tweet it replies to, to the
+/// account `token` is for. every post (earthquakes, summaries and digests) goes
+/// through here
+///
+/// returns the new tweet's id
+pub async fn post_tweet(
+    token: &Token,
+    text: String,
+    png: Option<&[u8]>,
+    reply_to: Option<u64>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    // each attempt gets its own span (inside the quake's, swarm's or digest's)
+    let span = info_span!("publish", publisher = TWITTER_PUBLISHER, reply_to = ?reply_to);
+
+    send_tweet(token, text, png, reply_to).instrument(span).await
+}
+
+/// the posting part of `post_tweet`
+async fn send_tweet(
+    token: &Token,
+    text: String,
+    png: Option<&[u8]>,
+    reply_to: Option<u64>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    // draft the tweet
+    let mut tweet = DraftTweet::new(text);
+    if let Some(reply_to) = reply_to {
+        tweet = tweet.in_reply_to(reply_to);
+    }
+
+    info!("drafted the tweet");
+
+    if let Some(png) = png {
         // upload the file
-        // let pic_handle = upload_media(img_64, &media_types::image_png(), &token).await?;
-        let pic_handle = upload_media(&img_64, &media_types::image_png(), &token).await?;
+        let pic_handle = upload_media(png, &media_types::image_png(), token).await?;
 
         info!("uploaded media: {:?}", pic_handle.id.clone());
 
@@ -49,7 +197,7 @@ impl Earthquake {
         // // this isn't working. When uploaded, the images appear to already be in the progress: None
         // // state, so maybe it's unnecessary. (this code was included in the docs)
         // for ct in 0..=60_u32 {
-        //     match get_status(pic_handle.id.clone(), &token).await?.progress {
+        //     match get_status(pic_handle.id.clone(), token).await?.progress {
         //         None | Some(ProgressInfo::Success) => {
         //             println!("\nMedia successfully processed");
         //             break;
@@ -64,13 +212,28 @@ impl Earthquake {
         //         Err("Error: timeout")?
         //     }
         // }
+    }
 
-        let sent = tweet.send(&token).await?;
-        info!("tweet id: {}\n{}", sent.response.id, sent.response.text);
+    let sent = tweet.send(token).await?;
+    info!("tweet id: {}\n{}", sent.response.id, sent.response.text);
 
-        // tweeted, so remove the temporary file
-        let _ = std::fs::remove_file("temp.png");
+    Ok(sent.response.id)
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::is_cut_to_length;
+
+    #[test]
+    fn summary_text_is_cut_to_a_posts_length() {
+        let swarm = [
+            Earthquake::for_test("2024-04-03 07:58:09", 5.0),
+            Earthquake::with_long_location("2024-04-03 08:11:00", 6.1),
+        ];
+
+        let text = summary_text(&swarm, Language::Chinese);
+        assert!(text.starts_with("2024-04-03 07:58:09～2024-04-03 08:11:00 共2次地震，最大規模6.1"));
+        assert!(is_cut_to_length(&text));
     }
 }
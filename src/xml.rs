@@ -0,0 +1,20 @@
+/// make text safe to put in xml (QuakeML, the Atom feed, or an svg map)
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markup_is_escaped_once() {
+        assert_eq!(
+            escape_xml(r#"花蓮縣 <東方> & "近海" &amp;"#),
+            "花蓮縣 &lt;東方&gt; &amp; &quot;近海&quot; &amp;amp;"
+        );
+    }
+}